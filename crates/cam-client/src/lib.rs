@@ -202,6 +202,7 @@ impl Middleware for StatusCheckMiddleware {
             );
             let error_text = resp.text().await?;
 
+            #[allow(clippy::collapsible_if)]
             if let Ok(e) = serde_json::from_str::<V3Error>(&error_text) {
                 if e.code == "tick-not-in-redis" {
                     let re = Regex::new(r"(\S+)\s").unwrap();
@@ -0,0 +1,345 @@
+//! Minimal Solidity ABI encoding helpers
+//!
+//! Only the handful of static types needed by the readers in this crate are supported.
+
+use std::{fmt, str::FromStr};
+
+use anyhow::{Result, anyhow};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Size of a single ABI word in bytes
+pub const WORD: usize = 32;
+
+/// Largest scale `rust_decimal` can represent
+const MAX_SCALE: u32 = 28;
+
+/// Largest mantissa `rust_decimal` can represent (96 bits)
+const MAX_MANTISSA: u128 = (1 << 96) - 1;
+
+/// Decimals of 18-decimal fixed point ("wad") values
+pub const WAD_DECIMALS: u32 = 18;
+
+/// `balanceOf(address)`
+pub const BALANCE_OF: [u8; 4] = [0x70, 0xa0, 0x82, 0x31];
+/// `decimals()`
+pub const DECIMALS: [u8; 4] = [0x31, 0x3c, 0xe5, 0x67];
+/// `totalSupply()`
+pub const TOTAL_SUPPLY: [u8; 4] = [0x18, 0x16, 0x0d, 0xdd];
+
+/// 20-byte account or contract address
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Address(pub [u8; 20]);
+
+impl Address {
+    pub const ZERO: Address = Address([0u8; 20]);
+
+    pub fn as_bytes(&self) -> &[u8; 20] {
+        &self.0
+    }
+}
+
+impl FromStr for Address {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let hex_str = s.strip_prefix("0x").unwrap_or(s);
+        let bytes = hex::decode(hex_str).map_err(|e| anyhow!("Invalid address {s}: {e}"))?;
+        let bytes: [u8; 20] = bytes
+            .try_into()
+            .map_err(|_| anyhow!("Invalid address {s}: expected 20 bytes"))?;
+        Ok(Address(bytes))
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{}", hex::encode(self.0))
+    }
+}
+
+impl fmt::Debug for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl Serialize for Address {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Address {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Builds calldata from a function selector and already-encoded static arguments
+pub fn encode_call(selector: [u8; 4], args: &[[u8; WORD]]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(4 + args.len() * WORD);
+    data.extend_from_slice(&selector);
+    for arg in args {
+        data.extend_from_slice(arg);
+    }
+    data
+}
+
+/// Encodes an address as a left-padded word
+pub fn encode_address(address: &Address) -> [u8; WORD] {
+    let mut word = [0u8; WORD];
+    word[12..].copy_from_slice(&address.0);
+    word
+}
+
+/// Encodes an unsigned integer as a big-endian word
+pub fn encode_uint(value: u128) -> [u8; WORD] {
+    let mut word = [0u8; WORD];
+    word[16..].copy_from_slice(&value.to_be_bytes());
+    word
+}
+
+/// Encodes a boolean as a word
+pub fn encode_bool(value: bool) -> [u8; WORD] {
+    encode_uint(value as u128)
+}
+
+/// Rounds a byte length up to the next multiple of the word size
+pub fn padded_len(len: usize) -> usize {
+    len.div_ceil(WORD) * WORD
+}
+
+/// Returns the word at the given index of ABI-encoded data
+pub fn decode_word(data: &[u8], index: usize) -> Result<&[u8]> {
+    let start = index * WORD;
+    data.get(start..start + WORD)
+        .ok_or_else(|| anyhow!("ABI data too short: no word at index {index}"))
+}
+
+/// Decodes an unsigned integer word, failing if it does not fit in 128 bits
+pub fn decode_uint(data: &[u8], index: usize) -> Result<u128> {
+    let word = decode_word(data, index)?;
+    if word[..16].iter().any(|b| *b != 0) {
+        return Err(anyhow!("uint256 at word {index} overflows u128"));
+    }
+    Ok(u128::from_be_bytes(word[16..].try_into().unwrap()))
+}
+
+/// Decodes a signed integer word, failing if it does not fit in 128 bits
+pub fn decode_int(data: &[u8], index: usize) -> Result<i128> {
+    let word = decode_word(data, index)?;
+    let sign = if word[0] & 0x80 != 0 { 0xff } else { 0x00 };
+    if word[..16].iter().any(|b| *b != sign) {
+        return Err(anyhow!("int256 at word {index} overflows i128"));
+    }
+    Ok(i128::from_be_bytes(word[16..].try_into().unwrap()))
+}
+
+/// Decodes a boolean word
+pub fn decode_bool(data: &[u8], index: usize) -> Result<bool> {
+    Ok(decode_uint(data, index)? != 0)
+}
+
+/// Decodes an address word
+pub fn decode_address(data: &[u8], index: usize) -> Result<Address> {
+    let word = decode_word(data, index)?;
+    Ok(Address(word[12..].try_into().unwrap()))
+}
+
+/// Decodes an offset or length word, failing if it does not fit in `usize`
+pub fn decode_usize(data: &[u8], index: usize) -> Result<usize> {
+    let value = decode_uint(data, index)?;
+    usize::try_from(value).map_err(|_| anyhow!("ABI offset {value} at word {index} out of range"))
+}
+
+/// Adds an offset taken from ABI data to a position, failing on overflow
+pub fn offset_from(base: usize, offset: usize) -> Result<usize> {
+    base.checked_add(offset)
+        .ok_or_else(|| anyhow!("ABI offset {offset} from {base} out of range"))
+}
+
+/// Decodes a dynamic `bytes` value whose head is at the given word index
+///
+/// `base` is the byte offset that the head's relative offset is measured from.
+pub fn decode_bytes(data: &[u8], base: usize, index: usize) -> Result<Vec<u8>> {
+    let offset = offset_from(
+        base,
+        decode_usize(data.get(base..).unwrap_or_default(), index)?,
+    )?;
+    let len = decode_usize(data.get(offset..).unwrap_or_default(), 0)?;
+    let start = offset_from(offset, WORD)?;
+    data.get(start..offset_from(start, len)?)
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| anyhow!("ABI bytes at offset {offset} out of bounds"))
+}
+
+/// Converts a raw integer token amount into a decimal with the given number of decimals
+///
+/// Precision beyond what `Decimal` can hold is truncated from the least significant digits.
+pub fn to_decimal(raw: u128, decimals: u32) -> Result<Decimal> {
+    let mut mantissa = raw;
+    let mut scale = decimals;
+    while mantissa > MAX_MANTISSA || scale > MAX_SCALE {
+        if scale == 0 {
+            return Err(anyhow!("Amount {raw} does not fit in a decimal"));
+        }
+        mantissa /= 10;
+        scale -= 1;
+    }
+    Ok(Decimal::from_i128_with_scale(mantissa as i128, scale))
+}
+
+/// Converts a signed raw integer amount into a decimal with the given number of decimals
+pub fn to_signed_decimal(raw: i128, decimals: u32) -> Result<Decimal> {
+    let value = to_decimal(raw.unsigned_abs(), decimals)?;
+    Ok(if raw < 0 { -value } else { value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn address_parses_with_and_without_prefix() {
+        let address: Address = "0x00000000000000000000000000000000000000aB"
+            .parse()
+            .unwrap();
+        assert_eq!(address.0[19], 0xab);
+        assert_eq!(
+            "00000000000000000000000000000000000000ab"
+                .parse::<Address>()
+                .unwrap(),
+            address
+        );
+        assert_eq!(
+            address.to_string(),
+            "0x00000000000000000000000000000000000000ab"
+        );
+        assert!("0x1234".parse::<Address>().is_err());
+        assert!(
+            "0xzz00000000000000000000000000000000000000"
+                .parse::<Address>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn encode_call_appends_words_to_selector() {
+        let owner = Address([0x11; 20]);
+        let data = encode_call(BALANCE_OF, &[encode_address(&owner)]);
+        assert_eq!(data.len(), 4 + WORD);
+        assert_eq!(data[..4], BALANCE_OF);
+        assert!(data[4..16].iter().all(|b| *b == 0));
+        assert_eq!(data[16..], owner.0);
+    }
+
+    #[test]
+    fn words_round_trip() {
+        let owner = Address([0x22; 20]);
+        let mut data = Vec::new();
+        data.extend_from_slice(&encode_uint(u128::MAX));
+        data.extend_from_slice(&encode_address(&owner));
+        data.extend_from_slice(&encode_bool(true));
+        data.extend_from_slice(&encode_bool(false));
+
+        assert_eq!(decode_uint(&data, 0).unwrap(), u128::MAX);
+        assert_eq!(decode_address(&data, 1).unwrap(), owner);
+        assert!(decode_bool(&data, 2).unwrap());
+        assert!(!decode_bool(&data, 3).unwrap());
+        assert!(decode_word(&data, 4).is_err());
+    }
+
+    #[test]
+    fn decode_uint_rejects_values_above_u128() {
+        let mut word = [0u8; WORD];
+        word[15] = 1;
+        assert!(decode_uint(&word, 0).is_err());
+    }
+
+    #[test]
+    fn decode_int_sign_extends() {
+        assert_eq!(decode_int(&[0xff; WORD], 0).unwrap(), -1);
+        assert_eq!(decode_int(&encode_uint(5), 0).unwrap(), 5);
+
+        let mut word = [0xff; WORD];
+        word[0] = 0x7f;
+        assert!(decode_int(&word, 0).is_err());
+    }
+
+    #[test]
+    fn decode_bytes_follows_offset() {
+        let mut data = Vec::new();
+        data.extend_from_slice(&encode_uint(WORD as u128));
+        data.extend_from_slice(&encode_uint(3));
+        data.extend_from_slice(&[0xaa, 0xbb, 0xcc]);
+        data.resize(3 * WORD, 0);
+        assert_eq!(decode_bytes(&data, 0, 0).unwrap(), vec![0xaa, 0xbb, 0xcc]);
+
+        data.truncate(2 * WORD);
+        assert!(decode_bytes(&data, 0, 0).is_err());
+    }
+
+    #[test]
+    fn decode_bytes_rejects_out_of_range_offsets() {
+        // Offset too large for usize
+        let data = encode_uint(u128::MAX);
+        assert!(decode_bytes(&data, 0, 0).is_err());
+
+        // Offset that overflows when added to the base
+        let data = encode_uint(usize::MAX as u128);
+        assert!(decode_bytes(&data, 1, 0).is_err());
+
+        // Length that overflows past the offset
+        let mut data = Vec::new();
+        data.extend_from_slice(&encode_uint(WORD as u128));
+        data.extend_from_slice(&encode_uint(usize::MAX as u128));
+        assert!(decode_bytes(&data, 0, 0).is_err());
+    }
+
+    #[test]
+    fn padded_len_rounds_up_to_words() {
+        assert_eq!(padded_len(0), 0);
+        assert_eq!(padded_len(1), WORD);
+        assert_eq!(padded_len(WORD), WORD);
+        assert_eq!(padded_len(WORD + 1), 2 * WORD);
+    }
+
+    #[test]
+    fn to_decimal_applies_decimals() {
+        assert_eq!(
+            to_decimal(1_500_000_000_000_000_000, WAD_DECIMALS).unwrap(),
+            Decimal::new(15, 1)
+        );
+        assert_eq!(
+            to_decimal(1_234_567, 6).unwrap(),
+            Decimal::new(1_234_567, 6)
+        );
+        assert_eq!(to_decimal(42, 0).unwrap(), Decimal::from(42));
+    }
+
+    #[test]
+    fn to_decimal_truncates_excess_precision() {
+        // More than 28 decimals drops the least significant digits
+        assert_eq!(to_decimal(123, 30).unwrap(), Decimal::new(1, 28));
+
+        // Mantissas above 96 bits lose their last digits rather than failing
+        let value = to_decimal(u128::MAX, WAD_DECIMALS).unwrap();
+        assert_eq!(
+            value.trunc(),
+            Decimal::from(340_282_366_920_938_463_463u128)
+        );
+    }
+
+    #[test]
+    fn to_decimal_fails_when_integer_part_overflows() {
+        assert!(to_decimal(u128::MAX, 0).is_err());
+    }
+
+    #[test]
+    fn to_signed_decimal_keeps_sign() {
+        assert_eq!(to_signed_decimal(-25, 1).unwrap(), Decimal::new(-25, 1));
+        assert_eq!(to_signed_decimal(25, 1).unwrap(), Decimal::new(25, 1));
+    }
+}
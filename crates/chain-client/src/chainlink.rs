@@ -0,0 +1,145 @@
+//! Chainlink price feed reads
+
+use anyhow::{Result, anyhow};
+use rust_decimal::Decimal;
+
+use crate::{
+    ChainClient,
+    abi::{Address, DECIMALS, decode_int, decode_uint, encode_call, to_signed_decimal},
+    types::BlockTag,
+};
+
+/// `latestRoundData()`
+const LATEST_ROUND_DATA: [u8; 4] = [0xfe, 0xaf, 0x96, 0x8c];
+
+/// Latest answer of a Chainlink price feed
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeedAnswer {
+    pub price: Decimal,
+    /// Timestamp of the round that produced the answer
+    pub updated_at: u64,
+}
+
+impl ChainClient {
+    /// Reads the number of decimals of a Chainlink price feed's answers
+    pub async fn read_feed_decimals(&self, feed: Address, block: BlockTag) -> Result<u32> {
+        let data = self
+            .eth_call(feed, &encode_call(DECIMALS, &[]), block)
+            .await?;
+        let decimals = decode_uint(&data, 0)?;
+        u32::try_from(decimals).map_err(|_| anyhow!("Invalid feed decimals {decimals}"))
+    }
+
+    /// Reads the latest answer of a Chainlink price feed at a block
+    ///
+    /// Returns `None` if the feed was not deployed yet at the block. Uses a plain `eth_call`
+    /// rather than Multicall3 so feeds can be read at blocks from before Multicall3 was
+    /// deployed.
+    pub async fn read_feed_answer(
+        &self,
+        feed: Address,
+        decimals: u32,
+        block: BlockTag,
+    ) -> Result<Option<FeedAnswer>> {
+        let data = self
+            .eth_call(feed, &encode_call(LATEST_ROUND_DATA, &[]), block)
+            .await?;
+        if data.is_empty() {
+            return Ok(None);
+        }
+        decode_round_data(&data, decimals).map(Some)
+    }
+}
+
+/// Decodes `(roundId, answer, startedAt, updatedAt, answeredInRound)`
+fn decode_round_data(data: &[u8], decimals: u32) -> Result<FeedAnswer> {
+    let answer = decode_int(data, 1)?;
+    if answer <= 0 {
+        return Err(anyhow!("Feed answered a non-positive price {answer}"));
+    }
+    let updated_at = decode_uint(data, 3)?;
+    Ok(FeedAnswer {
+        price: to_signed_decimal(answer, decimals)?,
+        updated_at: u64::try_from(updated_at)
+            .map_err(|_| anyhow!("Invalid round timestamp {updated_at}"))?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{abi::encode_uint, testing::local_node};
+
+    const FEED: Address = Address([0x60; 20]);
+    const BROKEN: Address = Address([0x61; 20]);
+    const UNDEPLOYED: Address = Address([0x62; 20]);
+
+    fn round_data(answer: u128, updated_at: u128) -> Vec<u8> {
+        [
+            encode_uint(7),
+            encode_uint(answer),
+            encode_uint(updated_at),
+            encode_uint(updated_at),
+            encode_uint(7),
+        ]
+        .concat()
+    }
+
+    /// ETH/USD feed answering 3,250.5 with 8 decimals, a feed answering zero and an address
+    /// without code
+    fn contracts(target: Address, data: &[u8]) -> Option<Vec<u8>> {
+        match (target, &data[..4]) {
+            (UNDEPLOYED, _) => Some(Vec::new()),
+            (_, s) if s == DECIMALS => Some(encode_uint(8).to_vec()),
+            (FEED, s) if s == LATEST_ROUND_DATA => Some(round_data(325_050_000_000, 1_200)),
+            (BROKEN, s) if s == LATEST_ROUND_DATA => Some(round_data(0, 1_200)),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_feed_answers() {
+        let client = local_node(Arc::new(contracts)).await;
+        let decimals = client
+            .read_feed_decimals(FEED, BlockTag::Latest)
+            .await
+            .unwrap();
+        assert_eq!(decimals, 8);
+
+        let answer = client
+            .read_feed_answer(FEED, decimals, BlockTag::Number(50))
+            .await
+            .unwrap();
+        assert_eq!(
+            answer,
+            Some(FeedAnswer {
+                price: Decimal::new(32505, 1),
+                updated_at: 1_200,
+            })
+        );
+        assert_eq!(
+            client
+                .read_feed_answer(UNDEPLOYED, decimals, BlockTag::Latest)
+                .await
+                .unwrap(),
+            None
+        );
+
+        assert!(
+            client
+                .read_feed_answer(BROKEN, decimals, BlockTag::Latest)
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn decode_round_data_rejects_negative_answers() {
+        let mut data = round_data(0, 1_200);
+        data[32..64].copy_from_slice(&[0xff; 32]);
+        assert!(decode_round_data(&data, 8).is_err());
+        assert!(decode_round_data(&data[..64], 8).is_err());
+    }
+}
@@ -0,0 +1,355 @@
+//! Chain Client
+//!
+//! This crate provides functionality for reading on-chain state from EVM JSON-RPC nodes.
+
+pub mod abi;
+pub mod chainlink;
+mod multicall;
+pub mod pendle;
+pub mod stakestone;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod types;
+
+use std::{
+    collections::HashMap,
+    env,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use abi::Address;
+use anyhow::{Result, anyhow};
+use reqwest::{Client, Url};
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+use types::{Block, BlockTag, RpcBlock, RpcError, RpcRequest, RpcResponse};
+
+pub use multicall::{BalanceMatrix, Call, MULTICALL3, MulticallConfig, TokenBalance};
+
+#[derive(Clone)]
+pub struct ChainClient {
+    pub rpc_url: Url,
+    pub client: Client,
+    pub multicall: MulticallConfig,
+    next_id: Arc<AtomicU64>,
+}
+
+impl ChainClient {
+    /// Creates a client for the node configured in `ETH_RPC_URL`
+    pub fn new() -> Self {
+        dotenvy::dotenv().ok();
+        let rpc_url = env::var("ETH_RPC_URL").expect("ETH_RPC_URL is not set");
+        Self::with_url(Url::parse(&rpc_url).unwrap())
+    }
+
+    /// Creates a client for the given node, e.g. a local development node
+    pub fn with_url(rpc_url: Url) -> Self {
+        Self {
+            rpc_url,
+            client: Client::new(),
+            multicall: MulticallConfig::default(),
+            next_id: Arc::new(AtomicU64::new(1)),
+        }
+    }
+
+    /// Overrides the chunking limits used for batched reads
+    pub fn with_multicall_config(mut self, multicall: MulticallConfig) -> Self {
+        self.multicall = multicall;
+        self
+    }
+
+    fn request_envelope(&self, method: &str, params: Value) -> RpcRequest {
+        RpcRequest {
+            jsonrpc: "2.0",
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            method: method.to_owned(),
+            params,
+        }
+    }
+
+    /// Sends a single JSON-RPC request
+    pub async fn request<T: DeserializeOwned>(&self, method: &str, params: Value) -> Result<T> {
+        let req = self.request_envelope(method, params);
+        let resp = self
+            .client
+            .post(self.rpc_url.clone())
+            .json(&req)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<RpcResponse>()
+            .await?;
+        let value = into_result(resp).map_err(|e| anyhow!(ChainError::from(e)))?;
+        serde_json::from_value(value)
+            .map_err(|e| anyhow!("Failed to parse response from {method}: {e}"))
+    }
+
+    /// Sends several JSON-RPC requests in a single HTTP round trip
+    ///
+    /// Results are returned in the order of the requests, regardless of the order the node
+    /// answered them in.
+    pub async fn batch_request(
+        &self,
+        calls: Vec<(&str, Value)>,
+    ) -> Result<Vec<Result<Value, RpcError>>> {
+        if calls.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let reqs = calls
+            .into_iter()
+            .map(|(method, params)| self.request_envelope(method, params))
+            .collect::<Vec<_>>();
+        let ids = reqs.iter().map(|req| req.id).collect::<Vec<_>>();
+
+        let resps = self
+            .client
+            .post(self.rpc_url.clone())
+            .json(&reqs)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Vec<RpcResponse>>()
+            .await?;
+
+        let mut by_id = resps
+            .into_iter()
+            .map(|resp| (resp.id, resp))
+            .collect::<HashMap<_, _>>();
+        ids.into_iter()
+            .map(|id| {
+                by_id
+                    .remove(&id)
+                    .map(into_result)
+                    .ok_or_else(|| anyhow!(ChainError::MissingResponse(id)))
+            })
+            .collect()
+    }
+
+    /// Get the number of the most recent block
+    pub async fn block_number(&self) -> Result<u64> {
+        let number: String = self.request("eth_blockNumber", json!([])).await?;
+        parse_quantity(&number)
+    }
+
+    /// Get the header of a block
+    pub async fn get_block(&self, block: BlockTag) -> Result<Block> {
+        let rpc_block: Option<RpcBlock> = match block {
+            BlockTag::Hash(hash) => {
+                let hash = format!("0x{}", hex::encode(hash));
+                self.request("eth_getBlockByHash", json!([hash, false]))
+                    .await?
+            }
+            _ => {
+                self.request("eth_getBlockByNumber", json!([block.to_param(), false]))
+                    .await?
+            }
+        };
+        let rpc_block = rpc_block.ok_or_else(|| anyhow!(ChainError::BlockNotFound(block)))?;
+        Ok(Block {
+            number: parse_quantity(&rpc_block.number)?,
+            hash: parse_data(&rpc_block.hash)?
+                .try_into()
+                .map_err(|_| anyhow!("Invalid block hash {}", rpc_block.hash))?,
+            timestamp: parse_quantity(&rpc_block.timestamp)?,
+        })
+    }
+
+    /// Get the block the given number of confirmations behind the chain head
+    pub async fn pin_block(&self, confirmations: u64) -> Result<Block> {
+        let head = self.block_number().await?;
+        self.get_block(BlockTag::Number(head.saturating_sub(confirmations)))
+            .await
+    }
+
+    /// Checks whether a block is still part of the canonical chain
+    pub async fn is_canonical(&self, block: &Block) -> Result<bool> {
+        let canonical = self.get_block(BlockTag::Number(block.number)).await?;
+        Ok(canonical.hash == block.hash)
+    }
+
+    /// Get the last block produced at or before the given unix timestamp
+    ///
+    /// Fails for timestamps past the block the given number of confirmations behind the head,
+    /// whose last block may still change.
+    pub async fn block_at_timestamp(&self, timestamp: u64, confirmations: u64) -> Result<Block> {
+        let genesis = self.get_block(BlockTag::Number(0)).await?;
+        let head = self.pin_block(confirmations).await?;
+        self.block_at_timestamp_between(timestamp, genesis, head)
+            .await
+    }
+
+    /// Binary searches for the last block at or before the timestamp between two known blocks
+    ///
+    /// `hi` is the newest block that may be returned, e.g. the confirmed head, and later
+    /// timestamps are rejected. Passing the result of a previous search as `lo` narrows the
+    /// search when resolving timestamps in ascending order.
+    pub async fn block_at_timestamp_between(
+        &self,
+        timestamp: u64,
+        mut lo: Block,
+        mut hi: Block,
+    ) -> Result<Block> {
+        if timestamp < lo.timestamp {
+            return Err(anyhow!(ChainError::TimestampOutOfRange(timestamp)));
+        }
+        if timestamp > hi.timestamp {
+            return Err(anyhow!(ChainError::TimestampNotConfirmed(timestamp)));
+        }
+        if timestamp == hi.timestamp {
+            return Ok(hi);
+        }
+
+        // Invariant: lo.timestamp <= timestamp < hi.timestamp
+        while hi.number - lo.number > 1 {
+            let mid = lo.number + (hi.number - lo.number) / 2;
+            let block = self.get_block(BlockTag::Number(mid)).await?;
+            if block.timestamp <= timestamp {
+                lo = block;
+            } else {
+                hi = block;
+            }
+        }
+        Ok(lo)
+    }
+
+    /// Executes a read-only contract call
+    pub async fn eth_call(&self, to: Address, data: &[u8], block: BlockTag) -> Result<Vec<u8>> {
+        let data: String = self
+            .request("eth_call", eth_call_params(to, data, block))
+            .await?;
+        parse_data(&data)
+    }
+}
+
+impl Default for ChainClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Builds the parameters of an `eth_call` request
+pub(crate) fn eth_call_params(to: Address, data: &[u8], block: BlockTag) -> Value {
+    json!([
+        { "to": to, "data": format!("0x{}", hex::encode(data)) },
+        block.to_param(),
+    ])
+}
+
+fn into_result(resp: RpcResponse) -> Result<Value, RpcError> {
+    match (resp.error, resp.result) {
+        (Some(error), _) => Err(error),
+        (None, Some(result)) => Ok(result),
+        (None, None) => Ok(Value::Null),
+    }
+}
+
+/// Parses a hex-encoded quantity such as `0x1b4`
+pub fn parse_quantity(value: &str) -> Result<u64> {
+    let hex_str = value.strip_prefix("0x").unwrap_or(value);
+    u64::from_str_radix(hex_str, 16).map_err(|e| anyhow!("Invalid quantity {value}: {e}"))
+}
+
+/// Parses hex-encoded binary data such as `0xdeadbeef`
+pub fn parse_data(value: &str) -> Result<Vec<u8>> {
+    let hex_str = value.strip_prefix("0x").unwrap_or(value);
+    hex::decode(hex_str).map_err(|e| anyhow!("Invalid data {value}: {e}"))
+}
+
+/// Custom error types for chain client
+#[derive(Debug, thiserror::Error)]
+pub enum ChainError {
+    #[error("RPC error {code}: {message}")]
+    Rpc { code: i64, message: String },
+
+    #[error("Missing response for request id {0}")]
+    MissingResponse(u64),
+
+    #[error("Block not found: {0:?}")]
+    BlockNotFound(BlockTag),
+
+    #[error("Block {0} was reorged on every read attempt")]
+    Reorged(u64),
+
+    #[error("No block at or before timestamp {0}")]
+    TimestampOutOfRange(u64),
+
+    #[error("Timestamp {0} is past the confirmed chain head")]
+    TimestampNotConfirmed(u64),
+}
+
+impl From<RpcError> for ChainError {
+    fn from(error: RpcError) -> Self {
+        ChainError::Rpc {
+            code: error.code,
+            message: error.message,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::testing::{BLOCK_TIME, GENESIS_TIME, local_node};
+
+    async fn client() -> ChainClient {
+        local_node(Arc::new(|_, _| None)).await
+    }
+
+    #[tokio::test]
+    async fn block_at_timestamp_finds_the_last_block_at_or_before() {
+        let client = client().await;
+        let block = client
+            .block_at_timestamp(GENESIS_TIME + 50 * BLOCK_TIME + 5, 10)
+            .await
+            .unwrap();
+        assert_eq!(block.number, 50);
+
+        let block = client
+            .block_at_timestamp(GENESIS_TIME + 7 * BLOCK_TIME, 10)
+            .await
+            .unwrap();
+        assert_eq!(block.number, 7);
+    }
+
+    #[tokio::test]
+    async fn block_at_timestamp_accepts_the_confirmed_head() {
+        let client = client().await;
+        let block = client
+            .block_at_timestamp(GENESIS_TIME + 90 * BLOCK_TIME, 10)
+            .await
+            .unwrap();
+        assert_eq!(block.number, 90);
+    }
+
+    #[tokio::test]
+    async fn block_at_timestamp_rejects_unconfirmed_timestamps() {
+        let client = client().await;
+        let e = client
+            .block_at_timestamp(GENESIS_TIME + 90 * BLOCK_TIME + 1, 10)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<ChainError>(),
+            Some(ChainError::TimestampNotConfirmed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn block_at_timestamp_rejects_timestamps_before_genesis() {
+        let client = client().await;
+        let e = client
+            .block_at_timestamp(GENESIS_TIME - 1, 10)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<ChainError>(),
+            Some(ChainError::TimestampOutOfRange(_))
+        ));
+    }
+}
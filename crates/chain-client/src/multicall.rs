@@ -0,0 +1,434 @@
+//! Batched contract reads through Multicall3
+
+use std::{collections::HashMap, ops::Range};
+
+use anyhow::{Result, anyhow};
+use rust_decimal::Decimal;
+use serde_json::Value;
+use tracing::warn;
+
+use crate::{
+    ChainClient, ChainError,
+    abi::{
+        Address, BALANCE_OF, DECIMALS, WORD, decode_bool, decode_bytes, decode_uint, decode_usize,
+        encode_address, encode_bool, encode_call, encode_uint, offset_from, padded_len, to_decimal,
+    },
+    eth_call_params, parse_data,
+    types::{Asset, BlockTag},
+};
+
+/// Multicall3, deployed at the same address on every major EVM chain
+pub const MULTICALL3: Address = Address([
+    0xca, 0x11, 0xbd, 0xe0, 0x59, 0x77, 0xb3, 0x63, 0x11, 0x67, 0x02, 0x88, 0x62, 0xbe, 0x2a, 0x17,
+    0x39, 0x76, 0xca, 0x11,
+]);
+
+/// `aggregate3((address,bool,bytes)[])`
+pub(crate) const AGGREGATE3: [u8; 4] = [0x82, 0xad, 0x56, 0xcb];
+/// `getEthBalance(address)`
+const GET_ETH_BALANCE: [u8; 4] = [0x4d, 0x23, 0x01, 0xcc];
+
+/// Decimals of the native currency
+const NATIVE_DECIMALS: u32 = 18;
+
+/// Limits used to split batched reads into requests
+#[derive(Debug, Clone)]
+pub struct MulticallConfig {
+    /// Maximum calldata size of a single `aggregate3` call, in bytes
+    pub max_calldata_bytes: usize,
+    /// Maximum number of requests sent in a single JSON-RPC batch
+    pub max_batch_size: usize,
+}
+
+impl Default for MulticallConfig {
+    fn default() -> Self {
+        Self {
+            max_calldata_bytes: 16 * 1024,
+            max_batch_size: 10,
+        }
+    }
+}
+
+/// A single read-only contract call
+#[derive(Debug, Clone)]
+pub struct Call {
+    pub target: Address,
+    pub data: Vec<u8>,
+}
+
+impl Call {
+    pub fn new(target: Address, data: Vec<u8>) -> Self {
+        Self { target, data }
+    }
+
+    /// Size this call adds to `aggregate3` calldata: the offset slot plus the encoded tuple
+    fn encoded_len(&self) -> usize {
+        WORD + 4 * WORD + padded_len(self.data.len())
+    }
+}
+
+/// Balance of one asset held by one account
+#[derive(Debug, Clone)]
+pub struct TokenBalance {
+    pub owner: Address,
+    pub asset: Asset,
+    pub amount: Decimal,
+}
+
+/// Balances of every requested account and asset pair
+#[derive(Debug, Clone, Default)]
+pub struct BalanceMatrix {
+    pub balances: Vec<TokenBalance>,
+    /// Pairs that could not be read, even with individual calls
+    pub failed: Vec<(Address, Asset)>,
+}
+
+impl ChainClient {
+    /// Executes calls through Multicall3 `aggregate3`
+    ///
+    /// Calls are chunked by calldata size and the chunks are sent as JSON-RPC batches. Calls
+    /// that fail inside the aggregate are retried individually; calls that still fail yield
+    /// `None`.
+    pub async fn multicall(&self, calls: &[Call], block: BlockTag) -> Result<Vec<Option<Vec<u8>>>> {
+        let mut results = vec![None; calls.len()];
+        let mut retry = Vec::new();
+
+        let chunks = chunk_calls(calls, self.multicall.max_calldata_bytes);
+        for group in chunks.chunks(self.multicall.max_batch_size.max(1)) {
+            let reqs = group
+                .iter()
+                .map(|range| {
+                    let data = encode_aggregate3(&calls[range.clone()]);
+                    ("eth_call", eth_call_params(MULTICALL3, &data, block))
+                })
+                .collect();
+            let resps = self.batch_request(reqs).await?;
+
+            for (range, resp) in group.iter().zip(resps) {
+                let decoded = resp
+                    .map_err(|e| anyhow!(ChainError::from(e)))
+                    .and_then(|value| parse_value(&value))
+                    .and_then(|data| decode_aggregate3(&data, range.len()));
+                match decoded {
+                    Ok(chunk_results) => {
+                        for (index, result) in range.clone().zip(chunk_results) {
+                            match result {
+                                Some(data) => results[index] = Some(data),
+                                None => retry.push(index),
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        warn!("Multicall chunk of {} calls failed: {e:#}", range.len());
+                        retry.extend(range.clone());
+                    }
+                }
+            }
+        }
+
+        for group in retry.chunks(self.multicall.max_batch_size.max(1)) {
+            let reqs = group
+                .iter()
+                .map(|index| {
+                    let call = &calls[*index];
+                    ("eth_call", eth_call_params(call.target, &call.data, block))
+                })
+                .collect();
+            let resps = self.batch_request(reqs).await?;
+
+            for (index, resp) in group.iter().zip(resps) {
+                match resp
+                    .map_err(|e| anyhow!(ChainError::from(e)))
+                    .and_then(|value| parse_value(&value))
+                {
+                    Ok(data) if !data.is_empty() => results[*index] = Some(data),
+                    Ok(_) => warn!("Call to {} returned no data", calls[*index].target),
+                    Err(e) => warn!("Call to {} failed: {e:#}", calls[*index].target),
+                }
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Reads the balance of every asset for every owner
+    pub async fn read_balances(
+        &self,
+        owners: &[Address],
+        assets: &[Asset],
+        block: BlockTag,
+    ) -> Result<BalanceMatrix> {
+        let tokens = assets
+            .iter()
+            .filter_map(|asset| match asset {
+                Asset::Erc20(token) => Some(*token),
+                Asset::Native => None,
+            })
+            .collect::<Vec<_>>();
+
+        let mut calls = tokens
+            .iter()
+            .map(|token| Call::new(*token, encode_call(DECIMALS, &[])))
+            .collect::<Vec<_>>();
+        let pairs = owners
+            .iter()
+            .flat_map(|owner| assets.iter().map(move |asset| (*owner, *asset)))
+            .collect::<Vec<_>>();
+        calls.extend(
+            pairs
+                .iter()
+                .map(|(owner, asset)| balance_call(owner, asset)),
+        );
+
+        let results = self.multicall(&calls, block).await?;
+        let (decimal_results, balance_results) = results.split_at(tokens.len());
+
+        let decimals = tokens
+            .iter()
+            .zip(decimal_results)
+            .filter_map(|(token, result)| {
+                let data = result.as_ref()?;
+                match decode_uint(data, 0) {
+                    Ok(decimals) => Some((*token, decimals as u32)),
+                    Err(e) => {
+                        warn!("Invalid decimals for token {token}: {e:#}");
+                        None
+                    }
+                }
+            })
+            .collect::<HashMap<_, _>>();
+
+        let mut matrix = BalanceMatrix::default();
+        for ((owner, asset), result) in pairs.into_iter().zip(balance_results) {
+            let asset_decimals = match asset {
+                Asset::Native => Some(NATIVE_DECIMALS),
+                Asset::Erc20(token) => decimals.get(&token).copied(),
+            };
+            let amount = match (result, asset_decimals) {
+                (Some(data), Some(asset_decimals)) => {
+                    decode_uint(data, 0).and_then(|raw| to_decimal(raw, asset_decimals))
+                }
+                _ => Err(anyhow!("no data")),
+            };
+            match amount {
+                Ok(amount) => matrix.balances.push(TokenBalance {
+                    owner,
+                    asset,
+                    amount,
+                }),
+                Err(e) => {
+                    warn!("Failed to read {asset} balance of {owner}: {e:#}");
+                    matrix.failed.push((owner, asset));
+                }
+            }
+        }
+
+        Ok(matrix)
+    }
+}
+
+/// Builds the call reading an owner's balance of an asset
+fn balance_call(owner: &Address, asset: &Asset) -> Call {
+    match asset {
+        Asset::Native => Call::new(
+            MULTICALL3,
+            encode_call(GET_ETH_BALANCE, &[encode_address(owner)]),
+        ),
+        Asset::Erc20(token) => Call::new(*token, encode_call(BALANCE_OF, &[encode_address(owner)])),
+    }
+}
+
+/// Splits calls into consecutive ranges whose `aggregate3` calldata stays within the limit
+fn chunk_calls(calls: &[Call], max_calldata_bytes: usize) -> Vec<Range<usize>> {
+    // Selector, array offset and array length
+    let overhead = 4 + 2 * WORD;
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    let mut size = overhead;
+    for (index, call) in calls.iter().enumerate() {
+        let len = call.encoded_len();
+        if index > start && size + len > max_calldata_bytes {
+            chunks.push(start..index);
+            start = index;
+            size = overhead;
+        }
+        size += len;
+    }
+    if start < calls.len() {
+        chunks.push(start..calls.len());
+    }
+    chunks
+}
+
+/// Encodes an `aggregate3` call that allows every subcall to fail
+fn encode_aggregate3(calls: &[Call]) -> Vec<u8> {
+    let mut head = Vec::with_capacity(calls.len() * WORD);
+    let mut tail = Vec::new();
+    for call in calls {
+        head.extend_from_slice(&encode_uint((calls.len() * WORD + tail.len()) as u128));
+        tail.extend_from_slice(&encode_address(&call.target));
+        tail.extend_from_slice(&encode_bool(true));
+        tail.extend_from_slice(&encode_uint((3 * WORD) as u128));
+        tail.extend_from_slice(&encode_uint(call.data.len() as u128));
+        tail.extend_from_slice(&call.data);
+        tail.resize(
+            tail.len() + padded_len(call.data.len()) - call.data.len(),
+            0,
+        );
+    }
+
+    let mut data = encode_call(
+        AGGREGATE3,
+        &[encode_uint(WORD as u128), encode_uint(calls.len() as u128)],
+    );
+    data.extend_from_slice(&head);
+    data.extend_from_slice(&tail);
+    data
+}
+
+/// Decodes the `(bool success, bytes returnData)[]` result of `aggregate3`
+fn decode_aggregate3(data: &[u8], expected: usize) -> Result<Vec<Option<Vec<u8>>>> {
+    let array = decode_usize(data, 0)?;
+    let len = decode_usize(data.get(array..).unwrap_or_default(), 0)?;
+    if len != expected {
+        return Err(anyhow!(
+            "aggregate3 returned {len} results, expected {expected}"
+        ));
+    }
+
+    let base = offset_from(array, WORD)?;
+    (0..len)
+        .map(|index| {
+            let tuple = offset_from(
+                base,
+                decode_usize(data.get(base..).unwrap_or_default(), index)?,
+            )?;
+            let tuple_data = data.get(tuple..).unwrap_or_default();
+            if decode_bool(tuple_data, 0)? {
+                decode_bytes(data, tuple, 1).map(Some)
+            } else {
+                Ok(None)
+            }
+        })
+        .collect()
+}
+
+fn parse_value(value: &Value) -> Result<Vec<u8>> {
+    value
+        .as_str()
+        .ok_or_else(|| anyhow!("Expected hex data, got {value}"))
+        .and_then(parse_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        abi::{decode_bool, encode_uint},
+        testing::{decode_calls, encode_results},
+    };
+
+    fn call(data_len: usize) -> Call {
+        Call::new(Address([0x01; 20]), vec![0xee; data_len])
+    }
+
+    #[test]
+    fn chunk_calls_keeps_everything_in_one_chunk_under_the_limit() {
+        let calls = vec![call(36); 3];
+        assert_eq!(chunk_calls(&calls, 16 * 1024), vec![0..3]);
+        assert!(chunk_calls(&[], 16 * 1024).is_empty());
+    }
+
+    #[test]
+    fn chunk_calls_splits_at_the_calldata_limit() {
+        // Each call adds a 32-byte offset, a 128-byte tuple head and 64 bytes of data
+        let calls = vec![call(36); 5];
+        let overhead = 4 + 2 * WORD;
+        let limit = overhead + 2 * calls[0].encoded_len();
+        assert_eq!(chunk_calls(&calls, limit), vec![0..2, 2..4, 4..5]);
+        assert_eq!(chunk_calls(&calls, limit + 1), vec![0..2, 2..4, 4..5]);
+        assert_eq!(
+            chunk_calls(&calls, limit - 1),
+            vec![0..1, 1..2, 2..3, 3..4, 4..5]
+        );
+    }
+
+    #[test]
+    fn chunk_calls_never_produces_empty_chunks() {
+        let calls = vec![call(1024), call(4)];
+        assert_eq!(chunk_calls(&calls, 100), vec![0..1, 1..2]);
+    }
+
+    #[test]
+    fn encoded_aggregate3_matches_the_estimated_size() {
+        let calls = vec![call(4), call(36), call(0)];
+        let data = encode_aggregate3(&calls);
+        let estimated = 4 + 2 * WORD + calls.iter().map(Call::encoded_len).sum::<usize>();
+        assert_eq!(data.len(), estimated);
+        assert_eq!(data[..4], AGGREGATE3);
+    }
+
+    #[test]
+    fn encoded_aggregate3_round_trips() {
+        let calls = vec![call(4), call(36)];
+        let data = encode_aggregate3(&calls);
+        assert_eq!(
+            decode_calls(&data),
+            calls
+                .iter()
+                .map(|call| (call.target, call.data.clone()))
+                .collect::<Vec<_>>()
+        );
+
+        // Every call allows failure, so one revert does not fail the aggregate
+        let array = &data[4 + 2 * WORD..];
+        for index in 0..calls.len() {
+            let tuple = &array[decode_uint(array, index).unwrap() as usize..];
+            assert!(decode_bool(tuple, 1).unwrap());
+        }
+    }
+
+    #[test]
+    fn decode_aggregate3_maps_failures_to_none() {
+        let data = encode_results(&[Some(vec![0xab; 33]), None, Some(Vec::new())]);
+        assert_eq!(
+            decode_aggregate3(&data, 3).unwrap(),
+            vec![Some(vec![0xab; 33]), None, Some(Vec::new())]
+        );
+    }
+
+    #[test]
+    fn decode_aggregate3_rejects_unexpected_lengths() {
+        let data = encode_results(&[Some(vec![0x01])]);
+        assert!(decode_aggregate3(&data, 2).is_err());
+        assert!(decode_aggregate3(&data[..WORD], 1).is_err());
+    }
+
+    #[test]
+    fn decode_aggregate3_rejects_out_of_range_offsets() {
+        let mut data = encode_results(&[Some(vec![0x01])]);
+        data[..WORD].copy_from_slice(&encode_uint(u128::MAX));
+        assert!(decode_aggregate3(&data, 1).is_err());
+
+        // Tuple offset that overflows when added to the array start
+        let mut data = encode_results(&[Some(vec![0x01])]);
+        data[2 * WORD..3 * WORD].copy_from_slice(&encode_uint(usize::MAX as u128));
+        assert!(decode_aggregate3(&data, 1).is_err());
+    }
+
+    #[test]
+    fn balance_call_reads_native_balance_through_multicall() {
+        let owner = Address([0x33; 20]);
+        let native = balance_call(&owner, &Asset::Native);
+        assert_eq!(native.target, MULTICALL3);
+        assert_eq!(native.data[..4], GET_ETH_BALANCE);
+
+        let token = Address([0x44; 20]);
+        let erc20 = balance_call(&owner, &Asset::Erc20(token));
+        assert_eq!(erc20.target, token);
+        assert_eq!(
+            erc20.data,
+            encode_call(BALANCE_OF, &[encode_address(&owner)])
+        );
+    }
+}
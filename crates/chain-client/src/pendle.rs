@@ -0,0 +1,490 @@
+//! Pendle v2 market state and position valuation
+
+use anyhow::{Result, anyhow};
+use rust_decimal::{Decimal, MathematicalOps};
+use tracing::warn;
+
+use crate::{
+    Call, ChainClient,
+    abi::{
+        Address, BALANCE_OF, DECIMALS, TOTAL_SUPPLY, WAD_DECIMALS, decode_address, decode_int,
+        decode_uint, encode_address, encode_call, to_decimal, to_signed_decimal,
+    },
+    types::BlockTag,
+};
+
+/// `readTokens()`
+const READ_TOKENS: [u8; 4] = [0x2c, 0x8c, 0xe6, 0xbc];
+/// `expiry()`
+const EXPIRY: [u8; 4] = [0xe1, 0x84, 0xc9, 0xbe];
+/// `_storage()`
+const STORAGE: [u8; 4] = [0xc3, 0xfb, 0x90, 0xd6];
+/// `exchangeRate()`
+const EXCHANGE_RATE: [u8; 4] = [0x3b, 0xa0, 0xb9, 0xa9];
+/// `assetInfo()`
+const ASSET_INFO: [u8; 4] = [0xa4, 0x0b, 0xee, 0x50];
+
+/// Seconds per year used by Pendle to annualise implied rates
+const SECONDS_PER_YEAR: u64 = 365 * 24 * 60 * 60;
+
+/// State of a Pendle v2 market and its standardized yield token
+#[derive(Debug, Clone)]
+pub struct PendleMarket {
+    pub address: Address,
+    pub sy: Address,
+    pub pt: Address,
+    pub yt: Address,
+    /// Underlying asset PT redeems to at maturity
+    pub asset: Address,
+    /// Maturity as a unix timestamp
+    pub expiry: u64,
+    /// Decimals shared by SY, PT and YT
+    pub decimals: u32,
+    /// Decimals of the LP token
+    pub lp_decimals: u32,
+    pub total_pt: Decimal,
+    pub total_sy: Decimal,
+    pub total_lp: Decimal,
+    /// Natural log of one plus the annual implied rate
+    pub ln_implied_rate: Decimal,
+    /// Amount of asset one SY is worth
+    pub asset_per_sy: Decimal,
+    /// Amount of asset one PT redeems for at maturity, which differs from one only when the
+    /// asset and SY have different decimals
+    pub asset_per_pt: Decimal,
+}
+
+impl PendleMarket {
+    pub fn is_expired(&self, now: u64) -> bool {
+        now >= self.expiry
+    }
+
+    /// Asset value of one PT, discounted from maturity at the implied rate
+    pub fn pt_price(&self, now: u64) -> Decimal {
+        if self.is_expired(now) {
+            return Decimal::ONE;
+        }
+        let years = Decimal::from(self.expiry - now) / Decimal::from(SECONDS_PER_YEAR);
+        (-(self.ln_implied_rate * years)).exp()
+    }
+
+    /// Asset value of one YT, the remainder of the yield until maturity
+    pub fn yt_price(&self, now: u64) -> Decimal {
+        if self.is_expired(now) {
+            return Decimal::ZERO;
+        }
+        Decimal::ONE - self.pt_price(now)
+    }
+
+    /// SY and PT amounts backing the given amount of LP
+    pub fn lp_composition(&self, lp: Decimal) -> (Decimal, Decimal) {
+        if self.total_lp.is_zero() {
+            return (Decimal::ZERO, Decimal::ZERO);
+        }
+        let share = lp / self.total_lp;
+        (self.total_sy * share, self.total_pt * share)
+    }
+}
+
+/// Kind of token held in a Pendle market
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PendleTokenKind {
+    Pt,
+    Yt,
+    Lp,
+}
+
+/// A holding of one Pendle token, valued in the market's underlying asset
+#[derive(Debug, Clone)]
+pub struct PendlePosition {
+    pub owner: Address,
+    pub market: Address,
+    pub kind: PendleTokenKind,
+    /// Maturity of the market as a unix timestamp
+    pub expiry: u64,
+    /// Token balance held
+    pub balance: Decimal,
+    pub asset: Address,
+    /// Underlying asset exposure of the position
+    pub asset_amount: Decimal,
+}
+
+impl ChainClient {
+    /// Reads the state of Pendle markets
+    ///
+    /// Markets whose state cannot be read are skipped with a warning.
+    pub async fn read_pendle_markets(
+        &self,
+        markets: &[Address],
+        block: BlockTag,
+    ) -> Result<Vec<PendleMarket>> {
+        let market_calls = markets
+            .iter()
+            .flat_map(|market| {
+                [READ_TOKENS, EXPIRY, TOTAL_SUPPLY, STORAGE, DECIMALS]
+                    .map(|selector| Call::new(*market, encode_call(selector, &[])))
+            })
+            .collect::<Vec<_>>();
+        let market_results = self.multicall(&market_calls, block).await?;
+
+        let mut partial = Vec::new();
+        for (market, results) in markets.iter().zip(market_results.chunks(5)) {
+            match decode_market_state(*market, results) {
+                Ok(state) => partial.push(state),
+                Err(e) => warn!("Failed to read Pendle market {market}: {e:#}"),
+            }
+        }
+
+        let sy_calls = partial
+            .iter()
+            .flat_map(|state| {
+                [EXCHANGE_RATE, ASSET_INFO, DECIMALS]
+                    .map(|selector| Call::new(state.sy, encode_call(selector, &[])))
+            })
+            .collect::<Vec<_>>();
+        let sy_results = self.multicall(&sy_calls, block).await?;
+
+        let mut markets = Vec::new();
+        for (state, results) in partial.into_iter().zip(sy_results.chunks(3)) {
+            let address = state.address;
+            match decode_sy_state(state, results) {
+                Ok(market) => markets.push(market),
+                Err(e) => warn!("Failed to read SY of Pendle market {address}: {e:#}"),
+            }
+        }
+        Ok(markets)
+    }
+
+    /// Reads the PT, YT and LP holdings of each owner and values them in the underlying asset
+    ///
+    /// `now` is the unix timestamp the positions are valued at, normally the block time.
+    pub async fn read_pendle_positions(
+        &self,
+        owners: &[Address],
+        markets: &[PendleMarket],
+        now: u64,
+        block: BlockTag,
+    ) -> Result<Vec<PendlePosition>> {
+        let pairs = owners
+            .iter()
+            .flat_map(|owner| markets.iter().map(move |market| (*owner, market)))
+            .collect::<Vec<_>>();
+        let calls = pairs
+            .iter()
+            .flat_map(|(owner, market)| {
+                let data = encode_call(BALANCE_OF, &[encode_address(owner)]);
+                [market.address, market.pt, market.yt].map(|token| Call::new(token, data.clone()))
+            })
+            .collect::<Vec<_>>();
+        let results = self.multicall(&calls, block).await?;
+
+        let mut positions = Vec::new();
+        for ((owner, market), results) in pairs.into_iter().zip(results.chunks(3)) {
+            let holdings = [
+                (PendleTokenKind::Lp, market.lp_decimals, &results[0]),
+                (PendleTokenKind::Pt, market.decimals, &results[1]),
+                (PendleTokenKind::Yt, market.decimals, &results[2]),
+            ];
+            for (kind, decimals, result) in holdings {
+                let balance = match result {
+                    Some(data) => decode_uint(data, 0).and_then(|raw| to_decimal(raw, decimals)),
+                    None => Err(anyhow!("no data")),
+                };
+                let balance = match balance {
+                    Ok(balance) if balance.is_zero() => continue,
+                    Ok(balance) => balance,
+                    Err(e) => {
+                        warn!(
+                            "Failed to read {kind:?} balance of {owner} in market {}: {e:#}",
+                            market.address
+                        );
+                        continue;
+                    }
+                };
+
+                let asset_amount = match kind {
+                    PendleTokenKind::Pt => balance * market.pt_price(now) * market.asset_per_pt,
+                    PendleTokenKind::Yt => balance * market.yt_price(now) * market.asset_per_pt,
+                    PendleTokenKind::Lp => {
+                        let (sy, pt) = market.lp_composition(balance);
+                        sy * market.asset_per_sy + pt * market.pt_price(now) * market.asset_per_pt
+                    }
+                };
+                positions.push(PendlePosition {
+                    owner,
+                    market: market.address,
+                    kind,
+                    expiry: market.expiry,
+                    balance,
+                    asset: market.asset,
+                    asset_amount,
+                });
+            }
+        }
+        Ok(positions)
+    }
+}
+
+/// Market fields read in the first round, before the SY is known
+struct MarketState {
+    address: Address,
+    sy: Address,
+    pt: Address,
+    yt: Address,
+    expiry: u64,
+    lp_decimals: u32,
+    total_lp: u128,
+    total_pt: i128,
+    total_sy: i128,
+    ln_implied_rate: u128,
+}
+
+fn decode_market_state(address: Address, results: &[Option<Vec<u8>>]) -> Result<MarketState> {
+    let [tokens, expiry, total_supply, storage, decimals] = results else {
+        return Err(anyhow!("unexpected number of results"));
+    };
+    let tokens = tokens
+        .as_deref()
+        .ok_or_else(|| anyhow!("readTokens failed"))?;
+    let expiry = expiry.as_deref().ok_or_else(|| anyhow!("expiry failed"))?;
+    let total_supply = total_supply
+        .as_deref()
+        .ok_or_else(|| anyhow!("totalSupply failed"))?;
+    let storage = storage
+        .as_deref()
+        .ok_or_else(|| anyhow!("_storage failed"))?;
+    let decimals = decimals
+        .as_deref()
+        .ok_or_else(|| anyhow!("decimals failed"))?;
+
+    Ok(MarketState {
+        address,
+        sy: decode_address(tokens, 0)?,
+        pt: decode_address(tokens, 1)?,
+        yt: decode_address(tokens, 2)?,
+        expiry: decode_uint(expiry, 0)? as u64,
+        lp_decimals: decode_uint(decimals, 0)? as u32,
+        total_lp: decode_uint(total_supply, 0)?,
+        total_pt: decode_int(storage, 0)?,
+        total_sy: decode_int(storage, 1)?,
+        ln_implied_rate: decode_uint(storage, 2)?,
+    })
+}
+
+fn decode_sy_state(state: MarketState, results: &[Option<Vec<u8>>]) -> Result<PendleMarket> {
+    let [exchange_rate, asset_info, decimals] = results else {
+        return Err(anyhow!("unexpected number of results"));
+    };
+    let exchange_rate = exchange_rate
+        .as_deref()
+        .ok_or_else(|| anyhow!("exchangeRate failed"))?;
+    let asset_info = asset_info
+        .as_deref()
+        .ok_or_else(|| anyhow!("assetInfo failed"))?;
+    let decimals = decimals
+        .as_deref()
+        .ok_or_else(|| anyhow!("decimals failed"))?;
+
+    let sy_decimals = decode_uint(decimals, 0)? as u32;
+    let asset_decimals = decode_uint(asset_info, 2)? as u32;
+    // PT redeems one raw asset unit per raw PT unit, and exchangeRate converts raw SY units
+    // into raw asset units scaled by 1e18
+    let asset_per_pt = Decimal::TEN.powi(sy_decimals as i64 - asset_decimals as i64);
+    let asset_per_sy = to_decimal(decode_uint(exchange_rate, 0)?, WAD_DECIMALS)? * asset_per_pt;
+
+    Ok(PendleMarket {
+        address: state.address,
+        sy: state.sy,
+        pt: state.pt,
+        yt: state.yt,
+        asset: decode_address(asset_info, 1)?,
+        expiry: state.expiry,
+        decimals: sy_decimals,
+        lp_decimals: state.lp_decimals,
+        total_pt: to_signed_decimal(state.total_pt, sy_decimals)?,
+        total_sy: to_signed_decimal(state.total_sy, sy_decimals)?,
+        total_lp: to_decimal(state.total_lp, state.lp_decimals)?,
+        ln_implied_rate: to_decimal(state.ln_implied_rate, WAD_DECIMALS)?,
+        asset_per_sy,
+        asset_per_pt,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::abi::encode_uint;
+
+    const EXPIRY_TIME: u64 = 2_000_000_000;
+
+    /// ln(1.05), a 5% annual implied rate
+    fn ln_rate() -> Decimal {
+        Decimal::from_str("0.048790164169432").unwrap()
+    }
+
+    fn dec(value: &str) -> Decimal {
+        Decimal::from_str(value).unwrap()
+    }
+
+    fn market() -> PendleMarket {
+        PendleMarket {
+            address: Address([0x01; 20]),
+            sy: Address([0x02; 20]),
+            pt: Address([0x03; 20]),
+            yt: Address([0x04; 20]),
+            asset: Address([0x05; 20]),
+            expiry: EXPIRY_TIME,
+            decimals: 18,
+            lp_decimals: 18,
+            total_pt: Decimal::from(60),
+            total_sy: Decimal::from(40),
+            total_lp: Decimal::from(100),
+            ln_implied_rate: ln_rate(),
+            asset_per_sy: Decimal::ONE,
+            asset_per_pt: Decimal::ONE,
+        }
+    }
+
+    fn assert_close(actual: Decimal, expected: Decimal) {
+        assert!(
+            (actual - expected).abs() < Decimal::new(1, 10),
+            "{actual} != {expected}"
+        );
+    }
+
+    #[test]
+    fn pt_and_yt_prices_discount_to_maturity() {
+        let half_year = SECONDS_PER_YEAR / 2;
+        // (seconds to expiry, PT price)
+        let cases = [
+            (SECONDS_PER_YEAR, dec("0.952380952380952")),
+            (half_year, dec("0.975900072948533")),
+            (1, dec("0.999999998452873")),
+            (0, Decimal::ONE),
+        ];
+        let market = market();
+        for (remaining, pt) in cases {
+            let now = EXPIRY_TIME - remaining;
+            assert_close(market.pt_price(now), pt);
+            assert_close(market.yt_price(now), Decimal::ONE - pt);
+        }
+    }
+
+    #[test]
+    fn matured_pt_redeems_at_par_and_yt_is_worthless() {
+        let market = market();
+        for now in [EXPIRY_TIME, EXPIRY_TIME + 1, EXPIRY_TIME + SECONDS_PER_YEAR] {
+            assert!(market.is_expired(now));
+            assert_eq!(market.pt_price(now), Decimal::ONE);
+            assert_eq!(market.yt_price(now), Decimal::ZERO);
+        }
+    }
+
+    #[test]
+    fn lp_composition_is_a_pro_rata_share_of_the_pool() {
+        let market = market();
+        // (LP held, SY, PT)
+        let cases = [
+            (Decimal::from(25), Decimal::from(10), Decimal::from(15)),
+            (Decimal::from(100), Decimal::from(40), Decimal::from(60)),
+            (dec("0.5"), dec("0.2"), dec("0.3")),
+            (Decimal::ZERO, Decimal::ZERO, Decimal::ZERO),
+        ];
+        for (lp, sy, pt) in cases {
+            assert_eq!(market.lp_composition(lp), (sy, pt));
+        }
+
+        let empty = PendleMarket {
+            total_lp: Decimal::ZERO,
+            ..market
+        };
+        assert_eq!(
+            empty.lp_composition(Decimal::ONE),
+            (Decimal::ZERO, Decimal::ZERO)
+        );
+    }
+
+    fn state(sy_decimals: u32) -> MarketState {
+        let unit = 10i128.pow(sy_decimals);
+        MarketState {
+            address: Address([0x01; 20]),
+            sy: Address([0x02; 20]),
+            pt: Address([0x03; 20]),
+            yt: Address([0x04; 20]),
+            expiry: EXPIRY_TIME,
+            lp_decimals: 18,
+            total_lp: 100 * 10u128.pow(18),
+            total_pt: 60 * unit,
+            total_sy: 40 * unit,
+            ln_implied_rate: 48_790_164_169_432_000,
+        }
+    }
+
+    fn sy_results(rate: u128, asset_decimals: u32, sy_decimals: u32) -> Vec<Option<Vec<u8>>> {
+        let mut asset_info = encode_uint(0).to_vec();
+        asset_info.extend_from_slice(&[0u8; 12]);
+        asset_info.extend_from_slice(&[0x05; 20]);
+        asset_info.extend_from_slice(&encode_uint(asset_decimals as u128));
+        vec![
+            Some(encode_uint(rate).to_vec()),
+            Some(asset_info),
+            Some(encode_uint(sy_decimals as u128).to_vec()),
+        ]
+    }
+
+    #[test]
+    fn decode_sy_state_scales_by_exchange_rate_and_decimals() {
+        // (SY decimals, asset decimals, exchangeRate, asset per PT, asset per SY)
+        let cases = [
+            (18, 18, 1_050_000_000_000_000_000, "1", "1.05"),
+            (6, 6, 1_000_000_000_000_000_000, "1", "1"),
+            (
+                18,
+                6,
+                1_020_000_000_000_000_000,
+                "1000000000000",
+                "1020000000000",
+            ),
+            (
+                6,
+                18,
+                2_000_000_000_000_000_000,
+                "0.000000000001",
+                "0.000000000002",
+            ),
+        ];
+        for (sy_decimals, asset_decimals, rate, per_pt, per_sy) in cases {
+            let market = decode_sy_state(
+                state(sy_decimals),
+                &sy_results(rate, asset_decimals, sy_decimals),
+            )
+            .unwrap();
+            assert_eq!(
+                market.asset_per_pt,
+                dec(per_pt),
+                "{sy_decimals}/{asset_decimals}"
+            );
+            assert_eq!(
+                market.asset_per_sy,
+                dec(per_sy),
+                "{sy_decimals}/{asset_decimals}"
+            );
+            assert_eq!(market.decimals, sy_decimals);
+            assert_eq!(market.asset, Address([0x05; 20]));
+            assert_eq!(market.total_pt, Decimal::from(60));
+            assert_eq!(market.total_sy, Decimal::from(40));
+            assert_eq!(market.total_lp, Decimal::from(100));
+            assert_eq!(market.ln_implied_rate, ln_rate());
+        }
+    }
+
+    #[test]
+    fn decode_sy_state_fails_on_a_failed_call() {
+        let mut results = sy_results(1_000_000_000_000_000_000, 18, 18);
+        results[0] = None;
+        assert!(decode_sy_state(state(18), &results).is_err());
+        assert!(decode_sy_state(state(18), &results[..2]).is_err());
+    }
+}
@@ -0,0 +1,305 @@
+//! StakeStone vault state and position reads
+
+use std::collections::{BTreeSet, HashMap};
+
+use anyhow::{Result, anyhow};
+use rust_decimal::Decimal;
+use tracing::warn;
+
+use crate::{
+    Call, ChainClient,
+    abi::{
+        Address, BALANCE_OF, WAD_DECIMALS, decode_address, decode_uint, encode_address,
+        encode_call, encode_uint, to_decimal,
+    },
+    types::BlockTag,
+};
+
+/// `stone()`
+const STONE: [u8; 4] = [0x01, 0x67, 0xeb, 0x85];
+/// `currentSharePrice()`
+const CURRENT_SHARE_PRICE: [u8; 4] = [0x28, 0xa7, 0x95, 0x76];
+/// `latestRoundID()`
+const LATEST_ROUND_ID: [u8; 4] = [0xf7, 0x63, 0x39, 0xdc];
+/// `userReceipts(address)`
+const USER_RECEIPTS: [u8; 4] = [0xa4, 0x78, 0x6f, 0x3d];
+/// `roundPricePerShare(uint256)`
+const ROUND_PRICE_PER_SHARE: [u8; 4] = [0x87, 0x15, 0x3e, 0xb1];
+
+/// State of the StakeStone vault
+#[derive(Debug, Clone)]
+pub struct StakestoneVault {
+    pub address: Address,
+    /// STONE share token
+    pub stone: Address,
+    /// ETH value of one STONE share
+    pub share_price: Decimal,
+    /// Round currently accepting withdrawal requests
+    pub latest_round: u64,
+}
+
+/// A withdrawal request queued in the vault
+#[derive(Debug, Clone)]
+pub struct WithdrawalRequest {
+    /// Round the request was made in
+    pub round: u64,
+    pub shares: Decimal,
+    /// Whether the round has been settled at a fixed share price
+    pub settled: bool,
+    /// ETH value of the shares, at the round price once settled
+    pub eth_amount: Decimal,
+}
+
+/// STONE holdings of one account and their ETH exposure
+#[derive(Debug, Clone)]
+pub struct StakestonePosition {
+    pub owner: Address,
+    /// STONE shares held in the wallet
+    pub shares: Decimal,
+    /// ETH value of the held shares
+    pub shares_eth: Decimal,
+    /// ETH from settled withdrawals that can be claimed
+    pub withdrawable_eth: Decimal,
+    pub pending: Option<WithdrawalRequest>,
+}
+
+impl StakestonePosition {
+    /// Total ETH exposure of held shares, queued withdrawals and claimable ETH
+    pub fn eth_exposure(&self) -> Decimal {
+        self.shares_eth
+            + self.withdrawable_eth
+            + self
+                .pending
+                .as_ref()
+                .map_or(Decimal::ZERO, |pending| pending.eth_amount)
+    }
+}
+
+impl ChainClient {
+    /// Reads the state of the StakeStone vault
+    pub async fn read_stakestone_vault(
+        &self,
+        vault: Address,
+        block: BlockTag,
+    ) -> Result<StakestoneVault> {
+        let calls = [STONE, CURRENT_SHARE_PRICE, LATEST_ROUND_ID]
+            .map(|selector| Call::new(vault, encode_call(selector, &[])));
+        let results = self.multicall(&calls, block).await?;
+        let [Some(stone), Some(share_price), Some(latest_round)] = results.as_slice() else {
+            return Err(anyhow!("Failed to read StakeStone vault {vault}"));
+        };
+
+        Ok(StakestoneVault {
+            address: vault,
+            stone: decode_address(stone, 0)?,
+            share_price: to_decimal(decode_uint(share_price, 0)?, WAD_DECIMALS)?,
+            latest_round: decode_uint(latest_round, 0)? as u64,
+        })
+    }
+
+    /// Reads STONE balances and withdrawal receipts of each owner
+    ///
+    /// Owners whose holdings cannot be read are skipped with a warning.
+    pub async fn read_stakestone_positions(
+        &self,
+        owners: &[Address],
+        vault: &StakestoneVault,
+        block: BlockTag,
+    ) -> Result<Vec<StakestonePosition>> {
+        let calls = owners
+            .iter()
+            .flat_map(|owner| {
+                [
+                    Call::new(
+                        vault.stone,
+                        encode_call(BALANCE_OF, &[encode_address(owner)]),
+                    ),
+                    Call::new(
+                        vault.address,
+                        encode_call(USER_RECEIPTS, &[encode_address(owner)]),
+                    ),
+                ]
+            })
+            .collect::<Vec<_>>();
+        let results = self.multicall(&calls, block).await?;
+
+        let mut holdings = Vec::new();
+        for (owner, results) in owners.iter().zip(results.chunks(2)) {
+            let [Some(balance), Some(receipt)] = results else {
+                warn!("Failed to read StakeStone holdings of {owner}");
+                continue;
+            };
+            match decode_holding(*owner, balance, receipt) {
+                Ok(holding) => holdings.push(holding),
+                Err(e) => warn!("Invalid StakeStone holdings of {owner}: {e:#}"),
+            }
+        }
+
+        // Requests from earlier rounds are settled at that round's share price
+        let settled_rounds = holdings
+            .iter()
+            .filter(|holding| {
+                !holding.withdraw_shares.is_zero() && holding.round < vault.latest_round
+            })
+            .map(|holding| holding.round)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        let round_calls = settled_rounds
+            .iter()
+            .map(|round| {
+                Call::new(
+                    vault.address,
+                    encode_call(ROUND_PRICE_PER_SHARE, &[encode_uint(*round as u128)]),
+                )
+            })
+            .collect::<Vec<_>>();
+        let round_prices = settled_rounds
+            .into_iter()
+            .zip(self.multicall(&round_calls, block).await?)
+            .map(|(round, result)| {
+                let data =
+                    result.ok_or_else(|| anyhow!("Failed to read price of round {round}"))?;
+                Ok((round, to_decimal(decode_uint(&data, 0)?, WAD_DECIMALS)?))
+            })
+            .collect::<Result<HashMap<_, _>>>()?;
+
+        Ok(holdings
+            .into_iter()
+            .map(|holding| {
+                let pending = (!holding.withdraw_shares.is_zero()).then(|| {
+                    let settled = holding.round < vault.latest_round;
+                    let price = match round_prices.get(&holding.round) {
+                        Some(price) if settled => *price,
+                        _ => vault.share_price,
+                    };
+                    WithdrawalRequest {
+                        round: holding.round,
+                        shares: holding.withdraw_shares,
+                        settled,
+                        eth_amount: holding.withdraw_shares * price,
+                    }
+                });
+                StakestonePosition {
+                    owner: holding.owner,
+                    shares: holding.shares,
+                    shares_eth: holding.shares * vault.share_price,
+                    withdrawable_eth: holding.withdrawable,
+                    pending,
+                }
+            })
+            .collect())
+    }
+}
+
+/// STONE balance and withdrawal receipt of one account
+struct Holding {
+    owner: Address,
+    shares: Decimal,
+    round: u64,
+    withdraw_shares: Decimal,
+    withdrawable: Decimal,
+}
+
+fn decode_holding(owner: Address, balance: &[u8], receipt: &[u8]) -> Result<Holding> {
+    Ok(Holding {
+        owner,
+        shares: to_decimal(decode_uint(balance, 0)?, WAD_DECIMALS)?,
+        round: decode_uint(receipt, 0)? as u64,
+        withdraw_shares: to_decimal(decode_uint(receipt, 1)?, WAD_DECIMALS)?,
+        withdrawable: to_decimal(decode_uint(receipt, 2)?, WAD_DECIMALS)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::testing::local_node;
+
+    const VAULT: Address = Address([0x50; 20]);
+    const STONE_TOKEN: Address = Address([0x51; 20]);
+    const HOLDER: Address = Address([0x01; 20]);
+    const SETTLED: Address = Address([0x02; 20]);
+    const QUEUED: Address = Address([0x03; 20]);
+    const UNREADABLE: Address = Address([0x04; 20]);
+
+    fn wad(value: u128, scale: u32) -> [u8; 32] {
+        encode_uint(value * 10u128.pow(18 - scale))
+    }
+
+    /// StakeStone vault at round 5 with a share price of 1.05 ETH
+    fn contracts(target: Address, data: &[u8]) -> Option<Vec<u8>> {
+        let (selector, args) = data.split_at(4);
+        let arg = args.get(12..32);
+        let word = match (target, selector) {
+            (VAULT, s) if s == STONE => encode_address(&STONE_TOKEN).to_vec(),
+            (VAULT, s) if s == CURRENT_SHARE_PRICE => wad(105, 2).to_vec(),
+            (VAULT, s) if s == LATEST_ROUND_ID => encode_uint(5).to_vec(),
+            (VAULT, s) if s == ROUND_PRICE_PER_SHARE => {
+                assert_eq!(decode_uint(args, 0).unwrap(), 3, "only settled rounds");
+                wad(102, 2).to_vec()
+            }
+            (VAULT, s) if s == USER_RECEIPTS => {
+                let (round, shares, withdrawable) = match arg? {
+                    a if a == SETTLED.0 => (3, 4, 1),
+                    a if a == QUEUED.0 => (5, 2, 0),
+                    _ => (0, 0, 0),
+                };
+                [encode_uint(round), wad(shares, 0), wad(withdrawable, 0)].concat()
+            }
+            (STONE_TOKEN, s) if s == BALANCE_OF => match arg? {
+                a if a == HOLDER.0 => wad(10, 0).to_vec(),
+                a if a == SETTLED.0 => wad(2, 0).to_vec(),
+                a if a == QUEUED.0 => wad(0, 0).to_vec(),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some(word)
+    }
+
+    #[tokio::test]
+    async fn reads_vault_and_positions() {
+        let client = local_node(Arc::new(contracts)).await;
+        let vault = client
+            .read_stakestone_vault(VAULT, BlockTag::Latest)
+            .await
+            .unwrap();
+        assert_eq!(vault.stone, STONE_TOKEN);
+        assert_eq!(vault.share_price, Decimal::new(105, 2));
+        assert_eq!(vault.latest_round, 5);
+
+        let positions = client
+            .read_stakestone_positions(
+                &[HOLDER, SETTLED, QUEUED, UNREADABLE],
+                &vault,
+                BlockTag::Latest,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            positions.iter().map(|p| p.owner).collect::<Vec<_>>(),
+            vec![HOLDER, SETTLED, QUEUED],
+            "unreadable owners are skipped"
+        );
+
+        let holder = &positions[0];
+        assert!(holder.pending.is_none());
+        assert_eq!(holder.eth_exposure(), Decimal::new(1050, 2));
+
+        // Settled at the price of its round, plus claimable ETH
+        let settled = &positions[1];
+        let pending = settled.pending.as_ref().unwrap();
+        assert!(pending.settled);
+        assert_eq!(pending.eth_amount, Decimal::new(408, 2));
+        assert_eq!(settled.eth_exposure(), Decimal::new(618, 2) + Decimal::ONE);
+
+        // Queued in the current round, valued at the current price
+        let queued = &positions[2];
+        let pending = queued.pending.as_ref().unwrap();
+        assert!(!pending.settled);
+        assert_eq!(queued.eth_exposure(), Decimal::new(210, 2));
+    }
+}
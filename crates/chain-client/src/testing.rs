@@ -0,0 +1,147 @@
+//! Local JSON-RPC node for tests
+
+use std::sync::Arc;
+
+use axum::{Json, Router, extract::State, routing::post};
+use reqwest::Url;
+use serde_json::{Value, json};
+
+use crate::{
+    ChainClient,
+    abi::{
+        Address, WORD, decode_address, decode_bytes, decode_uint, encode_bool, encode_uint,
+        padded_len,
+    },
+    multicall::{AGGREGATE3, MULTICALL3},
+};
+
+/// Number of the head block of the local chain
+pub const HEAD: u64 = 100;
+/// Seconds between two blocks
+pub const BLOCK_TIME: u64 = 12;
+/// Timestamp of the genesis block
+pub const GENESIS_TIME: u64 = 1_000;
+
+/// Answers a contract call with its return data, or `None` to revert
+pub type Contracts = Arc<dyn Fn(Address, &[u8]) -> Option<Vec<u8>> + Send + Sync>;
+
+/// Serves a chain of `HEAD` blocks, one every `BLOCK_TIME` seconds, and answers `eth_call`
+/// through `contracts`, including calls aggregated through Multicall3
+pub async fn local_node(contracts: Contracts) -> ChainClient {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let router = Router::new().route("/", post(handle)).with_state(contracts);
+    tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+    ChainClient::with_url(Url::parse(&url).unwrap())
+}
+
+/// Encodes the result of `aggregate3` the way Multicall3 returns it
+pub fn encode_results(results: &[Option<Vec<u8>>]) -> Vec<u8> {
+    let mut head = Vec::new();
+    let mut tail = Vec::new();
+    for result in results {
+        head.extend_from_slice(&encode_uint((results.len() * WORD + tail.len()) as u128));
+        let data = result.as_deref().unwrap_or_default();
+        tail.extend_from_slice(&encode_bool(result.is_some()));
+        tail.extend_from_slice(&encode_uint((2 * WORD) as u128));
+        tail.extend_from_slice(&encode_uint(data.len() as u128));
+        tail.extend_from_slice(data);
+        tail.resize(tail.len() + padded_len(data.len()) - data.len(), 0);
+    }
+
+    let mut data = Vec::new();
+    data.extend_from_slice(&encode_uint(WORD as u128));
+    data.extend_from_slice(&encode_uint(results.len() as u128));
+    data.extend_from_slice(&head);
+    data.extend_from_slice(&tail);
+    data
+}
+
+/// Decodes the calls of `aggregate3` calldata into targets and calldata
+pub fn decode_calls(data: &[u8]) -> Vec<(Address, Vec<u8>)> {
+    let args = &data[4..];
+    let array = &args[decode_uint(args, 0).unwrap() as usize..];
+    let len = decode_uint(array, 0).unwrap() as usize;
+    let heads = &array[WORD..];
+    (0..len)
+        .map(|index| {
+            let offset = decode_uint(heads, index).unwrap() as usize;
+            let tuple = &heads[offset..];
+            (
+                decode_address(tuple, 0).unwrap(),
+                decode_bytes(heads, offset, 2).unwrap(),
+            )
+        })
+        .collect()
+}
+
+async fn handle(State(contracts): State<Contracts>, Json(body): Json<Value>) -> Json<Value> {
+    match body {
+        Value::Array(requests) => Json(
+            requests
+                .iter()
+                .map(|request| respond(&contracts, request))
+                .collect(),
+        ),
+        request => Json(respond(&contracts, &request)),
+    }
+}
+
+fn respond(contracts: &Contracts, request: &Value) -> Value {
+    let params = &request["params"];
+    let result = match request["method"].as_str() {
+        Some("eth_blockNumber") => json!(format!("{HEAD:#x}")),
+        Some("eth_getBlockByNumber") => block(match params[0].as_str() {
+            Some("latest") => HEAD,
+            Some(number) => parse_hex(number),
+            None => panic!("missing block number"),
+        }),
+        Some("eth_getBlockByHash") => block(parse_hex(params[0].as_str().unwrap())),
+        Some("eth_call") => {
+            let to = params[0]["to"]
+                .as_str()
+                .unwrap()
+                .parse::<Address>()
+                .unwrap();
+            let data =
+                hex::decode(params[0]["data"].as_str().unwrap().trim_start_matches("0x")).unwrap();
+            let output = if to == MULTICALL3 && data.starts_with(&AGGREGATE3) {
+                let results = decode_calls(&data)
+                    .into_iter()
+                    .map(|(target, data)| contracts(target, &data))
+                    .collect::<Vec<_>>();
+                Some(encode_results(&results))
+            } else {
+                contracts(to, &data)
+            };
+            match output {
+                Some(output) => json!(format!("0x{}", hex::encode(output))),
+                None => {
+                    return json!({
+                        "jsonrpc": "2.0",
+                        "id": request["id"],
+                        "error": { "code": 3, "message": "execution reverted" },
+                    });
+                }
+            }
+        }
+        method => panic!("unexpected method {method:?}"),
+    };
+    json!({ "jsonrpc": "2.0", "id": request["id"], "result": result })
+}
+
+/// Header of a block of the local chain, `null` past the head
+fn block(number: u64) -> Value {
+    if number > HEAD {
+        return Value::Null;
+    }
+    json!({
+        "number": format!("{number:#x}"),
+        "hash": format!("0x{number:064x}"),
+        "timestamp": format!("{:#x}", GENESIS_TIME + number * BLOCK_TIME),
+    })
+}
+
+fn parse_hex(value: &str) -> u64 {
+    u64::from_str_radix(value.trim_start_matches("0x"), 16).unwrap()
+}
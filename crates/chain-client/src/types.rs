@@ -0,0 +1,119 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use crate::abi::Address;
+
+/// Block selector accepted by state-reading RPC methods
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockTag {
+    Latest,
+    Safe,
+    Finalized,
+    Number(u64),
+    /// A specific block, which must still be part of the canonical chain
+    Hash([u8; 32]),
+}
+
+impl BlockTag {
+    /// JSON-RPC parameter representation of the block selector
+    pub fn to_param(&self) -> Value {
+        match self {
+            BlockTag::Latest => Value::from("latest"),
+            BlockTag::Safe => Value::from("safe"),
+            BlockTag::Finalized => Value::from("finalized"),
+            BlockTag::Number(number) => Value::from(format!("{number:#x}")),
+            BlockTag::Hash(hash) => json!({
+                "blockHash": format!("0x{}", hex::encode(hash)),
+                "requireCanonical": true,
+            }),
+        }
+    }
+}
+
+/// Header fields of a block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Block {
+    pub number: u64,
+    pub hash: [u8; 32],
+    pub timestamp: u64,
+}
+
+impl Block {
+    /// Hex-encoded block hash with `0x` prefix
+    pub fn hash_hex(&self) -> String {
+        format!("0x{}", hex::encode(self.hash))
+    }
+}
+
+/// Block as returned by `eth_getBlockBy*`, with quantities still hex-encoded
+#[derive(Debug, Clone, Deserialize)]
+pub struct RpcBlock {
+    pub number: String,
+    pub hash: String,
+    pub timestamp: String,
+}
+
+/// Asset whose balance can be read for an account
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Asset {
+    /// The chain's native currency
+    Native,
+    /// An ERC-20 token contract
+    Erc20(Address),
+}
+
+impl Asset {
+    /// Raw currency name used for the native currency
+    pub const NATIVE_SYMBOL: &'static str = "ETH";
+}
+
+impl FromStr for Asset {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.eq_ignore_ascii_case(Self::NATIVE_SYMBOL) {
+            Ok(Asset::Native)
+        } else {
+            s.parse()
+                .map(Asset::Erc20)
+                .map_err(|e| anyhow!("Invalid asset {s}: {e}"))
+        }
+    }
+}
+
+impl fmt::Display for Asset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Asset::Native => f.write_str(Self::NATIVE_SYMBOL),
+            Asset::Erc20(address) => write!(f, "{address}"),
+        }
+    }
+}
+
+/// JSON-RPC request envelope
+#[derive(Debug, Clone, Serialize)]
+pub struct RpcRequest {
+    pub jsonrpc: &'static str,
+    pub id: u64,
+    pub method: String,
+    pub params: Value,
+}
+
+/// JSON-RPC response envelope
+#[derive(Debug, Clone, Deserialize)]
+pub struct RpcResponse {
+    pub id: u64,
+    pub result: Option<Value>,
+    pub error: Option<RpcError>,
+}
+
+/// JSON-RPC error object
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    pub data: Option<Value>,
+}
@@ -11,12 +11,25 @@ pub struct Model {
     pub wallet_id: i32,
     pub time: TimeDateTimeWithTimeZone,
     pub provider: DataProvider,
+    pub block_number: Option<i64>,
+    pub block_hash: Option<String>,
+    pub snapshot_id: Option<i32>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
     #[sea_orm(has_many = "super::balance_entry::Entity")]
     BalanceEntry,
+    #[sea_orm(has_many = "super::refresh_request::Entity")]
+    RefreshRequest,
+    #[sea_orm(
+        belongs_to = "super::snapshot::Entity",
+        from = "Column::SnapshotId",
+        to = "super::snapshot::Column::Id",
+        on_update = "Cascade",
+        on_delete = "SetNull"
+    )]
+    Snapshot,
     #[sea_orm(
         belongs_to = "super::wallet::Entity",
         from = "Column::WalletId",
@@ -33,6 +46,18 @@ impl Related<super::balance_entry::Entity> for Entity {
     }
 }
 
+impl Related<super::refresh_request::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::RefreshRequest.def()
+    }
+}
+
+impl Related<super::snapshot::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Snapshot.def()
+    }
+}
+
 impl Related<super::wallet::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::Wallet.def()
@@ -9,7 +9,7 @@ pub struct Model {
     pub id: i32,
     pub balance_id: i32,
     pub raw_currency: String,
-    #[sea_orm(column_type = "Decimal(Some((20, 8)))")]
+    #[sea_orm(column_type = "Decimal(Some((78, 18)))")]
     pub amount: Decimal,
 }
 
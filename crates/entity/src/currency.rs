@@ -13,8 +13,12 @@ pub struct Model {
 pub enum Relation {
     #[sea_orm(has_many = "super::currency_map::Entity")]
     CurrencyMap,
+    #[sea_orm(has_many = "super::price_backfill::Entity")]
+    PriceBackfill,
     #[sea_orm(has_many = "super::price_provider::Entity")]
     PriceProvider,
+    #[sea_orm(has_many = "super::refresh_request::Entity")]
+    RefreshRequest,
 }
 
 impl Related<super::currency_map::Entity> for Entity {
@@ -23,10 +27,22 @@ impl Related<super::currency_map::Entity> for Entity {
     }
 }
 
+impl Related<super::price_backfill::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::PriceBackfill.def()
+    }
+}
+
 impl Related<super::price_provider::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::PriceProvider.def()
     }
 }
 
+impl Related<super::refresh_request::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::RefreshRequest.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
@@ -7,8 +7,13 @@ pub mod balance_entry;
 pub mod balance_priority;
 pub mod currency;
 pub mod currency_map;
+pub mod job_lease;
+pub mod job_run;
 pub mod price;
+pub mod price_backfill;
 pub mod price_provider;
+pub mod refresh_request;
 pub mod sea_orm_active_enums;
+pub mod snapshot;
 pub mod wallet;
 pub mod wallet_metadata;
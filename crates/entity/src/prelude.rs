@@ -5,7 +5,12 @@ pub use super::balance_entry::Entity as BalanceEntry;
 pub use super::balance_priority::Entity as BalancePriority;
 pub use super::currency::Entity as Currency;
 pub use super::currency_map::Entity as CurrencyMap;
+pub use super::job_lease::Entity as JobLease;
+pub use super::job_run::Entity as JobRun;
 pub use super::price::Entity as Price;
+pub use super::price_backfill::Entity as PriceBackfill;
 pub use super::price_provider::Entity as PriceProvider;
+pub use super::refresh_request::Entity as RefreshRequest;
+pub use super::snapshot::Entity as Snapshot;
 pub use super::wallet::Entity as Wallet;
 pub use super::wallet_metadata::Entity as WalletMetadata;
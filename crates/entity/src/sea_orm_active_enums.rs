@@ -23,6 +23,16 @@ pub enum AssetScope {
     Other,
 }
 #[derive(Debug, Clone, PartialEq, Eq, EnumIter, DeriveActiveEnum, Copy)]
+#[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "backfill_status")]
+pub enum BackfillStatus {
+    #[sea_orm(string_value = "running")]
+    Running,
+    #[sea_orm(string_value = "complete")]
+    Complete,
+    #[sea_orm(string_value = "failed")]
+    Failed,
+}
+#[derive(Debug, Clone, PartialEq, Eq, EnumIter, DeriveActiveEnum, Copy)]
 #[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "data_provider")]
 pub enum DataProvider {
     #[sea_orm(string_value = "cam")]
@@ -31,4 +41,52 @@ pub enum DataProvider {
     Ccxt,
     #[sea_orm(string_value = "debank")]
     Debank,
+    #[sea_orm(string_value = "onchain")]
+    Onchain,
+}
+#[derive(Debug, Clone, PartialEq, Eq, EnumIter, DeriveActiveEnum, Copy)]
+#[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "job_status")]
+pub enum JobStatus {
+    #[sea_orm(string_value = "running")]
+    Running,
+    #[sea_orm(string_value = "succeeded")]
+    Succeeded,
+    #[sea_orm(string_value = "failed")]
+    Failed,
+    #[sea_orm(string_value = "interrupted")]
+    Interrupted,
+}
+#[derive(Debug, Clone, PartialEq, Eq, EnumIter, DeriveActiveEnum, Copy)]
+#[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "refresh_status")]
+pub enum RefreshStatus {
+    #[sea_orm(string_value = "pending")]
+    Pending,
+    #[sea_orm(string_value = "running")]
+    Running,
+    #[sea_orm(string_value = "succeeded")]
+    Succeeded,
+    #[sea_orm(string_value = "failed")]
+    Failed,
+}
+#[derive(Debug, Clone, PartialEq, Eq, EnumIter, DeriveActiveEnum, Copy)]
+#[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "refresh_target")]
+pub enum RefreshTarget {
+    #[sea_orm(string_value = "wallet")]
+    Wallet,
+    #[sea_orm(string_value = "price")]
+    Price,
+    #[sea_orm(string_value = "all")]
+    All,
+}
+#[derive(Debug, Clone, PartialEq, Eq, EnumIter, DeriveActiveEnum, Copy)]
+#[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "snapshot_status")]
+pub enum SnapshotStatus {
+    #[sea_orm(string_value = "running")]
+    Running,
+    #[sea_orm(string_value = "complete")]
+    Complete,
+    #[sea_orm(string_value = "partial")]
+    Partial,
+    #[sea_orm(string_value = "failed")]
+    Failed,
 }
@@ -1,6 +1,6 @@
 //! `SeaORM` Entity, @generated by sea-orm-codegen 1.1.12
 
-use super::sea_orm_active_enums::AssetScope;
+use super::sea_orm_active_enums::{AssetScope, DataProvider};
 use sea_orm::entity::prelude::*;
 
 #[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
@@ -10,6 +10,9 @@ pub struct Model {
     pub id: i32,
     pub parent_id: Option<i32>,
     pub scope: AssetScope,
+    pub provider: Option<DataProvider>,
+    pub external_id: Option<String>,
+    pub missing_since: Option<TimeDateTimeWithTimeZone>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -18,6 +21,8 @@ pub enum Relation {
     Balance,
     #[sea_orm(has_many = "super::balance_priority::Entity")]
     BalancePriority,
+    #[sea_orm(has_many = "super::refresh_request::Entity")]
+    RefreshRequest,
     #[sea_orm(
         belongs_to = "Entity",
         from = "Column::ParentId",
@@ -42,6 +47,12 @@ impl Related<super::balance_priority::Entity> for Entity {
     }
 }
 
+impl Related<super::refresh_request::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::RefreshRequest.def()
+    }
+}
+
 impl Related<super::wallet_metadata::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::WalletMetadata.def()
@@ -3,6 +3,15 @@ pub use sea_orm_migration::prelude::*;
 mod m20241201_000001_create_wallet_tables;
 mod m20241201_000002_create_currency_tables;
 mod m20241201_000003_create_balance_tables;
+mod m20241201_000004_add_balance_block_anchor;
+mod m20241201_000005_add_wallet_external_id;
+mod m20241201_000006_create_job_run_table;
+mod m20241201_000007_create_job_lease_table;
+mod m20241201_000008_create_snapshot_table;
+mod m20241201_000009_create_refresh_request_table;
+mod m20241201_000010_create_price_backfill_table;
+mod m20241201_000011_widen_balance_entry_amount;
+mod m20241201_000012_unique_price_time;
 
 pub struct Migrator;
 
@@ -13,6 +22,15 @@ impl MigratorTrait for Migrator {
             Box::new(m20241201_000001_create_wallet_tables::Migration),
             Box::new(m20241201_000002_create_currency_tables::Migration),
             Box::new(m20241201_000003_create_balance_tables::Migration),
+            Box::new(m20241201_000004_add_balance_block_anchor::Migration),
+            Box::new(m20241201_000005_add_wallet_external_id::Migration),
+            Box::new(m20241201_000006_create_job_run_table::Migration),
+            Box::new(m20241201_000007_create_job_lease_table::Migration),
+            Box::new(m20241201_000008_create_snapshot_table::Migration),
+            Box::new(m20241201_000009_create_refresh_request_table::Migration),
+            Box::new(m20241201_000010_create_price_backfill_table::Migration),
+            Box::new(m20241201_000011_widen_balance_entry_amount::Migration),
+            Box::new(m20241201_000012_unique_price_time::Migration),
         ]
     }
 }
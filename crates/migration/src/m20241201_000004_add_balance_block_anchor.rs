@@ -0,0 +1,65 @@
+use extension::postgres::Type;
+use sea_orm_migration::{prelude::*, schema::*};
+
+// Use existing enums from migration 001
+use crate::m20241201_000001_create_wallet_tables::DataProvider;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Add on-chain data provider
+        manager
+            .alter_type(
+                Type::alter()
+                    .name(DataProvider::Table)
+                    .add_value(DataProviderValue::Onchain)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+
+        // Add block anchor columns to balance table
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Balance::Table)
+                    .add_column_if_not_exists(big_integer_null(Balance::BlockNumber))
+                    .add_column_if_not_exists(string_null(Balance::BlockHash))
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Drop block anchor columns
+        // Note: Postgres cannot drop a value from an enum type, so `onchain` is kept
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Balance::Table)
+                    .drop_column(Balance::BlockHash)
+                    .drop_column(Balance::BlockNumber)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+pub enum DataProviderValue {
+    Onchain,
+}
+
+#[derive(DeriveIden)]
+pub enum Balance {
+    Table,
+    BlockNumber,
+    BlockHash,
+}
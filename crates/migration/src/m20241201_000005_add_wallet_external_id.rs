@@ -0,0 +1,79 @@
+use sea_orm::Iterable;
+use sea_orm_migration::{prelude::*, schema::*};
+
+// Use existing enums from migration 001
+use crate::m20241201_000001_create_wallet_tables::DataProvider;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Add upstream identity and disappearance flag to wallet table
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Wallet::Table)
+                    .add_column_if_not_exists(enumeration_null(
+                        Wallet::Provider,
+                        DataProvider::Table,
+                        DataProvider::iter().skip(1),
+                    ))
+                    .add_column_if_not_exists(string_null(Wallet::ExternalId))
+                    .add_column_if_not_exists(timestamp_with_time_zone_null(Wallet::MissingSince))
+                    .to_owned(),
+            )
+            .await?;
+
+        // Create unique index for upstream identity
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-wallet-provider-external_id")
+                    .table(Wallet::Table)
+                    .col(Wallet::Provider)
+                    .col(Wallet::ExternalId)
+                    .unique()
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Drop index
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx-wallet-provider-external_id")
+                    .table(Wallet::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        // Drop upstream identity columns
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Wallet::Table)
+                    .drop_column(Wallet::MissingSince)
+                    .drop_column(Wallet::ExternalId)
+                    .drop_column(Wallet::Provider)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+pub enum Wallet {
+    Table,
+    Provider,
+    ExternalId,
+    MissingSince,
+}
@@ -0,0 +1,92 @@
+use extension::postgres::Type;
+use sea_orm::{EnumIter, Iterable};
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Create job status enum
+        manager
+            .create_type(
+                Type::create()
+                    .as_enum(JobStatus::Table)
+                    .values(JobStatus::iter().skip(1))
+                    .to_owned(),
+            )
+            .await?;
+
+        // Create job run table
+        manager
+            .create_table(
+                Table::create()
+                    .table(JobRun::Table)
+                    .col(pk_auto(JobRun::Id))
+                    .col(string(JobRun::JobName))
+                    .col(timestamp_with_time_zone(JobRun::StartedAt))
+                    .col(timestamp_with_time_zone_null(JobRun::FinishedAt))
+                    .col(enumeration(
+                        JobRun::Status,
+                        JobStatus::Table,
+                        JobStatus::iter().skip(1),
+                    ))
+                    .col(integer_null(JobRun::WalletsProcessed))
+                    .col(text_null(JobRun::ErrorSummary))
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+
+        // Create index for latest runs per job
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-job_run-job_name-started_at")
+                    .table(JobRun::Table)
+                    .col(JobRun::JobName)
+                    .col(JobRun::StartedAt)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Drop table
+        manager
+            .drop_table(Table::drop().table(JobRun::Table).to_owned())
+            .await?;
+
+        // Drop enum
+        manager
+            .drop_type(Type::drop().name(JobStatus::Table).to_owned())
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+pub enum JobRun {
+    Table,
+    Id,
+    JobName,
+    StartedAt,
+    FinishedAt,
+    Status,
+    WalletsProcessed,
+    ErrorSummary,
+}
+
+#[derive(DeriveIden, EnumIter)]
+pub enum JobStatus {
+    Table,
+    Running,
+    Succeeded,
+    Failed,
+    Interrupted,
+}
@@ -0,0 +1,43 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Create job lease table, one row per job held by the replica leading it
+        manager
+            .create_table(
+                Table::create()
+                    .table(JobLease::Table)
+                    .col(string(JobLease::JobName).primary_key())
+                    .col(string(JobLease::Holder))
+                    .col(timestamp_with_time_zone(JobLease::ExpiresAt))
+                    .col(timestamp_with_time_zone_null(JobLease::LastTick))
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Drop table
+        manager
+            .drop_table(Table::drop().table(JobLease::Table).to_owned())
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+pub enum JobLease {
+    Table,
+    JobName,
+    Holder,
+    ExpiresAt,
+    LastTick,
+}
@@ -0,0 +1,136 @@
+use extension::postgres::Type;
+use sea_orm::{EnumIter, Iterable};
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Create snapshot status enum
+        manager
+            .create_type(
+                Type::create()
+                    .as_enum(SnapshotStatus::Table)
+                    .values(SnapshotStatus::iter().skip(1))
+                    .to_owned(),
+            )
+            .await?;
+
+        // Create snapshot table, one row per balance tick
+        manager
+            .create_table(
+                Table::create()
+                    .table(Snapshot::Table)
+                    .col(pk_auto(Snapshot::Id))
+                    .col(timestamp_with_time_zone(Snapshot::ScheduledTime))
+                    .col(timestamp_with_time_zone(Snapshot::StartedAt))
+                    .col(timestamp_with_time_zone_null(Snapshot::CompletedAt))
+                    .col(enumeration(
+                        Snapshot::Status,
+                        SnapshotStatus::Table,
+                        SnapshotStatus::iter().skip(1),
+                    ))
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+
+        // Create index for the latest snapshots by status
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-snapshot-status-scheduled_time")
+                    .table(Snapshot::Table)
+                    .col(Snapshot::Status)
+                    .col(Snapshot::ScheduledTime)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+
+        // Add snapshot column to balance table
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Balance::Table)
+                    .add_column_if_not_exists(integer_null(Balance::SnapshotId))
+                    .add_foreign_key(
+                        TableForeignKey::new()
+                            .name("fk-balance-snapshot_id")
+                            .from_tbl(Balance::Table)
+                            .from_col(Balance::SnapshotId)
+                            .to_tbl(Snapshot::Table)
+                            .to_col(Snapshot::Id)
+                            .on_delete(ForeignKeyAction::SetNull)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Create index for the balances of a snapshot
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-balance-snapshot_id")
+                    .table(Balance::Table)
+                    .col(Balance::SnapshotId)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Drop snapshot column, with its foreign key and index
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Balance::Table)
+                    .drop_column(Balance::SnapshotId)
+                    .to_owned(),
+            )
+            .await?;
+
+        // Drop table
+        manager
+            .drop_table(Table::drop().table(Snapshot::Table).to_owned())
+            .await?;
+
+        // Drop enum
+        manager
+            .drop_type(Type::drop().name(SnapshotStatus::Table).to_owned())
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+pub enum Snapshot {
+    Table,
+    Id,
+    ScheduledTime,
+    StartedAt,
+    CompletedAt,
+    Status,
+}
+
+#[derive(DeriveIden, EnumIter)]
+pub enum SnapshotStatus {
+    Table,
+    Running,
+    Complete,
+    Partial,
+    Failed,
+}
+
+#[derive(DeriveIden)]
+pub enum Balance {
+    Table,
+    SnapshotId,
+}
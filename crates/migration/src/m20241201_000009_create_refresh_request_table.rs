@@ -0,0 +1,164 @@
+use extension::postgres::Type;
+use sea_orm::{EnumIter, Iterable};
+use sea_orm_migration::{prelude::*, schema::*};
+
+use crate::{
+    m20241201_000001_create_wallet_tables::Wallet,
+    m20241201_000002_create_currency_tables::Currency,
+    m20241201_000003_create_balance_tables::Balance,
+    m20241201_000008_create_snapshot_table::Snapshot,
+};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Create refresh target enum
+        manager
+            .create_type(
+                Type::create()
+                    .as_enum(RefreshTarget::Table)
+                    .values(RefreshTarget::iter().skip(1))
+                    .to_owned(),
+            )
+            .await?;
+
+        // Create refresh status enum
+        manager
+            .create_type(
+                Type::create()
+                    .as_enum(RefreshStatus::Table)
+                    .values(RefreshStatus::iter().skip(1))
+                    .to_owned(),
+            )
+            .await?;
+
+        // Create refresh request table, polled by the worker as a queue
+        manager
+            .create_table(
+                Table::create()
+                    .table(RefreshRequest::Table)
+                    .col(pk_auto(RefreshRequest::Id))
+                    .col(enumeration(
+                        RefreshRequest::Target,
+                        RefreshTarget::Table,
+                        RefreshTarget::iter().skip(1),
+                    ))
+                    .col(integer_null(RefreshRequest::WalletId))
+                    .col(string_null(RefreshRequest::Currency))
+                    .col(enumeration(
+                        RefreshRequest::Status,
+                        RefreshStatus::Table,
+                        RefreshStatus::iter().skip(1),
+                    ))
+                    .col(timestamp_with_time_zone(RefreshRequest::RequestedAt))
+                    .col(timestamp_with_time_zone_null(RefreshRequest::StartedAt))
+                    .col(timestamp_with_time_zone_null(RefreshRequest::FinishedAt))
+                    .col(integer_null(RefreshRequest::BalanceId))
+                    .col(integer_null(RefreshRequest::SnapshotId))
+                    .col(text_null(RefreshRequest::Error))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-refresh_request-wallet_id")
+                            .from(RefreshRequest::Table, RefreshRequest::WalletId)
+                            .to(Wallet::Table, Wallet::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-refresh_request-currency")
+                            .from(RefreshRequest::Table, RefreshRequest::Currency)
+                            .to(Currency::Table, Currency::Name)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-refresh_request-balance_id")
+                            .from(RefreshRequest::Table, RefreshRequest::BalanceId)
+                            .to(Balance::Table, Balance::Id)
+                            .on_delete(ForeignKeyAction::SetNull)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-refresh_request-snapshot_id")
+                            .from(RefreshRequest::Table, RefreshRequest::SnapshotId)
+                            .to(Snapshot::Table, Snapshot::Id)
+                            .on_delete(ForeignKeyAction::SetNull)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+
+        // Create index for claiming the oldest open requests
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-refresh_request-status-requested_at")
+                    .table(RefreshRequest::Table)
+                    .col(RefreshRequest::Status)
+                    .col(RefreshRequest::RequestedAt)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Drop table
+        manager
+            .drop_table(Table::drop().table(RefreshRequest::Table).to_owned())
+            .await?;
+
+        // Drop enums
+        manager
+            .drop_type(Type::drop().name(RefreshStatus::Table).to_owned())
+            .await?;
+        manager
+            .drop_type(Type::drop().name(RefreshTarget::Table).to_owned())
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+pub enum RefreshRequest {
+    Table,
+    Id,
+    Target,
+    WalletId,
+    Currency,
+    Status,
+    RequestedAt,
+    StartedAt,
+    FinishedAt,
+    BalanceId,
+    SnapshotId,
+    Error,
+}
+
+#[derive(DeriveIden, EnumIter)]
+pub enum RefreshTarget {
+    Table,
+    Wallet,
+    Price,
+    All,
+}
+
+#[derive(DeriveIden, EnumIter)]
+pub enum RefreshStatus {
+    Table,
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+}
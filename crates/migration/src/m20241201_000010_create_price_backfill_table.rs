@@ -0,0 +1,148 @@
+use extension::postgres::Type;
+use sea_orm::{EnumIter, Iterable};
+use sea_orm_migration::{prelude::*, schema::*};
+
+use crate::{
+    m20241201_000001_create_wallet_tables::DataProvider,
+    m20241201_000002_create_currency_tables::{Currency, Price},
+};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Create backfill status enum
+        manager
+            .create_type(
+                Type::create()
+                    .as_enum(BackfillStatus::Table)
+                    .values(BackfillStatus::iter().skip(1))
+                    .to_owned(),
+            )
+            .await?;
+
+        // Create price backfill table, tracking the progress of each backfill to resume it
+        manager
+            .create_table(
+                Table::create()
+                    .table(PriceBackfill::Table)
+                    .col(pk_auto(PriceBackfill::Id))
+                    .col(string(PriceBackfill::Currency))
+                    .col(enumeration(
+                        PriceBackfill::Provider,
+                        DataProvider::Table,
+                        DataProvider::iter().skip(1),
+                    ))
+                    .col(timestamp_with_time_zone(PriceBackfill::StartTime))
+                    .col(timestamp_with_time_zone(PriceBackfill::EndTime))
+                    .col(big_integer(PriceBackfill::GranularitySecs))
+                    .col(timestamp_with_time_zone_null(PriceBackfill::Cursor))
+                    .col(enumeration(
+                        PriceBackfill::Status,
+                        BackfillStatus::Table,
+                        BackfillStatus::iter().skip(1),
+                    ))
+                    .col(integer(PriceBackfill::Inserted).default(0))
+                    .col(integer(PriceBackfill::Skipped).default(0))
+                    .col(text_null(PriceBackfill::Error))
+                    .col(timestamp_with_time_zone(PriceBackfill::CreatedAt))
+                    .col(timestamp_with_time_zone(PriceBackfill::UpdatedAt))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-price_backfill-currency")
+                            .from(PriceBackfill::Table, PriceBackfill::Currency)
+                            .to(Currency::Table, Currency::Name)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+
+        // Create unique index so that the same backfill resumes rather than starting over
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-price_backfill-range")
+                    .table(PriceBackfill::Table)
+                    .col(PriceBackfill::Currency)
+                    .col(PriceBackfill::Provider)
+                    .col(PriceBackfill::StartTime)
+                    .col(PriceBackfill::EndTime)
+                    .col(PriceBackfill::GranularitySecs)
+                    .unique()
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+
+        // Create index for the price times of a currency and provider
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-price-currency-provider-time")
+                    .table(Price::Table)
+                    .col(Price::Currency)
+                    .col(Price::Provider)
+                    .col(Price::Time)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Drop price index
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx-price-currency-provider-time")
+                    .table(Price::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        // Drop table
+        manager
+            .drop_table(Table::drop().table(PriceBackfill::Table).to_owned())
+            .await?;
+
+        // Drop enum
+        manager
+            .drop_type(Type::drop().name(BackfillStatus::Table).to_owned())
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+pub enum PriceBackfill {
+    Table,
+    Id,
+    Currency,
+    Provider,
+    StartTime,
+    EndTime,
+    GranularitySecs,
+    Cursor,
+    Status,
+    Inserted,
+    Skipped,
+    Error,
+    CreatedAt,
+    UpdatedAt,
+}
+
+#[derive(DeriveIden, EnumIter)]
+pub enum BackfillStatus {
+    Table,
+    Running,
+    Complete,
+    Failed,
+}
@@ -0,0 +1,41 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Widen amounts to hold any uint256 token amount with 18 decimals, as read on-chain
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(BalanceEntry::Table)
+                    .modify_column(decimal_len(BalanceEntry::Amount, 78, 18))
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Note: amounts are rounded to 8 decimals, and fail to convert if too large
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(BalanceEntry::Table)
+                    .modify_column(decimal_len(BalanceEntry::Amount, 20, 8))
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+pub enum BalanceEntry {
+    Table,
+    Amount,
+}
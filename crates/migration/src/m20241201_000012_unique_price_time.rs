@@ -0,0 +1,76 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Keep only the first price of each currency, provider and time
+        manager
+            .get_connection()
+            .execute_unprepared(
+                "DELETE FROM price a USING price b \
+                 WHERE a.currency = b.currency AND a.provider = b.provider \
+                 AND a.time = b.time AND a.id > b.id",
+            )
+            .await?;
+
+        // Replace the price time index with a unique one so overlapping inserts are skipped
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx-price-currency-provider-time")
+                    .table(Price::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-price-currency-provider-time")
+                    .table(Price::Table)
+                    .col(Price::Currency)
+                    .col(Price::Provider)
+                    .col(Price::Time)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Restore the non-unique price time index
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx-price-currency-provider-time")
+                    .table(Price::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-price-currency-provider-time")
+                    .table(Price::Table)
+                    .col(Price::Currency)
+                    .col(Price::Provider)
+                    .col(Price::Time)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+pub enum Price {
+    Table,
+    Currency,
+    Provider,
+    Time,
+}
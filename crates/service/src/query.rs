@@ -1,8 +1,14 @@
-use sea_orm::DatabaseConnection;
+use sea_orm::{DatabaseConnection, DbErr};
 
 mod balance;
 mod currency;
+mod job_lease;
+mod job_run;
 mod price;
+mod price_backfill;
+mod refresh_request;
+mod snapshot;
+mod valuation;
 mod wallet;
 
 #[derive(Clone)]
@@ -14,4 +20,9 @@ impl QueryService {
     pub fn new(db: DatabaseConnection) -> Self {
         Self { db }
     }
+
+    /// Check that the database is reachable
+    pub async fn ping(&self) -> Result<(), DbErr> {
+        self.db.ping().await
+    }
 }
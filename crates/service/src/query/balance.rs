@@ -1,6 +1,15 @@
-use crate::types::{NewBalance, NewBalanceEntry, NewBalancePriority};
-use hammer_entity::{balance, balance_entry, balance_priority};
-use sea_orm::{Order, QueryOrder, Set, TransactionTrait, entity::prelude::*};
+use crate::types::{DataProvider, NewBalance, NewBalanceEntry, NewBalancePriority};
+use hammer_entity::{
+    balance, balance_entry, balance_priority,
+    sea_orm_active_enums::{AssetScope, DataProvider as EntityDataProvider},
+    wallet,
+};
+use sea_orm::{
+    DatabaseTransaction, JoinType, Order, QueryOrder, QuerySelect, RelationTrait, Set,
+    TransactionTrait,
+    entity::prelude::*,
+    sea_query::{Expr, IntoCondition},
+};
 
 use super::QueryService;
 
@@ -21,6 +30,95 @@ impl QueryService {
             .await
     }
 
+    /// Get the times of a wallet's balances from a provider within a time range
+    pub async fn get_balance_times(
+        &self,
+        wallet_id: i32,
+        provider: DataProvider,
+        start_time: time::OffsetDateTime,
+        end_time: time::OffsetDateTime,
+    ) -> Result<Vec<time::OffsetDateTime>, DbErr> {
+        balance::Entity::find()
+            .select_only()
+            .column(balance::Column::Time)
+            .filter(balance::Column::WalletId.eq(wallet_id))
+            .filter(balance::Column::Provider.eq(EntityDataProvider::from(provider)))
+            .filter(balance::Column::Time.gte(start_time))
+            .filter(balance::Column::Time.lte(end_time))
+            .order_by(balance::Column::Time, Order::Asc)
+            .into_tuple()
+            .all(&self.db)
+            .await
+    }
+
+    /// Get the time of the newest balance of every wallet
+    pub async fn get_latest_balance_times(
+        &self,
+    ) -> Result<Vec<(i32, time::OffsetDateTime)>, DbErr> {
+        balance::Entity::find()
+            .select_only()
+            .column(balance::Column::WalletId)
+            .column_as(balance::Column::Time.max(), "time")
+            .group_by(balance::Column::WalletId)
+            .into_tuple()
+            .all(&self.db)
+            .await
+    }
+
+    /// Get the time of the newest balance of every wallet from each of its prioritised providers
+    ///
+    /// The time is `None` when the provider never returned the wallet.
+    pub async fn get_latest_balance_times_by_provider(
+        &self,
+    ) -> Result<Vec<(i32, DataProvider, Option<time::OffsetDateTime>)>, DbErr> {
+        let rows: Vec<(i32, EntityDataProvider, Option<time::OffsetDateTime>)> =
+            balance_priority::Entity::find()
+                .select_only()
+                .column(balance_priority::Column::WalletId)
+                .column(balance_priority::Column::Provider)
+                .column_as(balance::Column::Time.max(), "time")
+                .join(
+                    JoinType::LeftJoin,
+                    balance_priority::Entity::belongs_to(balance::Entity)
+                        .from(balance_priority::Column::WalletId)
+                        .to(balance::Column::WalletId)
+                        .on_condition(|left, right| {
+                            Expr::col((left, balance_priority::Column::Provider))
+                                .equals((right, balance::Column::Provider))
+                                .into_condition()
+                        })
+                        .into(),
+                )
+                .group_by(balance_priority::Column::WalletId)
+                .group_by(balance_priority::Column::Provider)
+                .order_by(balance_priority::Column::WalletId, Order::Asc)
+                .into_tuple()
+                .all(&self.db)
+                .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(wallet_id, provider, time)| (wallet_id, provider.into(), time))
+            .collect())
+    }
+
+    /// Get the distinct raw currencies held since a time, with the scope of the wallet holding them
+    pub async fn get_raw_currencies_since(
+        &self,
+        since: time::OffsetDateTime,
+    ) -> Result<Vec<(AssetScope, String)>, DbErr> {
+        balance_entry::Entity::find()
+            .select_only()
+            .column(wallet::Column::Scope)
+            .column(balance_entry::Column::RawCurrency)
+            .join(JoinType::InnerJoin, balance_entry::Relation::Balance.def())
+            .join(JoinType::InnerJoin, balance::Relation::Wallet.def())
+            .filter(balance::Column::Time.gte(since))
+            .distinct()
+            .into_tuple()
+            .all(&self.db)
+            .await
+    }
+
     /// Get balance with entries
     pub async fn get_balance_with_entries(
         &self,
@@ -41,15 +139,7 @@ impl QueryService {
     ) -> Result<balance::Model, DbErr> {
         let tx = self.db.begin().await?;
 
-        // Create balance
-        let balance = balance::ActiveModel {
-            wallet_id: Set(new_balance.wallet_id),
-            time: Set(new_balance.time),
-            provider: Set(new_balance.provider.into()),
-            ..Default::default()
-        };
-
-        let balance = match balance.insert(&tx).await {
+        let balance = match insert_balance_with_entries(&tx, new_balance, entries).await {
             Ok(balance) => balance,
             Err(e) => {
                 tx.rollback().await?;
@@ -57,31 +147,47 @@ impl QueryService {
             }
         };
 
-        // Create balance entries
-        if !entries.is_empty() {
-            let balance_entries = entries
-                .into_iter()
-                .map(|entry| balance_entry::ActiveModel {
-                    balance_id: Set(balance.id),
-                    raw_currency: Set(entry.raw_currency),
-                    amount: Set(entry.amount),
-                    ..Default::default()
-                })
-                .collect::<Vec<_>>();
-
-            if let Err(e) = balance_entry::Entity::insert_many(balance_entries)
-                .exec_without_returning(&tx)
-                .await
-            {
+        tx.commit().await?;
+        Ok(balance)
+    }
+
+    /// Replace the reading of a balance and its entries, e.g. after a chain reorg
+    ///
+    /// The balance is updated in place, so it keeps its ID and rows referencing it stay valid.
+    pub async fn replace_balance_with_entries(
+        &self,
+        id: i32,
+        new_balance: NewBalance,
+        entries: Vec<NewBalanceEntry>,
+    ) -> Result<balance::Model, DbErr> {
+        let tx = self.db.begin().await?;
+
+        let balance = match replace_balance_entries(&tx, id, new_balance, entries).await {
+            Ok(balance) => balance,
+            Err(e) => {
                 tx.rollback().await?;
                 return Err(e);
             }
-        }
+        };
 
         tx.commit().await?;
         Ok(balance)
     }
 
+    /// Get block-anchored balances of a provider read after the given block
+    pub async fn get_balances_after_block(
+        &self,
+        provider: DataProvider,
+        block_number: i64,
+    ) -> Result<Vec<balance::Model>, DbErr> {
+        balance::Entity::find()
+            .filter(balance::Column::Provider.eq(EntityDataProvider::from(provider)))
+            .filter(balance::Column::BlockNumber.gt(block_number))
+            .order_by(balance::Column::BlockNumber, Order::Asc)
+            .all(&self.db)
+            .await
+    }
+
     /// Update balance
     pub async fn update_balance(
         &self,
@@ -93,6 +199,9 @@ impl QueryService {
             wallet_id: Set(new_balance.wallet_id),
             time: Set(new_balance.time),
             provider: Set(new_balance.provider.into()),
+            block_number: Set(new_balance.block_number),
+            block_hash: Set(new_balance.block_hash),
+            snapshot_id: Set(new_balance.snapshot_id),
         };
         balance.update(&self.db).await
     }
@@ -200,3 +309,80 @@ impl QueryService {
         Ok(result.rows_affected == 1)
     }
 }
+
+/// Insert a balance and its entries within an open transaction
+async fn insert_balance_with_entries(
+    tx: &DatabaseTransaction,
+    new_balance: NewBalance,
+    entries: Vec<NewBalanceEntry>,
+) -> Result<balance::Model, DbErr> {
+    // Create balance
+    let balance = balance::ActiveModel {
+        wallet_id: Set(new_balance.wallet_id),
+        time: Set(new_balance.time),
+        provider: Set(new_balance.provider.into()),
+        block_number: Set(new_balance.block_number),
+        block_hash: Set(new_balance.block_hash),
+        snapshot_id: Set(new_balance.snapshot_id),
+        ..Default::default()
+    }
+    .insert(tx)
+    .await?;
+
+    insert_balance_entries(tx, balance.id, entries).await?;
+
+    Ok(balance)
+}
+
+/// Update a balance and replace its entries within an open transaction
+async fn replace_balance_entries(
+    tx: &DatabaseTransaction,
+    id: i32,
+    new_balance: NewBalance,
+    entries: Vec<NewBalanceEntry>,
+) -> Result<balance::Model, DbErr> {
+    let balance = balance::ActiveModel {
+        id: Set(id),
+        wallet_id: Set(new_balance.wallet_id),
+        time: Set(new_balance.time),
+        provider: Set(new_balance.provider.into()),
+        block_number: Set(new_balance.block_number),
+        block_hash: Set(new_balance.block_hash),
+        snapshot_id: Set(new_balance.snapshot_id),
+    }
+    .update(tx)
+    .await?;
+
+    balance_entry::Entity::delete_many()
+        .filter(balance_entry::Column::BalanceId.eq(id))
+        .exec(tx)
+        .await?;
+    insert_balance_entries(tx, id, entries).await?;
+
+    Ok(balance)
+}
+
+/// Insert the entries of a balance within an open transaction
+async fn insert_balance_entries(
+    tx: &DatabaseTransaction,
+    balance_id: i32,
+    entries: Vec<NewBalanceEntry>,
+) -> Result<(), DbErr> {
+    if entries.is_empty() {
+        return Ok(());
+    }
+    let balance_entries = entries
+        .into_iter()
+        .map(|entry| balance_entry::ActiveModel {
+            balance_id: Set(balance_id),
+            raw_currency: Set(entry.raw_currency),
+            amount: Set(entry.amount),
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
+
+    balance_entry::Entity::insert_many(balance_entries)
+        .exec_without_returning(tx)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use hammer_entity::job_lease;
+use sea_orm::{ConnectionTrait, DbBackend, Statement, entity::prelude::*};
+
+use super::QueryService;
+
+impl QueryService {
+    /// Get the lease of a job
+    pub async fn get_job_lease(&self, job_name: &str) -> Result<Option<job_lease::Model>, DbErr> {
+        job_lease::Entity::find_by_id(job_name).one(&self.db).await
+    }
+
+    /// Acquire or renew the lease of a job for one tick
+    ///
+    /// Succeeds when the lease is free, expired or already held by `holder`, and no replica
+    /// has run the tick yet. Expiry is measured on the database clock.
+    pub async fn acquire_job_lease(
+        &self,
+        job_name: &str,
+        holder: &str,
+        tick: time::OffsetDateTime,
+        ttl: std::time::Duration,
+    ) -> Result<bool, DbErr> {
+        let acquired = self
+            .db
+            .query_one(Statement::from_sql_and_values(
+                DbBackend::Postgres,
+                r#"
+                INSERT INTO job_lease (job_name, holder, expires_at, last_tick)
+                VALUES ($1, $2, now() + make_interval(secs => $3), $4)
+                ON CONFLICT (job_name) DO UPDATE
+                SET holder = EXCLUDED.holder,
+                    expires_at = EXCLUDED.expires_at,
+                    last_tick = EXCLUDED.last_tick
+                WHERE (job_lease.expires_at < now() OR job_lease.holder = EXCLUDED.holder)
+                  AND (job_lease.last_tick IS NULL OR job_lease.last_tick < EXCLUDED.last_tick)
+                RETURNING job_name
+                "#,
+                [
+                    job_name.into(),
+                    holder.into(),
+                    ttl.as_secs_f64().into(),
+                    tick.into(),
+                ],
+            ))
+            .await?;
+        Ok(acquired.is_some())
+    }
+
+    /// Extend a lease still held by `holder`, returning whether it was held
+    pub async fn renew_job_lease(
+        &self,
+        job_name: &str,
+        holder: &str,
+        ttl: std::time::Duration,
+    ) -> Result<bool, DbErr> {
+        let result = self
+            .db
+            .execute(Statement::from_sql_and_values(
+                DbBackend::Postgres,
+                r#"
+                UPDATE job_lease
+                SET expires_at = now() + make_interval(secs => $3)
+                WHERE job_name = $1 AND holder = $2 AND expires_at >= now()
+                "#,
+                [job_name.into(), holder.into(), ttl.as_secs_f64().into()],
+            ))
+            .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Expire every lease held by `holder` so another replica can take over immediately
+    pub async fn release_job_leases(&self, holder: &str) -> Result<u64, DbErr> {
+        let result = self
+            .db
+            .execute(Statement::from_sql_and_values(
+                DbBackend::Postgres,
+                "UPDATE job_lease SET expires_at = now() WHERE holder = $1",
+                [holder.into()],
+            ))
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
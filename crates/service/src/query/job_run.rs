@@ -0,0 +1,98 @@
+use crate::types::{FinishedJobRun, JobStatus};
+use hammer_entity::{job_run, sea_orm_active_enums::JobStatus as EntityJobStatus};
+use sea_orm::{
+    ActiveEnum, Order, QueryOrder, QuerySelect, Set, entity::prelude::*, sea_query::Expr,
+};
+
+use super::QueryService;
+
+impl QueryService {
+    /// Get job run by ID
+    pub async fn get_job_run_by_id(&self, id: i32) -> Result<Option<job_run::Model>, DbErr> {
+        job_run::Entity::find_by_id(id).one(&self.db).await
+    }
+
+    /// Get the most recent runs of a job
+    pub async fn get_job_runs(
+        &self,
+        job_name: &str,
+        limit: u64,
+    ) -> Result<Vec<job_run::Model>, DbErr> {
+        job_run::Entity::find()
+            .filter(job_run::Column::JobName.eq(job_name))
+            .order_by(job_run::Column::StartedAt, Order::Desc)
+            .limit(limit)
+            .all(&self.db)
+            .await
+    }
+
+    /// Get the last successful run of a job
+    pub async fn get_last_successful_job_run(
+        &self,
+        job_name: &str,
+    ) -> Result<Option<job_run::Model>, DbErr> {
+        job_run::Entity::find()
+            .filter(job_run::Column::JobName.eq(job_name))
+            .filter(job_run::Column::Status.eq(EntityJobStatus::Succeeded))
+            .order_by(job_run::Column::StartedAt, Order::Desc)
+            .one(&self.db)
+            .await
+    }
+
+    /// Get the last successful run of every job
+    pub async fn get_last_successful_job_runs(&self) -> Result<Vec<job_run::Model>, DbErr> {
+        job_run::Entity::find()
+            .distinct_on([job_run::Column::JobName])
+            .filter(job_run::Column::Status.eq(EntityJobStatus::Succeeded))
+            .order_by(job_run::Column::JobName, Order::Asc)
+            .order_by(job_run::Column::StartedAt, Order::Desc)
+            .all(&self.db)
+            .await
+    }
+
+    /// Record the start of a job run
+    pub async fn start_job_run(&self, job_name: &str) -> Result<job_run::Model, DbErr> {
+        let run = job_run::ActiveModel {
+            job_name: Set(job_name.to_owned()),
+            started_at: Set(time::OffsetDateTime::now_utc()),
+            status: Set(EntityJobStatus::Running),
+            ..Default::default()
+        };
+        run.insert(&self.db).await
+    }
+
+    /// Record the end of a job run
+    pub async fn finish_job_run(
+        &self,
+        id: i32,
+        finished: FinishedJobRun,
+    ) -> Result<job_run::Model, DbErr> {
+        let run = job_run::ActiveModel {
+            id: Set(id),
+            finished_at: Set(Some(time::OffsetDateTime::now_utc())),
+            status: Set(finished.status.into()),
+            wallets_processed: Set(finished.wallets_processed),
+            error_summary: Set(finished.error_summary),
+            ..Default::default()
+        };
+        run.update(&self.db).await
+    }
+
+    /// Mark a job run as interrupted if it is still recorded as running, e.g. after a shutdown
+    pub async fn interrupt_job_run(&self, id: i32) -> Result<u64, DbErr> {
+        let result = job_run::Entity::update_many()
+            .col_expr(
+                job_run::Column::Status,
+                EntityJobStatus::from(JobStatus::Interrupted).as_enum(),
+            )
+            .col_expr(
+                job_run::Column::FinishedAt,
+                Expr::value(time::OffsetDateTime::now_utc()),
+            )
+            .filter(job_run::Column::Id.eq(id))
+            .filter(job_run::Column::Status.eq(EntityJobStatus::Running))
+            .exec(&self.db)
+            .await?;
+        Ok(result.rows_affected)
+    }
+}
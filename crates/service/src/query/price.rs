@@ -1,6 +1,10 @@
-use crate::types::{NewPrice, NewPricePriority};
-use hammer_entity::{price, price_provider};
-use sea_orm::{Order, QueryOrder, Set, entity::prelude::*};
+use crate::types::{DataProvider, NewPrice, NewPricePriority};
+use hammer_entity::{
+    price, price_provider, sea_orm_active_enums::DataProvider as EntityDataProvider,
+};
+use sea_orm::{
+    JoinType, Order, QueryOrder, QuerySelect, Set, entity::prelude::*, sea_query::OnConflict,
+};
 
 use super::QueryService;
 
@@ -28,6 +32,65 @@ impl QueryService {
             .await
     }
 
+    /// Get the time of the newest price of every currency
+    pub async fn get_latest_price_times(
+        &self,
+    ) -> Result<Vec<(String, time::OffsetDateTime)>, DbErr> {
+        price::Entity::find()
+            .select_only()
+            .column(price::Column::Currency)
+            .column_as(price::Column::Time.max(), "time")
+            .group_by(price::Column::Currency)
+            .into_tuple()
+            .all(&self.db)
+            .await
+    }
+
+    /// Get the time of the newest price of every currency with a price provider
+    ///
+    /// The time is `None` when the currency was never priced.
+    pub async fn get_latest_price_times_by_priced_currency(
+        &self,
+    ) -> Result<Vec<(String, Option<time::OffsetDateTime>)>, DbErr> {
+        price_provider::Entity::find()
+            .select_only()
+            .column(price_provider::Column::Currency)
+            .column_as(price::Column::Time.max(), "time")
+            .join(
+                JoinType::LeftJoin,
+                price_provider::Entity::belongs_to(price::Entity)
+                    .from(price_provider::Column::Currency)
+                    .to(price::Column::Currency)
+                    .into(),
+            )
+            .group_by(price_provider::Column::Currency)
+            .order_by(price_provider::Column::Currency, Order::Asc)
+            .into_tuple()
+            .all(&self.db)
+            .await
+    }
+
+    /// Get the times of a currency's prices from a provider within a time range
+    pub async fn get_price_times(
+        &self,
+        currency: &str,
+        provider: DataProvider,
+        start_time: time::OffsetDateTime,
+        end_time: time::OffsetDateTime,
+    ) -> Result<Vec<time::OffsetDateTime>, DbErr> {
+        price::Entity::find()
+            .select_only()
+            .column(price::Column::Time)
+            .filter(price::Column::Currency.eq(currency))
+            .filter(price::Column::Provider.eq(EntityDataProvider::from(provider)))
+            .filter(price::Column::Time.gte(start_time))
+            .filter(price::Column::Time.lte(end_time))
+            .order_by(price::Column::Time, Order::Asc)
+            .into_tuple()
+            .all(&self.db)
+            .await
+    }
+
     /// Get prices by currency and time range
     pub async fn get_prices_by_currency_and_time_range(
         &self,
@@ -57,6 +120,35 @@ impl QueryService {
         price.insert(&self.db).await
     }
 
+    /// Create several prices in one statement, skipping prices already stored
+    ///
+    /// Returns the number of prices inserted.
+    pub async fn create_prices(&self, new_prices: Vec<NewPrice>) -> Result<u64, DbErr> {
+        if new_prices.is_empty() {
+            return Ok(0);
+        }
+        let prices = new_prices.into_iter().map(|new_price| price::ActiveModel {
+            currency: Set(new_price.currency),
+            time: Set(new_price.time),
+            value: Set(new_price.value),
+            liquidity: Set(new_price.liquidity),
+            provider: Set(new_price.provider.into()),
+            ..Default::default()
+        });
+        price::Entity::insert_many(prices)
+            .on_conflict(
+                OnConflict::columns([
+                    price::Column::Currency,
+                    price::Column::Provider,
+                    price::Column::Time,
+                ])
+                .do_nothing()
+                .to_owned(),
+            )
+            .exec_without_returning(&self.db)
+            .await
+    }
+
     /// Update price
     pub async fn update_price(&self, id: i32, new_price: NewPrice) -> Result<price::Model, DbErr> {
         let price = price::ActiveModel {
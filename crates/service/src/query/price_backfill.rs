@@ -0,0 +1,122 @@
+use crate::types::{BackfillStatus, NewPriceBackfill, PriceBackfillProgress};
+use hammer_entity::{
+    price_backfill,
+    sea_orm_active_enums::{
+        BackfillStatus as EntityBackfillStatus, DataProvider as EntityDataProvider,
+    },
+};
+use sea_orm::{Order, QueryOrder, Set, entity::prelude::*};
+
+use super::QueryService;
+
+impl QueryService {
+    /// Get price backfill by ID
+    pub async fn get_price_backfill_by_id(
+        &self,
+        id: i32,
+    ) -> Result<Option<price_backfill::Model>, DbErr> {
+        price_backfill::Entity::find_by_id(id).one(&self.db).await
+    }
+
+    /// Get the backfills of a currency, newest first
+    pub async fn get_price_backfills_by_currency(
+        &self,
+        currency: &str,
+    ) -> Result<Vec<price_backfill::Model>, DbErr> {
+        price_backfill::Entity::find()
+            .filter(price_backfill::Column::Currency.eq(currency))
+            .order_by(price_backfill::Column::CreatedAt, Order::Desc)
+            .all(&self.db)
+            .await
+    }
+
+    /// Get the backfill of the same currency, provider, range and granularity
+    pub async fn find_price_backfill(
+        &self,
+        backfill: &NewPriceBackfill,
+    ) -> Result<Option<price_backfill::Model>, DbErr> {
+        price_backfill::Entity::find()
+            .filter(price_backfill::Column::Currency.eq(&backfill.currency))
+            .filter(
+                price_backfill::Column::Provider.eq(EntityDataProvider::from(backfill.provider)),
+            )
+            .filter(price_backfill::Column::StartTime.eq(backfill.start_time))
+            .filter(price_backfill::Column::EndTime.eq(backfill.end_time))
+            .filter(price_backfill::Column::GranularitySecs.eq(backfill.granularity_secs))
+            .one(&self.db)
+            .await
+    }
+
+    /// Start a backfill, or resume the same one from its cursor
+    pub async fn start_price_backfill(
+        &self,
+        backfill: NewPriceBackfill,
+    ) -> Result<price_backfill::Model, DbErr> {
+        let now = time::OffsetDateTime::now_utc();
+        if let Some(existing) = self.find_price_backfill(&backfill).await? {
+            // A complete backfill is left as is
+            if existing.status == EntityBackfillStatus::Complete {
+                return Ok(existing);
+            }
+            let resumed = price_backfill::ActiveModel {
+                id: Set(existing.id),
+                status: Set(EntityBackfillStatus::Running),
+                error: Set(None),
+                updated_at: Set(now),
+                ..Default::default()
+            };
+            return resumed.update(&self.db).await;
+        }
+
+        let created = price_backfill::ActiveModel {
+            currency: Set(backfill.currency),
+            provider: Set(backfill.provider.into()),
+            start_time: Set(backfill.start_time),
+            end_time: Set(backfill.end_time),
+            granularity_secs: Set(backfill.granularity_secs),
+            cursor: Set(None),
+            status: Set(EntityBackfillStatus::Running),
+            inserted: Set(0),
+            skipped: Set(0),
+            error: Set(None),
+            created_at: Set(now),
+            updated_at: Set(now),
+            ..Default::default()
+        };
+        created.insert(&self.db).await
+    }
+
+    /// Record the progress of a backfill
+    pub async fn update_price_backfill_progress(
+        &self,
+        id: i32,
+        progress: PriceBackfillProgress,
+    ) -> Result<price_backfill::Model, DbErr> {
+        let backfill = price_backfill::ActiveModel {
+            id: Set(id),
+            cursor: Set(Some(progress.cursor)),
+            inserted: Set(progress.inserted),
+            skipped: Set(progress.skipped),
+            updated_at: Set(time::OffsetDateTime::now_utc()),
+            ..Default::default()
+        };
+        backfill.update(&self.db).await
+    }
+
+    /// Record the end of a backfill
+    pub async fn finish_price_backfill(
+        &self,
+        id: i32,
+        status: BackfillStatus,
+        error: Option<String>,
+    ) -> Result<price_backfill::Model, DbErr> {
+        let backfill = price_backfill::ActiveModel {
+            id: Set(id),
+            status: Set(status.into()),
+            error: Set(error),
+            updated_at: Set(time::OffsetDateTime::now_utc()),
+            ..Default::default()
+        };
+        backfill.update(&self.db).await
+    }
+}
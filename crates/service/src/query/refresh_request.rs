@@ -0,0 +1,132 @@
+use crate::types::{FinishedRefresh, RefreshTarget};
+use hammer_entity::{
+    refresh_request,
+    sea_orm_active_enums::{
+        RefreshStatus as EntityRefreshStatus, RefreshTarget as EntityRefreshTarget,
+    },
+};
+use sea_orm::{
+    ActiveEnum, Condition, Order, QueryOrder, Set,
+    entity::prelude::*,
+    sea_query::{Expr, LockBehavior, LockType, Query},
+};
+
+use super::QueryService;
+
+impl QueryService {
+    /// Get refresh request by ID, e.g. to follow its status
+    pub async fn get_refresh_request_by_id(
+        &self,
+        id: i32,
+    ) -> Result<Option<refresh_request::Model>, DbErr> {
+        refresh_request::Entity::find_by_id(id).one(&self.db).await
+    }
+
+    /// Get the pending and running refresh requests, oldest first
+    pub async fn get_open_refresh_requests(&self) -> Result<Vec<refresh_request::Model>, DbErr> {
+        refresh_request::Entity::find()
+            .filter(
+                refresh_request::Column::Status
+                    .is_in([EntityRefreshStatus::Pending, EntityRefreshStatus::Running]),
+            )
+            .order_by(refresh_request::Column::RequestedAt, Order::Asc)
+            .order_by(refresh_request::Column::Id, Order::Asc)
+            .all(&self.db)
+            .await
+    }
+
+    /// Queue a refresh request for the worker
+    pub async fn create_refresh_request(
+        &self,
+        target: RefreshTarget,
+    ) -> Result<refresh_request::Model, DbErr> {
+        let (kind, wallet_id, currency) = match target {
+            RefreshTarget::Wallet(wallet_id) => {
+                (EntityRefreshTarget::Wallet, Some(wallet_id), None)
+            }
+            RefreshTarget::Price(currency) => (EntityRefreshTarget::Price, None, Some(currency)),
+            RefreshTarget::All => (EntityRefreshTarget::All, None, None),
+        };
+        let request = refresh_request::ActiveModel {
+            target: Set(kind),
+            wallet_id: Set(wallet_id),
+            currency: Set(currency),
+            status: Set(EntityRefreshStatus::Pending),
+            requested_at: Set(time::OffsetDateTime::now_utc()),
+            ..Default::default()
+        };
+        request.insert(&self.db).await
+    }
+
+    /// Claim the oldest pending refresh request and mark it running
+    ///
+    /// Requests locked by another worker are skipped, so concurrent workers never claim the
+    /// same one. A request whose claim was not extended with
+    /// [`QueryService::touch_refresh_request`] for longer than `stale_after`, e.g. by a worker
+    /// that was killed, is claimed again.
+    pub async fn claim_refresh_request(
+        &self,
+        stale_after: std::time::Duration,
+    ) -> Result<Option<refresh_request::Model>, DbErr> {
+        let now = time::OffsetDateTime::now_utc();
+        let oldest_open = Query::select()
+            .column(refresh_request::Column::Id)
+            .from(refresh_request::Entity)
+            .cond_where(
+                Condition::any()
+                    .add(refresh_request::Column::Status.eq(EntityRefreshStatus::Pending))
+                    .add(
+                        Condition::all()
+                            .add(refresh_request::Column::Status.eq(EntityRefreshStatus::Running))
+                            .add(refresh_request::Column::StartedAt.lt(now - stale_after)),
+                    ),
+            )
+            .order_by(refresh_request::Column::RequestedAt, Order::Asc)
+            .order_by(refresh_request::Column::Id, Order::Asc)
+            .limit(1)
+            .lock_with_behavior(LockType::Update, LockBehavior::SkipLocked)
+            .to_owned();
+        let claimed = refresh_request::Entity::update_many()
+            .col_expr(
+                refresh_request::Column::Status,
+                EntityRefreshStatus::Running.as_enum(),
+            )
+            .col_expr(refresh_request::Column::StartedAt, Expr::value(now))
+            .filter(refresh_request::Column::Id.in_subquery(oldest_open))
+            .exec_with_returning(&self.db)
+            .await?;
+        Ok(claimed.into_iter().next())
+    }
+
+    /// Extend the claim on a running refresh request so it is not claimed again as abandoned
+    pub async fn touch_refresh_request(&self, id: i32) -> Result<(), DbErr> {
+        refresh_request::Entity::update_many()
+            .col_expr(
+                refresh_request::Column::StartedAt,
+                Expr::value(time::OffsetDateTime::now_utc()),
+            )
+            .filter(refresh_request::Column::Id.eq(id))
+            .filter(refresh_request::Column::Status.eq(EntityRefreshStatus::Running))
+            .exec(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// Record the end of a refresh request
+    pub async fn finish_refresh_request(
+        &self,
+        id: i32,
+        finished: FinishedRefresh,
+    ) -> Result<refresh_request::Model, DbErr> {
+        let request = refresh_request::ActiveModel {
+            id: Set(id),
+            status: Set(finished.status.into()),
+            finished_at: Set(Some(time::OffsetDateTime::now_utc())),
+            balance_id: Set(finished.balance_id),
+            snapshot_id: Set(finished.snapshot_id),
+            error: Set(finished.error),
+            ..Default::default()
+        };
+        request.update(&self.db).await
+    }
+}
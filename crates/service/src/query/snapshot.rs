@@ -0,0 +1,84 @@
+use crate::types::SnapshotStatus;
+use hammer_entity::{
+    balance, balance_entry, sea_orm_active_enums::SnapshotStatus as EntitySnapshotStatus, snapshot,
+};
+use sea_orm::{Order, QueryOrder, Set, entity::prelude::*};
+
+use super::QueryService;
+
+impl QueryService {
+    /// Get snapshot by ID
+    pub async fn get_snapshot_by_id(&self, id: i32) -> Result<Option<snapshot::Model>, DbErr> {
+        snapshot::Entity::find_by_id(id).one(&self.db).await
+    }
+
+    /// Get the complete snapshot with the latest scheduled time
+    pub async fn get_latest_complete_snapshot(&self) -> Result<Option<snapshot::Model>, DbErr> {
+        snapshot::Entity::find()
+            .filter(snapshot::Column::Status.eq(EntitySnapshotStatus::Complete))
+            .order_by(snapshot::Column::ScheduledTime, Order::Desc)
+            .order_by(snapshot::Column::Id, Order::Desc)
+            .one(&self.db)
+            .await
+    }
+
+    /// Record the start of a snapshot scheduled at `scheduled_time`
+    pub async fn start_snapshot(
+        &self,
+        scheduled_time: time::OffsetDateTime,
+    ) -> Result<snapshot::Model, DbErr> {
+        let snapshot = snapshot::ActiveModel {
+            scheduled_time: Set(scheduled_time),
+            started_at: Set(time::OffsetDateTime::now_utc()),
+            status: Set(EntitySnapshotStatus::Running),
+            ..Default::default()
+        };
+        snapshot.insert(&self.db).await
+    }
+
+    /// Record the end of a snapshot
+    pub async fn finish_snapshot(
+        &self,
+        id: i32,
+        status: SnapshotStatus,
+    ) -> Result<snapshot::Model, DbErr> {
+        let snapshot = snapshot::ActiveModel {
+            id: Set(id),
+            completed_at: Set(Some(time::OffsetDateTime::now_utc())),
+            status: Set(status.into()),
+            ..Default::default()
+        };
+        snapshot.update(&self.db).await
+    }
+
+    /// Get the balances with entries of every wallet in a snapshot
+    pub async fn get_snapshot_balances(
+        &self,
+        snapshot_id: i32,
+    ) -> Result<Vec<(balance::Model, Vec<balance_entry::Model>)>, DbErr> {
+        balance::Entity::find()
+            .filter(balance::Column::SnapshotId.eq(snapshot_id))
+            .order_by(balance::Column::WalletId, Order::Asc)
+            .order_by(balance::Column::Id, Order::Asc)
+            .find_with_related(balance_entry::Entity)
+            .all(&self.db)
+            .await
+    }
+
+    /// Get the latest complete snapshot with the balances of every wallet in it
+    pub async fn get_latest_complete_snapshot_balances(
+        &self,
+    ) -> Result<
+        Option<(
+            snapshot::Model,
+            Vec<(balance::Model, Vec<balance_entry::Model>)>,
+        )>,
+        DbErr,
+    > {
+        let Some(snapshot) = self.get_latest_complete_snapshot().await? else {
+            return Ok(None);
+        };
+        let balances = self.get_snapshot_balances(snapshot.id).await?;
+        Ok(Some((snapshot, balances)))
+    }
+}
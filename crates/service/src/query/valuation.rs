@@ -0,0 +1,459 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::types::{
+    AssetScope, CurrencyValue, DataProvider, PortfolioValuation, UnmappedHolding, UnpricedHolding,
+    ValuedBalance, WalletRollup, WalletValuation,
+};
+use hammer_entity::{
+    balance, balance_entry, balance_priority, currency_map, price, price_provider, wallet,
+};
+use rust_decimal::Decimal;
+use sea_orm::{Order, QueryOrder, QuerySelect, entity::prelude::*};
+
+use super::{QueryService, wallet::WalletWalk};
+
+impl QueryService {
+    /// Get the current value of every wallet
+    pub async fn get_portfolio_valuation(&self) -> Result<PortfolioValuation, DbErr> {
+        let wallets = wallet::Entity::find()
+            .order_by(wallet::Column::Id, Order::Asc)
+            .all(&self.db)
+            .await?;
+        let wallets = self.value_wallets(wallets).await?;
+        let total = wallets.iter().map(|wallet| wallet.total).sum();
+        Ok(PortfolioValuation { wallets, total })
+    }
+
+    /// Get the current value of wallets, sorted by wallet ID
+    ///
+    /// Unknown wallet IDs are ignored.
+    pub async fn get_wallet_valuations(
+        &self,
+        wallet_ids: &[i32],
+    ) -> Result<Vec<WalletValuation>, DbErr> {
+        let wallets = wallet::Entity::find()
+            .filter(wallet::Column::Id.is_in(wallet_ids.iter().copied()))
+            .order_by(wallet::Column::Id, Order::Asc)
+            .all(&self.db)
+            .await?;
+        self.value_wallets(wallets).await
+    }
+
+    /// Get the value of a wallet rolled up with its descendants, or `None` if it does not exist
+    ///
+    /// Every node of the rollup sums its own balance with the rollups of its children, so the
+    /// value can be reported at any level, e.g. per fund, venue master account or sub-account.
+    pub async fn get_wallet_rollup(&self, wallet_id: i32) -> Result<Option<WalletRollup>, DbErr> {
+        let nodes = self
+            .walk_wallets(wallet_id, WalletWalk::Descendants)
+            .await?;
+        if nodes.is_empty() {
+            return Ok(None);
+        }
+        let wallet_ids = nodes.iter().map(|node| node.wallet_id).collect::<Vec<_>>();
+        let mut valuations = self
+            .get_wallet_valuations(&wallet_ids)
+            .await?
+            .into_iter()
+            .map(|valuation| (valuation.wallet_id, valuation))
+            .collect::<HashMap<_, _>>();
+
+        // Skip the parent of the starting wallet, which is a descendant when parents form a cycle
+        let mut tree: HashMap<i32, Vec<i32>> = HashMap::new();
+        for node in nodes.iter().filter(|node| node.depth > 0) {
+            if let Some(parent_id) = node.parent_id {
+                tree.entry(parent_id).or_default().push(node.wallet_id);
+            }
+        }
+        Ok(Some(roll_up(wallet_id, 0, &tree, &mut valuations)))
+    }
+
+    /// Values the latest balance of each wallet from its highest-priority provider with a
+    /// balance, mapping raw currencies through the wallet's scope and pricing them by price
+    /// provider priority
+    async fn value_wallets(
+        &self,
+        wallets: Vec<wallet::Model>,
+    ) -> Result<Vec<WalletValuation>, DbErr> {
+        let wallet_ids = wallets.iter().map(|wallet| wallet.id).collect::<Vec<_>>();
+        let balances = self.get_prioritised_latest_balances(&wallet_ids).await?;
+        let mut entries: HashMap<i32, Vec<balance_entry::Model>> = HashMap::new();
+        for entry in balance_entry::Entity::find()
+            .filter(balance_entry::Column::BalanceId.is_in(balances.values().map(|b| b.id)))
+            .all(&self.db)
+            .await?
+        {
+            entries.entry(entry.balance_id).or_default().push(entry);
+        }
+        let mappings = currency_map::Entity::find()
+            .all(&self.db)
+            .await?
+            .into_iter()
+            .map(|mapping| {
+                (
+                    (mapping.scope.into(), mapping.raw_currency),
+                    mapping.currency,
+                )
+            })
+            .collect::<HashMap<(AssetScope, String), String>>();
+
+        // Sum the holdings of each wallet by canonical currency
+        let mut holdings = Vec::with_capacity(wallets.len());
+        for wallet in &wallets {
+            let mut currencies: BTreeMap<String, Decimal> = BTreeMap::new();
+            let mut unmapped: BTreeMap<String, Decimal> = BTreeMap::new();
+            let balance = balances.get(&wallet.id);
+            for entry in balance
+                .and_then(|balance| entries.remove(&balance.id))
+                .unwrap_or_default()
+            {
+                match mappings.get(&(wallet.scope.into(), entry.raw_currency.clone())) {
+                    Some(currency) => {
+                        *currencies.entry(currency.clone()).or_default() += entry.amount
+                    }
+                    None => *unmapped.entry(entry.raw_currency).or_default() += entry.amount,
+                }
+            }
+            holdings.push((wallet.id, balance, currencies, unmapped));
+        }
+
+        let held = holdings
+            .iter()
+            .flat_map(|(_, _, currencies, _)| currencies.keys().cloned())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        let prices = self.get_prioritised_latest_prices(&held).await?;
+
+        Ok(holdings
+            .into_iter()
+            .map(|(wallet_id, balance, currencies, unmapped)| {
+                let mut valuation = WalletValuation {
+                    wallet_id,
+                    balance: balance.map(|balance| ValuedBalance {
+                        id: balance.id,
+                        provider: balance.provider.into(),
+                        time: balance.time,
+                    }),
+                    currencies: Vec::new(),
+                    total: Decimal::ZERO,
+                    unmapped: unmapped
+                        .into_iter()
+                        .map(|(raw_currency, amount)| UnmappedHolding {
+                            raw_currency,
+                            amount,
+                        })
+                        .collect(),
+                    unpriced: Vec::new(),
+                };
+                for (currency, amount) in currencies {
+                    match prices.get(&currency) {
+                        Some(price) => {
+                            let value = amount * price.value;
+                            valuation.total += value;
+                            valuation.currencies.push(CurrencyValue {
+                                currency,
+                                amount,
+                                price: price.value,
+                                price_provider: price.provider.into(),
+                                price_time: price.time,
+                                value,
+                            });
+                        }
+                        None => valuation
+                            .unpriced
+                            .push(UnpricedHolding { currency, amount }),
+                    }
+                }
+                valuation
+            })
+            .collect())
+    }
+
+    /// Get the latest balance of each wallet from its highest-priority provider with a balance
+    async fn get_prioritised_latest_balances(
+        &self,
+        wallet_ids: &[i32],
+    ) -> Result<HashMap<i32, balance::Model>, DbErr> {
+        let latest = balance::Entity::find()
+            .filter(balance::Column::WalletId.is_in(wallet_ids.iter().copied()))
+            .distinct_on([
+                (balance::Entity, balance::Column::WalletId),
+                (balance::Entity, balance::Column::Provider),
+            ])
+            .order_by(balance::Column::WalletId, Order::Asc)
+            .order_by(balance::Column::Provider, Order::Asc)
+            .order_by(balance::Column::Time, Order::Desc)
+            .order_by(balance::Column::Id, Order::Desc)
+            .all(&self.db)
+            .await?
+            .into_iter()
+            .map(|balance| ((balance.wallet_id, balance.provider.into()), balance))
+            .collect::<HashMap<(i32, DataProvider), balance::Model>>();
+        let priorities = balance_priority::Entity::find()
+            .filter(balance_priority::Column::WalletId.is_in(wallet_ids.iter().copied()))
+            .order_by(balance_priority::Column::Priority, Order::Asc)
+            .all(&self.db)
+            .await?;
+
+        let mut balances = HashMap::new();
+        for priority in priorities {
+            if let Some(balance) = latest.get(&(priority.wallet_id, priority.provider.into())) {
+                balances
+                    .entry(priority.wallet_id)
+                    .or_insert_with(|| balance.clone());
+            }
+        }
+        Ok(balances)
+    }
+
+    /// Get the latest price of each currency from its highest-priority provider with a price
+    async fn get_prioritised_latest_prices(
+        &self,
+        currencies: &[String],
+    ) -> Result<HashMap<String, price::Model>, DbErr> {
+        let latest = price::Entity::find()
+            .filter(price::Column::Currency.is_in(currencies.iter().cloned()))
+            .distinct_on([
+                (price::Entity, price::Column::Currency),
+                (price::Entity, price::Column::Provider),
+            ])
+            .order_by(price::Column::Currency, Order::Asc)
+            .order_by(price::Column::Provider, Order::Asc)
+            .order_by(price::Column::Time, Order::Desc)
+            .order_by(price::Column::Id, Order::Desc)
+            .all(&self.db)
+            .await?
+            .into_iter()
+            .map(|price| ((price.currency.clone(), price.provider.into()), price))
+            .collect::<HashMap<(String, DataProvider), price::Model>>();
+        let priorities = price_provider::Entity::find()
+            .filter(price_provider::Column::Currency.is_in(currencies.iter().cloned()))
+            .order_by(price_provider::Column::Priority, Order::Asc)
+            .all(&self.db)
+            .await?;
+
+        let mut prices = HashMap::new();
+        for priority in priorities {
+            if let Some(price) = latest.get(&(priority.currency.clone(), priority.provider.into()))
+            {
+                prices
+                    .entry(priority.currency)
+                    .or_insert_with(|| price.clone());
+            }
+        }
+        Ok(prices)
+    }
+}
+
+/// Sums the valuation of a wallet with the rollups of its children
+fn roll_up(
+    wallet_id: i32,
+    depth: i32,
+    tree: &HashMap<i32, Vec<i32>>,
+    valuations: &mut HashMap<i32, WalletValuation>,
+) -> WalletRollup {
+    let own = valuations
+        .remove(&wallet_id)
+        .unwrap_or_else(|| WalletValuation {
+            wallet_id,
+            balance: None,
+            currencies: Vec::new(),
+            total: Decimal::ZERO,
+            unmapped: Vec::new(),
+            unpriced: Vec::new(),
+        });
+    let children = tree
+        .get(&wallet_id)
+        .into_iter()
+        .flatten()
+        .map(|&child_id| roll_up(child_id, depth + 1, tree, valuations))
+        .collect::<Vec<_>>();
+
+    let mut currencies: BTreeMap<String, CurrencyValue> = BTreeMap::new();
+    let mut unmapped: BTreeMap<String, Decimal> = BTreeMap::new();
+    let mut unpriced: BTreeMap<String, Decimal> = BTreeMap::new();
+    let parts = std::iter::once((&own.currencies, &own.unmapped, &own.unpriced)).chain(
+        children
+            .iter()
+            .map(|child| (&child.currencies, &child.unmapped, &child.unpriced)),
+    );
+    for (values, unmapped_holdings, unpriced_holdings) in parts {
+        for value in values {
+            currencies
+                .entry(value.currency.clone())
+                .and_modify(|sum| {
+                    sum.amount += value.amount;
+                    sum.value += value.value;
+                })
+                .or_insert_with(|| value.clone());
+        }
+        for holding in unmapped_holdings {
+            *unmapped.entry(holding.raw_currency.clone()).or_default() += holding.amount;
+        }
+        for holding in unpriced_holdings {
+            *unpriced.entry(holding.currency.clone()).or_default() += holding.amount;
+        }
+    }
+
+    WalletRollup {
+        wallet_id,
+        depth,
+        own,
+        total: currencies.values().map(|value| value.value).sum(),
+        currencies: currencies.into_values().collect(),
+        unmapped: unmapped
+            .into_iter()
+            .map(|(raw_currency, amount)| UnmappedHolding {
+                raw_currency,
+                amount,
+            })
+            .collect(),
+        unpriced: unpriced
+            .into_iter()
+            .map(|(currency, amount)| UnpricedHolding { currency, amount })
+            .collect(),
+        children,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::OffsetDateTime;
+
+    use super::*;
+
+    fn value(currency: &str, amount: Decimal, price: Decimal) -> CurrencyValue {
+        CurrencyValue {
+            currency: currency.to_owned(),
+            amount,
+            price,
+            price_provider: DataProvider::Cam,
+            price_time: OffsetDateTime::UNIX_EPOCH,
+            value: amount * price,
+        }
+    }
+
+    fn valuation(
+        wallet_id: i32,
+        currencies: Vec<CurrencyValue>,
+        unmapped: &[(&str, i64)],
+        unpriced: &[(&str, i64)],
+    ) -> WalletValuation {
+        WalletValuation {
+            wallet_id,
+            balance: None,
+            total: currencies.iter().map(|value| value.value).sum(),
+            currencies,
+            unmapped: unmapped
+                .iter()
+                .map(|&(raw_currency, amount)| UnmappedHolding {
+                    raw_currency: raw_currency.to_owned(),
+                    amount: amount.into(),
+                })
+                .collect(),
+            unpriced: unpriced
+                .iter()
+                .map(|&(currency, amount)| UnpricedHolding {
+                    currency: currency.to_owned(),
+                    amount: amount.into(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Wallet 1 with children 2 and 3, and wallet 4 below 2; wallet 3 has no valuation
+    fn roll_up_tree() -> WalletRollup {
+        let eth = Decimal::from(2000);
+        let btc = Decimal::from(50000);
+        let tree = HashMap::from([(1, vec![2, 3]), (2, vec![4])]);
+        let mut valuations = HashMap::from([
+            (
+                1,
+                valuation(1, vec![value("ETH", Decimal::ONE, eth)], &[("XYZ", 5)], &[]),
+            ),
+            (
+                2,
+                valuation(
+                    2,
+                    vec![
+                        value("BTC", Decimal::new(1, 1), btc),
+                        value("ETH", Decimal::TWO, eth),
+                    ],
+                    &[],
+                    &[("FOO", 3)],
+                ),
+            ),
+            (
+                4,
+                valuation(
+                    4,
+                    vec![value("BTC", Decimal::new(2, 1), btc)],
+                    &[("XYZ", 1)],
+                    &[("FOO", 2)],
+                ),
+            ),
+        ]);
+        roll_up(1, 0, &tree, &mut valuations)
+    }
+
+    #[test]
+    fn sums_children_by_currency() {
+        let root = roll_up_tree();
+        let sums = root
+            .currencies
+            .iter()
+            .map(|value| (value.currency.as_str(), value.amount, value.value))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sums,
+            vec![
+                ("BTC", Decimal::new(3, 1), Decimal::from(15000)),
+                ("ETH", Decimal::from(3), Decimal::from(6000)),
+            ]
+        );
+        assert_eq!(root.total, Decimal::from(21000));
+        assert_eq!(root.own.total, Decimal::from(2000));
+
+        let child = &root.children[0];
+        assert_eq!(child.total, Decimal::from(19000));
+        assert_eq!(child.own.total, Decimal::from(9000));
+    }
+
+    #[test]
+    fn merges_unmapped_and_unpriced_holdings() {
+        let root = roll_up_tree();
+        let unmapped = root
+            .unmapped
+            .iter()
+            .map(|holding| (holding.raw_currency.as_str(), holding.amount))
+            .collect::<Vec<_>>();
+        assert_eq!(unmapped, vec![("XYZ", Decimal::from(6))]);
+        let unpriced = root
+            .unpriced
+            .iter()
+            .map(|holding| (holding.currency.as_str(), holding.amount))
+            .collect::<Vec<_>>();
+        assert_eq!(unpriced, vec![("FOO", Decimal::from(5))]);
+    }
+
+    #[test]
+    fn nests_children_with_their_depth() {
+        let root = roll_up_tree();
+        assert_eq!((root.wallet_id, root.depth), (1, 0));
+        let children = root
+            .children
+            .iter()
+            .map(|child| (child.wallet_id, child.depth))
+            .collect::<Vec<_>>();
+        assert_eq!(children, vec![(2, 1), (3, 1)]);
+        let grandchild = &root.children[0].children[0];
+        assert_eq!((grandchild.wallet_id, grandchild.depth), (4, 2));
+        assert!(grandchild.children.is_empty());
+
+        // A wallet without a valuation rolls up to nothing
+        let empty = &root.children[1];
+        assert!(empty.own.currencies.is_empty() && empty.children.is_empty());
+        assert_eq!(empty.total, Decimal::ZERO);
+    }
+}
@@ -1,9 +1,18 @@
-use crate::types::{NewWallet, NewWalletMetadata};
-use hammer_entity::{wallet, wallet_metadata};
-use sea_orm::{Set, entity::prelude::*};
+use crate::types::{DataProvider, NewWallet, NewWalletMetadata, WalletNode};
+use hammer_entity::{
+    sea_orm_active_enums::DataProvider as EntityDataProvider, wallet, wallet_metadata,
+};
+use sea_orm::{ConnectionTrait, DbBackend, Set, Statement, TransactionTrait, entity::prelude::*};
 
 use super::QueryService;
 
+/// Direction of a walk of the wallet hierarchy
+#[derive(Debug, Clone, Copy)]
+pub(super) enum WalletWalk {
+    Descendants,
+    Ancestors,
+}
+
 impl QueryService {
     /// Get all wallets with their metadata
     pub async fn get_wallets_with_metadata(
@@ -25,6 +34,8 @@ impl QueryService {
         let wallet = wallet::ActiveModel {
             scope: Set(new_wallet.scope.into()),
             parent_id: Set(new_wallet.parent_id),
+            provider: Set(new_wallet.provider.map(Into::into)),
+            external_id: Set(new_wallet.external_id),
             ..Default::default()
         };
         wallet.insert(&self.db).await
@@ -40,6 +51,156 @@ impl QueryService {
             id: Set(id),
             scope: Set(new_wallet.scope.into()),
             parent_id: Set(new_wallet.parent_id),
+            provider: Set(new_wallet.provider.map(Into::into)),
+            external_id: Set(new_wallet.external_id),
+            ..Default::default()
+        };
+        wallet.update(&self.db).await
+    }
+
+    /// Create a wallet together with its metadata
+    pub async fn create_wallet_with_metadata(
+        &self,
+        new_wallet: NewWallet,
+        alias: String,
+        address: Option<String>,
+    ) -> Result<wallet::Model, DbErr> {
+        let tx = self.db.begin().await?;
+
+        let wallet = wallet::ActiveModel {
+            scope: Set(new_wallet.scope.into()),
+            parent_id: Set(new_wallet.parent_id),
+            provider: Set(new_wallet.provider.map(Into::into)),
+            external_id: Set(new_wallet.external_id),
+            ..Default::default()
+        };
+        let wallet = match wallet.insert(&tx).await {
+            Ok(wallet) => wallet,
+            Err(e) => {
+                tx.rollback().await?;
+                return Err(e);
+            }
+        };
+
+        let metadata = wallet_metadata::ActiveModel {
+            wallet_id: Set(wallet.id),
+            alias: Set(alias),
+            address: Set(address),
+            ..Default::default()
+        };
+        if let Err(e) = metadata.insert(&tx).await {
+            tx.rollback().await?;
+            return Err(e);
+        }
+
+        tx.commit().await?;
+        Ok(wallet)
+    }
+
+    /// Get wallets synced from a provider
+    pub async fn get_wallets_by_provider(
+        &self,
+        provider: DataProvider,
+    ) -> Result<Vec<wallet::Model>, DbErr> {
+        wallet::Entity::find()
+            .filter(wallet::Column::Provider.eq(EntityDataProvider::from(provider)))
+            .all(&self.db)
+            .await
+    }
+
+    /// Set the parent of a wallet
+    pub async fn set_wallet_parent(
+        &self,
+        id: i32,
+        parent_id: Option<i32>,
+    ) -> Result<wallet::Model, DbErr> {
+        let wallet = wallet::ActiveModel {
+            id: Set(id),
+            parent_id: Set(parent_id),
+            ..Default::default()
+        };
+        wallet.update(&self.db).await
+    }
+
+    /// Get the descendants of a wallet, nearest first
+    ///
+    /// Depths count the levels below the wallet, from 1 for its children.
+    pub async fn get_wallet_descendants(&self, id: i32) -> Result<Vec<WalletNode>, DbErr> {
+        let mut nodes = self.walk_wallets(id, WalletWalk::Descendants).await?;
+        nodes.retain(|node| node.depth > 0);
+        Ok(nodes)
+    }
+
+    /// Get the ancestors of a wallet, from its parent up to the root
+    ///
+    /// Depths count the levels above the wallet, from 1 for its parent.
+    pub async fn get_wallet_ancestors(&self, id: i32) -> Result<Vec<WalletNode>, DbErr> {
+        let mut nodes = self.walk_wallets(id, WalletWalk::Ancestors).await?;
+        nodes.retain(|node| node.depth > 0);
+        Ok(nodes)
+    }
+
+    /// Get the depth of a wallet in the hierarchy, 0 for a root, or `None` if it does not exist
+    pub async fn get_wallet_depth(&self, id: i32) -> Result<Option<i32>, DbErr> {
+        let nodes = self.walk_wallets(id, WalletWalk::Ancestors).await?;
+        Ok(nodes.iter().map(|node| node.depth).max())
+    }
+
+    /// Walk the wallet hierarchy from a wallet with a recursive CTE, including the wallet itself
+    /// at depth 0
+    ///
+    /// A cycle of parents ends the walk where a wallet would be visited again. Detecting it uses
+    /// the `CYCLE` clause, which requires PostgreSQL 14 or later.
+    pub(super) async fn walk_wallets(
+        &self,
+        id: i32,
+        walk: WalletWalk,
+    ) -> Result<Vec<WalletNode>, DbErr> {
+        let join = match walk {
+            WalletWalk::Descendants => "wallet.parent_id = tree.wallet_id",
+            WalletWalk::Ancestors => "wallet.id = tree.parent_id",
+        };
+        let rows = self
+            .db
+            .query_all(Statement::from_sql_and_values(
+                DbBackend::Postgres,
+                format!(
+                    r#"
+                    WITH RECURSIVE tree (wallet_id, parent_id, depth) AS (
+                        SELECT id, parent_id, 0 FROM wallet WHERE id = $1
+                        UNION ALL
+                        SELECT wallet.id, wallet.parent_id, tree.depth + 1
+                        FROM wallet JOIN tree ON {join}
+                    ) CYCLE wallet_id SET is_cycle USING path
+                    SELECT wallet_id, parent_id, depth FROM tree
+                    WHERE NOT is_cycle
+                    ORDER BY depth, wallet_id
+                    "#
+                ),
+                [id.into()],
+            ))
+            .await?;
+        rows.into_iter()
+            .map(|row| {
+                Ok(WalletNode {
+                    wallet_id: row.try_get("", "wallet_id")?,
+                    parent_id: row.try_get("", "parent_id")?,
+                    depth: row.try_get("", "depth")?,
+                })
+            })
+            .collect()
+    }
+
+    /// Flag a wallet as missing upstream since the given time, or clear the flag
+    pub async fn set_wallet_missing_since(
+        &self,
+        id: i32,
+        missing_since: Option<time::OffsetDateTime>,
+    ) -> Result<wallet::Model, DbErr> {
+        let wallet = wallet::ActiveModel {
+            id: Set(id),
+            missing_since: Set(missing_since),
+            ..Default::default()
         };
         wallet.update(&self.db).await
     }
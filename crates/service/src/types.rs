@@ -1,5 +1,11 @@
-use hammer_entity::sea_orm_active_enums::{
-    AssetScope as EntityAssetScope, DataProvider as EntityDataProvider,
+use hammer_entity::{
+    refresh_request,
+    sea_orm_active_enums::{
+        AssetScope as EntityAssetScope, BackfillStatus as EntityBackfillStatus,
+        DataProvider as EntityDataProvider, JobStatus as EntityJobStatus,
+        RefreshStatus as EntityRefreshStatus, RefreshTarget as EntityRefreshTarget,
+        SnapshotStatus as EntitySnapshotStatus,
+    },
 };
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
@@ -10,6 +16,10 @@ use time::OffsetDateTime;
 pub struct NewWallet {
     pub scope: AssetScope,
     pub parent_id: Option<i32>,
+    /// Provider the wallet is synced from, if any
+    pub provider: Option<DataProvider>,
+    /// Stable ID of the account at the provider
+    pub external_id: Option<String>,
 }
 
 /// New wallet metadata structure
@@ -26,6 +36,9 @@ pub struct NewBalance {
     pub wallet_id: i32,
     pub time: OffsetDateTime,
     pub provider: DataProvider,
+    pub block_number: Option<i64>,
+    pub block_hash: Option<String>,
+    pub snapshot_id: Option<i32>,
 }
 
 /// New balance entry data structure
@@ -76,8 +89,153 @@ pub struct NewPricePriority {
     pub priority: i32,
 }
 
-/// Asset scope enum
+/// Finished job run data structure
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FinishedJobRun {
+    pub status: JobStatus,
+    pub wallets_processed: Option<i32>,
+    pub error_summary: Option<String>,
+}
+
+/// New price backfill data structure, identifying a backfill to start or resume
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewPriceBackfill {
+    pub currency: String,
+    pub provider: DataProvider,
+    pub start_time: OffsetDateTime,
+    pub end_time: OffsetDateTime,
+    pub granularity_secs: i64,
+}
+
+/// Price backfill progress data structure
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PriceBackfillProgress {
+    /// Next timestamp to backfill
+    pub cursor: OffsetDateTime,
+    pub inserted: i32,
+    pub skipped: i32,
+}
+
+/// Finished refresh request data structure
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FinishedRefresh {
+    pub status: RefreshStatus,
+    pub balance_id: Option<i32>,
+    pub snapshot_id: Option<i32>,
+    pub error: Option<String>,
+}
+
+/// What a refresh request refreshes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RefreshTarget {
+    /// Balances of one wallet from its prioritised providers
+    Wallet(i32),
+    /// Price of one canonical currency
+    Price(String),
+    /// Balances of every wallet and prices of every held currency
+    All,
+}
+
+impl TryFrom<&refresh_request::Model> for RefreshTarget {
+    type Error = String;
+
+    fn try_from(request: &refresh_request::Model) -> Result<Self, Self::Error> {
+        match (request.target, request.wallet_id, &request.currency) {
+            (EntityRefreshTarget::Wallet, Some(wallet_id), _) => {
+                Ok(RefreshTarget::Wallet(wallet_id))
+            }
+            (EntityRefreshTarget::Price, _, Some(currency)) => {
+                Ok(RefreshTarget::Price(currency.clone()))
+            }
+            (EntityRefreshTarget::All, _, _) => Ok(RefreshTarget::All),
+            (target, _, _) => Err(format!("{target:?} refresh request without its subject")),
+        }
+    }
+}
+
+/// Wallet reached by walking the wallet hierarchy
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WalletNode {
+    pub wallet_id: i32,
+    pub parent_id: Option<i32>,
+    /// Levels away from the wallet the walk started at
+    pub depth: i32,
+}
+
+/// Value of one canonical currency held
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CurrencyValue {
+    pub currency: String,
+    pub amount: Decimal,
+    /// Latest price from the highest-priority price provider with a price
+    pub price: Decimal,
+    pub price_provider: DataProvider,
+    pub price_time: OffsetDateTime,
+    pub value: Decimal,
+}
+
+/// Holding whose raw currency has no mapping in the wallet's scope
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnmappedHolding {
+    pub raw_currency: String,
+    pub amount: Decimal,
+}
+
+/// Holding of a canonical currency without a price
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnpricedHolding {
+    pub currency: String,
+    pub amount: Decimal,
+}
+
+/// Balance a wallet valuation is based on
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValuedBalance {
+    pub id: i32,
+    pub provider: DataProvider,
+    pub time: OffsetDateTime,
+}
+
+/// Current value of a wallet
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WalletValuation {
+    pub wallet_id: i32,
+    /// Balance valued, the latest from the highest-priority provider with a balance
+    pub balance: Option<ValuedBalance>,
+    /// Values by currency, sorted by currency
+    pub currencies: Vec<CurrencyValue>,
+    /// Sum of the currency values, excluding unmapped and unpriced holdings
+    pub total: Decimal,
+    pub unmapped: Vec<UnmappedHolding>,
+    pub unpriced: Vec<UnpricedHolding>,
+}
+
+/// Current value of every wallet
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PortfolioValuation {
+    pub wallets: Vec<WalletValuation>,
+    pub total: Decimal,
+}
+
+/// Valuation of a wallet together with its descendants
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WalletRollup {
+    pub wallet_id: i32,
+    /// Levels below the wallet the rollup was requested for
+    pub depth: i32,
+    /// Value of the wallet's own balance
+    pub own: WalletValuation,
+    /// Values of the wallet and its descendants by currency, sorted by currency
+    pub currencies: Vec<CurrencyValue>,
+    pub total: Decimal,
+    pub unmapped: Vec<UnmappedHolding>,
+    pub unpriced: Vec<UnpricedHolding>,
+    /// Rollups of the wallet's children, sorted by wallet ID
+    pub children: Vec<WalletRollup>,
+}
+
+/// Asset scope enum
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum AssetScope {
     Binance,
     Upbit,
@@ -120,11 +278,12 @@ impl From<AssetScope> for EntityAssetScope {
 }
 
 /// Data provider enum
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum DataProvider {
     Cam,
     Ccxt,
     Debank,
+    Onchain,
 }
 
 impl From<EntityDataProvider> for DataProvider {
@@ -133,6 +292,7 @@ impl From<EntityDataProvider> for DataProvider {
             EntityDataProvider::Cam => DataProvider::Cam,
             EntityDataProvider::Ccxt => DataProvider::Ccxt,
             EntityDataProvider::Debank => DataProvider::Debank,
+            EntityDataProvider::Onchain => DataProvider::Onchain,
         }
     }
 }
@@ -143,6 +303,128 @@ impl From<DataProvider> for EntityDataProvider {
             DataProvider::Cam => EntityDataProvider::Cam,
             DataProvider::Ccxt => EntityDataProvider::Ccxt,
             DataProvider::Debank => EntityDataProvider::Debank,
+            DataProvider::Onchain => EntityDataProvider::Onchain,
+        }
+    }
+}
+
+/// Job status enum
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum JobStatus {
+    Running,
+    Succeeded,
+    Failed,
+    Interrupted,
+}
+
+impl From<EntityJobStatus> for JobStatus {
+    fn from(value: EntityJobStatus) -> Self {
+        match value {
+            EntityJobStatus::Running => JobStatus::Running,
+            EntityJobStatus::Succeeded => JobStatus::Succeeded,
+            EntityJobStatus::Failed => JobStatus::Failed,
+            EntityJobStatus::Interrupted => JobStatus::Interrupted,
+        }
+    }
+}
+
+impl From<JobStatus> for EntityJobStatus {
+    fn from(value: JobStatus) -> Self {
+        match value {
+            JobStatus::Running => EntityJobStatus::Running,
+            JobStatus::Succeeded => EntityJobStatus::Succeeded,
+            JobStatus::Failed => EntityJobStatus::Failed,
+            JobStatus::Interrupted => EntityJobStatus::Interrupted,
+        }
+    }
+}
+
+/// Snapshot status enum
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SnapshotStatus {
+    Running,
+    Complete,
+    Partial,
+    Failed,
+}
+
+impl From<EntitySnapshotStatus> for SnapshotStatus {
+    fn from(value: EntitySnapshotStatus) -> Self {
+        match value {
+            EntitySnapshotStatus::Running => SnapshotStatus::Running,
+            EntitySnapshotStatus::Complete => SnapshotStatus::Complete,
+            EntitySnapshotStatus::Partial => SnapshotStatus::Partial,
+            EntitySnapshotStatus::Failed => SnapshotStatus::Failed,
+        }
+    }
+}
+
+impl From<SnapshotStatus> for EntitySnapshotStatus {
+    fn from(value: SnapshotStatus) -> Self {
+        match value {
+            SnapshotStatus::Running => EntitySnapshotStatus::Running,
+            SnapshotStatus::Complete => EntitySnapshotStatus::Complete,
+            SnapshotStatus::Partial => EntitySnapshotStatus::Partial,
+            SnapshotStatus::Failed => EntitySnapshotStatus::Failed,
+        }
+    }
+}
+
+/// Refresh request status enum
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RefreshStatus {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+impl From<EntityRefreshStatus> for RefreshStatus {
+    fn from(value: EntityRefreshStatus) -> Self {
+        match value {
+            EntityRefreshStatus::Pending => RefreshStatus::Pending,
+            EntityRefreshStatus::Running => RefreshStatus::Running,
+            EntityRefreshStatus::Succeeded => RefreshStatus::Succeeded,
+            EntityRefreshStatus::Failed => RefreshStatus::Failed,
+        }
+    }
+}
+
+impl From<RefreshStatus> for EntityRefreshStatus {
+    fn from(value: RefreshStatus) -> Self {
+        match value {
+            RefreshStatus::Pending => EntityRefreshStatus::Pending,
+            RefreshStatus::Running => EntityRefreshStatus::Running,
+            RefreshStatus::Succeeded => EntityRefreshStatus::Succeeded,
+            RefreshStatus::Failed => EntityRefreshStatus::Failed,
+        }
+    }
+}
+
+/// Backfill status enum
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum BackfillStatus {
+    Running,
+    Complete,
+    Failed,
+}
+
+impl From<EntityBackfillStatus> for BackfillStatus {
+    fn from(value: EntityBackfillStatus) -> Self {
+        match value {
+            EntityBackfillStatus::Running => BackfillStatus::Running,
+            EntityBackfillStatus::Complete => BackfillStatus::Complete,
+            EntityBackfillStatus::Failed => BackfillStatus::Failed,
+        }
+    }
+}
+
+impl From<BackfillStatus> for EntityBackfillStatus {
+    fn from(value: BackfillStatus) -> Self {
+        match value {
+            BackfillStatus::Running => EntityBackfillStatus::Running,
+            BackfillStatus::Complete => EntityBackfillStatus::Complete,
+            BackfillStatus::Failed => EntityBackfillStatus::Failed,
         }
     }
 }
@@ -0,0 +1,27 @@
+//! Provider-agnostic account listings used to sync wallets
+
+use anyhow::Result;
+use async_trait::async_trait;
+use hammer_service::types::{AssetScope, DataProvider};
+
+/// An account as listed by a provider
+#[derive(Debug, Clone)]
+pub struct ExternalAccount {
+    /// Stable ID of the account at the provider
+    pub external_id: String,
+    /// External ID of the account this one belongs to, e.g. a sub-account's master
+    pub parent_external_id: Option<String>,
+    pub scope: AssetScope,
+    pub alias: String,
+    pub address: Option<String>,
+}
+
+/// A provider that lists the accounts wallets are synced from
+#[async_trait]
+pub trait AccountSource: Send + Sync {
+    /// Provider the listed accounts belong to
+    fn provider(&self) -> DataProvider;
+
+    /// Lists every account currently known to the provider
+    async fn fetch_accounts(&self) -> Result<Vec<ExternalAccount>>;
+}
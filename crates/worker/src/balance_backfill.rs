@@ -0,0 +1,328 @@
+//! Historical on-chain balance backfill
+
+use std::collections::{BTreeMap, HashSet};
+
+use anyhow::{Result, anyhow};
+use chain_client::{
+    abi::Address,
+    types::{Asset, BlockTag},
+};
+use hammer_entity::sea_orm_active_enums::AssetScope;
+use hammer_service::{
+    HammerService,
+    types::{DataProvider, NewBalance, NewBalanceEntry},
+};
+use time::{Duration, OffsetDateTime};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, instrument};
+
+use crate::onchain::{OnchainReader, anchored_balance, balance_entries, raw_currencies};
+
+/// Range of snapshots to backfill for a wallet
+#[derive(Debug, Clone)]
+pub struct BalanceBackfill {
+    pub wallet_id: i32,
+    pub start: OffsetDateTime,
+    pub end: OffsetDateTime,
+    pub step: Duration,
+}
+
+impl BalanceBackfill {
+    /// Snapshot times from `start` to `end` inclusive, `step` apart
+    pub fn timestamps(&self) -> Vec<OffsetDateTime> {
+        let mut timestamps = Vec::new();
+        let mut time = self.start;
+        while time <= self.end {
+            timestamps.push(time);
+            time += self.step;
+        }
+        timestamps
+    }
+}
+
+/// Outcome of a backfill run
+#[derive(Debug, Clone, Default)]
+pub struct BackfillReport {
+    pub inserted: usize,
+    pub skipped: usize,
+    /// Whether every time was backfilled, `false` when stopped by a shutdown
+    pub complete: bool,
+}
+
+/// Reads a wallet's on-chain balances at historical times and stores them as snapshots
+///
+/// Each time is mapped to the last block at or before it, which must be served by an
+/// archive-capable node. Times past the reader's confirmed head are rejected. Snapshots are
+/// stored at the requested time rather than the block time; times that already have an
+/// on-chain snapshot are skipped, so re-running the same backfill is a no-op and resumes one
+/// stopped by `token`.
+#[instrument(skip(svc, reader, token))]
+pub async fn backfill_onchain_balances(
+    svc: &HammerService,
+    reader: &OnchainReader,
+    backfill: BalanceBackfill,
+    token: &CancellationToken,
+) -> Result<BackfillReport> {
+    if backfill.step <= Duration::ZERO {
+        return Err(anyhow!("Backfill step must be positive"));
+    }
+
+    let wallet = svc
+        .query
+        .get_wallet_by_id(backfill.wallet_id)
+        .await?
+        .ok_or_else(|| anyhow!("Wallet {} not found", backfill.wallet_id))?;
+    if wallet.scope != AssetScope::Ethereum {
+        return Err(anyhow!("Wallet {} is not an Ethereum wallet", wallet.id));
+    }
+    let address = svc
+        .query
+        .get_wallet_metadata(wallet.id)
+        .await?
+        .and_then(|metadata| metadata.address)
+        .ok_or_else(|| anyhow!("Wallet {} has no on-chain address", wallet.id))?
+        .parse::<Address>()?;
+    let raw_currencies = raw_currencies(svc, AssetScope::Ethereum).await?;
+
+    let existing = svc
+        .query
+        .get_balance_times(
+            wallet.id,
+            DataProvider::Onchain,
+            backfill.start,
+            backfill.end,
+        )
+        .await?
+        .into_iter()
+        .collect::<HashSet<_>>();
+
+    let report = backfill_times(
+        reader,
+        &backfill,
+        address,
+        &raw_currencies,
+        &existing,
+        token,
+        |balance, entries| async move {
+            svc.query
+                .create_balance_with_entries(balance, entries)
+                .await?;
+            Ok(())
+        },
+    )
+    .await?;
+    if report.complete {
+        info!(
+            "Backfilled wallet {}: {} inserted, {} skipped",
+            wallet.id, report.inserted, report.skipped
+        );
+    }
+    Ok(report)
+}
+
+/// Reads the balances of `owner` at every backfill time not in `existing` and passes each to
+/// `store`
+async fn backfill_times<F, Fut>(
+    reader: &OnchainReader,
+    backfill: &BalanceBackfill,
+    owner: Address,
+    raw_currencies: &BTreeMap<Asset, String>,
+    existing: &HashSet<OffsetDateTime>,
+    token: &CancellationToken,
+    mut store: F,
+) -> Result<BackfillReport>
+where
+    F: FnMut(NewBalance, Vec<NewBalanceEntry>) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let assets = raw_currencies.keys().copied().collect::<Vec<_>>();
+    let client = reader.client();
+    let mut lo = client.get_block(BlockTag::Number(0)).await?;
+    let hi = client.pin_block(reader.confirmations()).await?;
+    if backfill.end.unix_timestamp() > hi.timestamp as i64 {
+        return Err(anyhow!(
+            "Backfill end {} is past the confirmed head at block {}",
+            backfill.end,
+            hi.number
+        ));
+    }
+
+    let mut report = BackfillReport::default();
+    for time in backfill.timestamps() {
+        if token.is_cancelled() {
+            info!(
+                "Backfill of wallet {} interrupted by shutdown at {}",
+                backfill.wallet_id, time
+            );
+            return Ok(report);
+        }
+        if existing.contains(&time) {
+            report.skipped += 1;
+            continue;
+        }
+
+        let block = client
+            .block_at_timestamp_between(time.unix_timestamp() as u64, lo, hi)
+            .await?;
+        lo = block;
+
+        let matrix = client
+            .read_balances(&[owner], &assets, BlockTag::Number(block.number))
+            .await?;
+        let mut new_balance = anchored_balance(backfill.wallet_id, &block)?;
+        new_balance.time = time;
+        let entries = balance_entries(&matrix, &owner, raw_currencies)
+            .map_err(|e| anyhow!("Failed to backfill {time}: {e:#}"))?;
+        store(new_balance, entries).await?;
+        report.inserted += 1;
+    }
+
+    report.complete = true;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chain_client::{
+        MULTICALL3,
+        abi::encode_uint,
+        testing::{BLOCK_TIME, GENESIS_TIME, local_node},
+    };
+    use rust_decimal::Decimal;
+
+    use super::*;
+
+    const OWNER: Address = Address([0x11; 20]);
+
+    /// Time of the given block of the local chain
+    fn block_time(number: u64) -> OffsetDateTime {
+        OffsetDateTime::from_unix_timestamp((GENESIS_TIME + number * BLOCK_TIME) as i64).unwrap()
+    }
+
+    /// Backfill of blocks 10, 30 and 50
+    fn backfill() -> BalanceBackfill {
+        BalanceBackfill {
+            wallet_id: 1,
+            start: block_time(10),
+            end: block_time(50),
+            step: Duration::seconds(20 * BLOCK_TIME as i64),
+        }
+    }
+
+    /// Reader of a chain where every owner holds 1.5 ETH
+    async fn reader() -> OnchainReader {
+        let client = local_node(Arc::new(|target, _| {
+            (target == MULTICALL3).then(|| encode_uint(1_500_000_000_000_000_000).to_vec())
+        }))
+        .await;
+        OnchainReader::new(client).with_confirmations(10)
+    }
+
+    fn raw_currencies() -> BTreeMap<Asset, String> {
+        BTreeMap::from([(Asset::Native, "ETH".to_owned())])
+    }
+
+    async fn run(
+        backfill: &BalanceBackfill,
+        existing: &HashSet<OffsetDateTime>,
+        token: &CancellationToken,
+    ) -> (BackfillReport, Vec<(NewBalance, Vec<NewBalanceEntry>)>) {
+        let mut stored = Vec::new();
+        let report = backfill_times(
+            &reader().await,
+            backfill,
+            OWNER,
+            &raw_currencies(),
+            existing,
+            token,
+            |balance, entries| {
+                stored.push((balance, entries));
+                std::future::ready(Ok(()))
+            },
+        )
+        .await
+        .unwrap();
+        (report, stored)
+    }
+
+    #[test]
+    fn timestamps_include_both_ends() {
+        let backfill = backfill();
+        assert_eq!(
+            backfill.timestamps(),
+            vec![block_time(10), block_time(30), block_time(50)]
+        );
+    }
+
+    #[tokio::test]
+    async fn stores_balances_at_the_requested_times() {
+        let (report, stored) = run(&backfill(), &HashSet::new(), &CancellationToken::new()).await;
+        assert_eq!((report.inserted, report.skipped), (3, 0));
+        assert!(report.complete);
+
+        let blocks = stored
+            .iter()
+            .map(|(balance, _)| balance.block_number)
+            .collect::<Vec<_>>();
+        assert_eq!(blocks, vec![Some(10), Some(30), Some(50)]);
+        let (balance, entries) = &stored[1];
+        assert_eq!(balance.time, block_time(30));
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].raw_currency, "ETH");
+        assert_eq!(entries[0].amount, Decimal::new(15, 1));
+    }
+
+    #[tokio::test]
+    async fn rerun_inserts_nothing() {
+        let backfill = backfill();
+        let (_, stored) = run(&backfill, &HashSet::new(), &CancellationToken::new()).await;
+        let existing = stored
+            .into_iter()
+            .map(|(balance, _)| balance.time)
+            .collect::<HashSet<_>>();
+
+        let (report, stored) = run(&backfill, &existing, &CancellationToken::new()).await;
+        assert_eq!((report.inserted, report.skipped), (0, 3));
+        assert!(report.complete);
+        assert!(stored.is_empty());
+    }
+
+    #[tokio::test]
+    async fn resumes_after_the_stored_times() {
+        let backfill = backfill();
+        let existing = HashSet::from([block_time(10)]);
+        let (report, stored) = run(&backfill, &existing, &CancellationToken::new()).await;
+        assert_eq!((report.inserted, report.skipped), (2, 1));
+        assert_eq!(stored[0].0.time, block_time(30));
+    }
+
+    #[tokio::test]
+    async fn stops_when_cancelled() {
+        let token = CancellationToken::new();
+        token.cancel();
+        let (report, stored) = run(&backfill(), &HashSet::new(), &token).await;
+        assert!(!report.complete);
+        assert!(stored.is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_times_past_the_confirmed_head() {
+        let backfill = BalanceBackfill {
+            end: block_time(95),
+            ..backfill()
+        };
+        let result = backfill_times(
+            &reader().await,
+            &backfill,
+            OWNER,
+            &raw_currencies(),
+            &HashSet::new(),
+            &CancellationToken::new(),
+            |_, _| std::future::ready(Ok(())),
+        )
+        .await;
+        assert!(result.is_err());
+    }
+}
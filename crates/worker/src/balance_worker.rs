@@ -1,19 +1,477 @@
 //! Balance worker for fetching balance data
 
-use anyhow::Result;
-use hammer_service::HammerService;
-use tracing::{info, instrument};
+use std::sync::Arc;
 
-/// Fetches balance data from CAM and stores it in the database
-#[instrument(skip(_svc))]
-pub async fn fetch_balances(_svc: &HammerService) -> Result<()> {
+use anyhow::{Result, anyhow};
+use hammer_entity::{wallet, wallet_metadata};
+use hammer_service::{
+    HammerService,
+    types::{DataProvider, SnapshotStatus},
+};
+use time::OffsetDateTime;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, instrument, warn};
+
+use crate::{
+    job::RunOptions,
+    limits::FetchLimits,
+    source::{BalanceSource, FetchedBalance, PreparedBalances, SourceRegistry, WalletWithMetadata},
+};
+
+/// Outcome of fetching one wallet from one provider
+#[derive(Debug)]
+pub struct WalletOutcome {
+    pub wallet_id: i32,
+    pub provider: DataProvider,
+    /// Balance fetched, or the reason the wallet failed
+    pub result: Result<FetchedBalance, String>,
+    /// ID of the stored balance, `None` in a dry run or on failure
+    pub balance_id: Option<i32>,
+}
+
+/// Per-wallet outcomes of a balance fetch
+#[derive(Debug, Default)]
+pub struct BalanceReport {
+    pub outcomes: Vec<WalletOutcome>,
+    /// Wallets selected by the run options without a registered source for any of their prioritised providers
+    pub skipped: Vec<i32>,
+    /// Snapshot grouping the stored balances, `None` in a dry run
+    pub snapshot_id: Option<i32>,
+    /// Previously stored balances re-read by their sources, e.g. after a chain reorg
+    pub rechecked: usize,
+}
+
+impl BalanceReport {
+    pub fn succeeded(&self) -> usize {
+        self.outcomes.iter().filter(|o| o.result.is_ok()).count()
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = &WalletOutcome> {
+        self.outcomes.iter().filter(|o| o.result.is_err())
+    }
+}
+
+/// Fetches balances of every wallet from its eligible providers and stores them
+///
+/// A provider is eligible for a wallet when it appears in the wallet's `balance_priority`
+/// rows and a source is registered for it in the wallet's scope. Wallets are fetched
+/// concurrently within `limits`, and a failing or timed out wallet is reported without aborting
+/// the others. Once `token` is cancelled no further wallets are fetched and the remaining ones
+/// are reported as failed. Only wallets selected by `options` are fetched, and in a dry run
+/// the balances are only reported.
+///
+/// Balances stored by one call share a snapshot for the `scheduled` tick. The snapshot is
+/// complete when every wallet was fetched from every eligible provider, and partial when the
+/// fetch was filtered, cancelled or some wallets failed or were skipped.
+///
+/// After a full tick every source rechecks the balances it stored before, e.g. re-reading
+/// balances whose block was reorged. A failed recheck is logged without failing the tick.
+#[instrument(skip_all)]
+pub async fn fetch_balances(
+    svc: &HammerService,
+    sources: &SourceRegistry,
+    limits: &FetchLimits,
+    scheduled: OffsetDateTime,
+    options: &RunOptions,
+    token: &CancellationToken,
+) -> Result<BalanceReport> {
     info!("Starting balance fetch");
+    if options.dry_run {
+        return fetch_snapshot(svc, sources, limits, None, options, token).await;
+    }
+
+    let snapshot = svc.query.start_snapshot(scheduled).await?;
+    let result = fetch_snapshot(svc, sources, limits, Some(snapshot.id), options, token).await;
+    let status = match &result {
+        Ok(report)
+            if report.failed().next().is_none()
+                && report.skipped.is_empty()
+                && options.wallet_ids.is_empty()
+                && !token.is_cancelled() =>
+        {
+            SnapshotStatus::Complete
+        }
+        Ok(_) => SnapshotStatus::Partial,
+        Err(_) => SnapshotStatus::Failed,
+    };
+    svc.query.finish_snapshot(snapshot.id, status).await?;
+    info!("Snapshot {} finished as {:?}", snapshot.id, status);
+
+    let mut report = result?;
+    if options.wallet_ids.is_empty() && !token.is_cancelled() {
+        report.rechecked = recheck_balances(svc, sources).await;
+    }
+    Ok(report)
+}
+
+/// Asks every source to recheck its stored balances, returning how many were re-read
+async fn recheck_balances(svc: &HammerService, sources: &SourceRegistry) -> usize {
+    let mut rechecked = 0;
+    for source in sources.all() {
+        match source.recheck(svc).await {
+            Ok(count) => rechecked += count,
+            Err(e) => error!(
+                "Failed to recheck {:?} balances: {:#}",
+                source.provider(),
+                e
+            ),
+        }
+    }
+    if rechecked > 0 {
+        info!("Re-read {} stored balances", rechecked);
+    }
+    rechecked
+}
+
+/// Fetches the balances of one tick, storing them in the snapshot unless `snapshot_id` is `None`
+async fn fetch_snapshot(
+    svc: &HammerService,
+    sources: &SourceRegistry,
+    limits: &FetchLimits,
+    snapshot_id: Option<i32>,
+    options: &RunOptions,
+    token: &CancellationToken,
+) -> Result<BalanceReport> {
+    let mut wallets = Vec::new();
+    for wallet in svc.query.get_wallets_with_metadata().await? {
+        if !options.includes_wallet(wallet.0.id) {
+            continue;
+        }
+        let providers = svc
+            .query
+            .get_balance_priorities(wallet.0.id)
+            .await?
+            .into_iter()
+            .map(|priority| DataProvider::from(priority.provider))
+            .collect();
+        wallets.push((wallet, providers));
+    }
+    let (assigned, skipped) = assign_sources(sources, wallets);
+    let mut report = BalanceReport {
+        skipped,
+        snapshot_id,
+        ..Default::default()
+    };
+
+    let mut tasks = JoinSet::new();
+    for SourceAssignment {
+        provider,
+        source,
+        wallets,
+    } in assigned
+    {
+        let svc = svc.clone();
+        let limits = limits.clone();
+        let token = token.clone();
+        tasks.spawn(async move {
+            fetch_from_source(
+                &svc,
+                provider,
+                source,
+                wallets,
+                &limits,
+                snapshot_id,
+                &token,
+            )
+            .await
+        });
+    }
+    while let Some(outcomes) = tasks.join_next().await {
+        match outcomes {
+            Ok(outcomes) => report.outcomes.extend(outcomes),
+            Err(e) => error!("Balance fetch task failed: {:#}", e),
+        }
+    }
+
+    if !report.skipped.is_empty() {
+        warn!(
+            "No eligible balance source for wallets {:?}",
+            report.skipped
+        );
+    }
+    for outcome in report.failed() {
+        if let Err(e) = &outcome.result {
+            error!(
+                "Failed to fetch {:?} balance of wallet {}: {}",
+                outcome.provider, outcome.wallet_id, e
+            );
+        }
+    }
+    info!(
+        "Balance fetch completed: {} fetched, {} failed, {} skipped",
+        report.succeeded(),
+        report.failed().count(),
+        report.skipped.len()
+    );
+    Ok(report)
+}
+
+/// Wallets to fetch from one source
+struct SourceAssignment {
+    provider: DataProvider,
+    source: Arc<dyn BalanceSource>,
+    wallets: Vec<WalletWithMetadata>,
+}
+
+/// Groups wallets by the source registered for each of their prioritised providers in their
+/// scope, returning the IDs of wallets without any
+///
+/// Sources are told apart by identity rather than provider, as several sources report the
+/// same provider for different scopes, e.g. every on-chain reader.
+fn assign_sources(
+    sources: &SourceRegistry,
+    wallets: Vec<(WalletWithMetadata, Vec<DataProvider>)>,
+) -> (Vec<SourceAssignment>, Vec<i32>) {
+    let mut assigned = Vec::<SourceAssignment>::new();
+    let mut skipped = Vec::new();
+    for (wallet, providers) in wallets {
+        let scope = wallet.0.scope.into();
+        let mut eligible = false;
+        for provider in providers {
+            let Some(source) = sources.get(provider, scope) else {
+                continue;
+            };
+            eligible = true;
+            match assigned
+                .iter_mut()
+                .find(|assignment| Arc::ptr_eq(&assignment.source, &source))
+            {
+                Some(assignment) => assignment.wallets.push(wallet.clone()),
+                None => assigned.push(SourceAssignment {
+                    provider,
+                    source,
+                    wallets: vec![wallet.clone()],
+                }),
+            }
+        }
+        if !eligible {
+            skipped.push(wallet.0.id);
+        }
+    }
+    (assigned, skipped)
+}
+
+/// Fetches and stores the balances of wallets assigned to one source
+async fn fetch_from_source(
+    svc: &HammerService,
+    provider: DataProvider,
+    source: Arc<dyn BalanceSource>,
+    wallets: Vec<WalletWithMetadata>,
+    limits: &FetchLimits,
+    snapshot_id: Option<i32>,
+    token: &CancellationToken,
+) -> Vec<WalletOutcome> {
+    let prepared = {
+        let _permit = limits.acquire(provider).await;
+        tokio::time::timeout(limits.prepare_timeout(), source.prepare(svc, &wallets))
+            .await
+            .unwrap_or_else(|_| Err(anyhow!("Timed out after {:?}", limits.prepare_timeout())))
+    };
+    let prepared = match prepared {
+        Ok(prepared) => Arc::new(prepared),
+        Err(e) => {
+            let error = format!("Failed to prepare source: {e:#}");
+            return wallets
+                .iter()
+                .map(|(wallet, _)| WalletOutcome {
+                    wallet_id: wallet.id,
+                    provider,
+                    result: Err(error.clone()),
+                    balance_id: None,
+                })
+                .collect();
+        }
+    };
+
+    let mut tasks = JoinSet::new();
+    for (wallet, metadata) in wallets {
+        let svc = svc.clone();
+        let source = source.clone();
+        let prepared = prepared.clone();
+        let limits = limits.clone();
+        let token = token.clone();
+        tasks.spawn(async move {
+            let _permit = limits.acquire(provider).await;
+            let result = if token.is_cancelled() {
+                Err(anyhow!("Cancelled by shutdown"))
+            } else {
+                let fetch = fetch_wallet(
+                    &svc,
+                    source.as_ref(),
+                    &wallet,
+                    &metadata,
+                    &prepared,
+                    snapshot_id,
+                );
+                tokio::time::timeout(limits.wallet_timeout(), fetch)
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(anyhow!("Timed out after {:?}", limits.wallet_timeout()))
+                    })
+            };
+            let (result, balance_id) = match result {
+                Ok((balance, id)) => (Ok(balance), id),
+                Err(e) => (Err(format!("{e:#}")), None),
+            };
+            WalletOutcome {
+                wallet_id: wallet.id,
+                provider,
+                result,
+                balance_id,
+            }
+        });
+    }
+
+    let mut outcomes = Vec::with_capacity(tasks.len());
+    while let Some(outcome) = tasks.join_next().await {
+        match outcome {
+            Ok(outcome) => outcomes.push(outcome),
+            Err(e) => error!("{:?} wallet fetch task failed: {:#}", provider, e),
+        }
+    }
+    outcomes
+}
+
+/// Fetches one wallet and, given a snapshot, stores its balance with entries in one transaction
+async fn fetch_wallet(
+    svc: &HammerService,
+    source: &dyn BalanceSource,
+    wallet: &wallet::Model,
+    metadata: &[wallet_metadata::Model],
+    prepared: &PreparedBalances,
+    snapshot_id: Option<i32>,
+) -> Result<(FetchedBalance, Option<i32>)> {
+    let fetched = source.fetch(wallet, metadata, prepared).await?;
+    if snapshot_id.is_none() {
+        return Ok((fetched, None));
+    }
+    let (balance, entries) = fetched.clone().into_parts(wallet.id, snapshot_id);
+    let balance = svc
+        .query
+        .create_balance_with_entries(balance, entries)
+        .await?;
+    Ok((fetched, Some(balance.id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use hammer_entity::sea_orm_active_enums::AssetScope as EntityAssetScope;
+    use hammer_service::types::AssetScope;
+
+    use super::*;
+
+    /// Source that fetches nothing
+    struct FakeSource {
+        provider: DataProvider,
+        scopes: Vec<AssetScope>,
+    }
+
+    #[async_trait]
+    impl BalanceSource for FakeSource {
+        fn provider(&self) -> DataProvider {
+            self.provider
+        }
+
+        fn scopes(&self) -> Vec<AssetScope> {
+            self.scopes.clone()
+        }
+
+        async fn fetch(
+            &self,
+            _wallet: &wallet::Model,
+            _metadata: &[wallet_metadata::Model],
+            _prepared: &PreparedBalances,
+        ) -> Result<FetchedBalance> {
+            Err(anyhow!("not fetched in tests"))
+        }
+    }
+
+    fn wallet(id: i32, scope: EntityAssetScope) -> WalletWithMetadata {
+        let wallet = wallet::Model {
+            id,
+            parent_id: None,
+            scope,
+            provider: None,
+            external_id: None,
+            missing_since: None,
+        };
+        (wallet, Vec::new())
+    }
+
+    fn wallet_ids(assignment: &SourceAssignment) -> Vec<i32> {
+        assignment
+            .wallets
+            .iter()
+            .map(|(wallet, _)| wallet.id)
+            .collect()
+    }
+
+    #[test]
+    fn assign_sources_keeps_sources_of_one_provider_apart() {
+        let mut sources = SourceRegistry::new();
+        for scope in [AssetScope::Ethereum, AssetScope::Pendle2] {
+            sources.register(Arc::new(FakeSource {
+                provider: DataProvider::Onchain,
+                scopes: vec![scope],
+            }));
+        }
+        let wallets = vec![
+            (
+                wallet(1, EntityAssetScope::Ethereum),
+                vec![DataProvider::Onchain],
+            ),
+            (
+                wallet(2, EntityAssetScope::Pendle2),
+                vec![DataProvider::Onchain],
+            ),
+            (
+                wallet(3, EntityAssetScope::Ethereum),
+                vec![DataProvider::Onchain],
+            ),
+            (
+                wallet(4, EntityAssetScope::Binance),
+                vec![DataProvider::Onchain],
+            ),
+        ];
+
+        let (assigned, skipped) = assign_sources(&sources, wallets);
+        assert_eq!(assigned.len(), 2);
+        let ethereum = sources
+            .get(DataProvider::Onchain, AssetScope::Ethereum)
+            .unwrap();
+        let (ethereum, pendle): (Vec<_>, Vec<_>) = assigned
+            .iter()
+            .partition(|assignment| Arc::ptr_eq(&assignment.source, &ethereum));
+        assert_eq!(wallet_ids(ethereum[0]), vec![1, 3]);
+        assert_eq!(wallet_ids(pendle[0]), vec![2]);
+        assert_eq!(pendle[0].provider, DataProvider::Onchain);
+        assert_eq!(skipped, vec![4]);
+    }
 
-    // TODO: Implement balance fetching logic
-    // 1. Get list of wallets from database
-    // 2. Fetch balances from CAM for each wallet
-    // 3. Store balances in database
+    #[test]
+    fn assign_sources_groups_scopes_of_one_source_together() {
+        let mut sources = SourceRegistry::new();
+        sources.register(Arc::new(FakeSource {
+            provider: DataProvider::Onchain,
+            scopes: vec![AssetScope::Ethereum, AssetScope::Pendle2],
+        }));
+        let wallets = vec![
+            (
+                wallet(1, EntityAssetScope::Ethereum),
+                vec![DataProvider::Onchain],
+            ),
+            (
+                wallet(2, EntityAssetScope::Pendle2),
+                vec![DataProvider::Onchain],
+            ),
+            (wallet(3, EntityAssetScope::Ethereum), Vec::new()),
+        ];
 
-    info!("Balance fetch completed");
-    Ok(())
+        let (assigned, skipped) = assign_sources(&sources, wallets);
+        assert_eq!(assigned.len(), 1);
+        assert_eq!(wallet_ids(&assigned[0]), vec![1, 2]);
+        assert_eq!(skipped, vec![3]);
+    }
 }
@@ -0,0 +1,180 @@
+//! Historical prices read from Chainlink price feeds
+
+use std::collections::HashMap;
+
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use chain_client::{abi::Address, types::BlockTag};
+use hammer_service::types::DataProvider;
+use rust_decimal::Decimal;
+use time::{Duration, OffsetDateTime};
+
+use crate::{
+    onchain::OnchainReader, price_backfill::PriceHistorySource, price_source::QuotedPrice,
+};
+
+/// Timestamps read per request, each costing a block search and a call
+const MAX_POINTS: usize = 100;
+
+/// Reads the answer of a currency's Chainlink feed at the last confirmed block at or before
+/// each requested time
+///
+/// Feeds must quote in the same unit as the other price sources, e.g. USD. Times before a feed
+/// was deployed or after the confirmed head are left unfilled.
+pub struct ChainlinkHistory {
+    onchain: OnchainReader,
+    feeds: HashMap<String, Address>,
+}
+
+impl ChainlinkHistory {
+    pub fn new(onchain: OnchainReader, feeds: HashMap<String, Address>) -> Self {
+        Self { onchain, feeds }
+    }
+
+    /// Creates a source for the feeds in `CHAINLINK_FEEDS`, e.g. `BTC=0x...,ETH=0x...`
+    pub fn from_env(onchain: OnchainReader) -> Result<Self> {
+        let feeds = std::env::var("CHAINLINK_FEEDS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|feed| !feed.is_empty())
+            .map(|feed| {
+                let (currency, address) = feed
+                    .split_once('=')
+                    .ok_or_else(|| anyhow!("Invalid CHAINLINK_FEEDS entry {feed:?}"))?;
+                Ok((currency.trim().to_owned(), address.trim().parse()?))
+            })
+            .collect::<Result<HashMap<_, _>>>()?;
+        Ok(Self::new(onchain, feeds))
+    }
+}
+
+#[async_trait]
+impl PriceHistorySource for ChainlinkHistory {
+    fn provider(&self) -> DataProvider {
+        DataProvider::Onchain
+    }
+
+    fn max_points(&self) -> usize {
+        MAX_POINTS
+    }
+
+    async fn fetch_history(
+        &self,
+        currency: &str,
+        start: OffsetDateTime,
+        end: OffsetDateTime,
+        granularity: Duration,
+    ) -> Result<Vec<QuotedPrice>> {
+        let feed = *self
+            .feeds
+            .get(currency)
+            .ok_or_else(|| anyhow!("No Chainlink feed configured for {currency}"))?;
+        let client = self.onchain.client();
+        let head = client.pin_block(self.onchain.confirmations()).await?;
+        let decimals = client
+            .read_feed_decimals(feed, BlockTag::Number(head.number))
+            .await?;
+        let mut lo = client.get_block(BlockTag::Number(0)).await?;
+
+        let mut prices = Vec::new();
+        let mut time = start;
+        while time <= end {
+            let timestamp = u64::try_from(time.unix_timestamp())
+                .ok()
+                .filter(|timestamp| (lo.timestamp..=head.timestamp).contains(timestamp));
+            if let Some(timestamp) = timestamp {
+                lo = client
+                    .block_at_timestamp_between(timestamp, lo, head)
+                    .await?;
+                if let Some(answer) = client
+                    .read_feed_answer(feed, decimals, BlockTag::Number(lo.number))
+                    .await?
+                {
+                    prices.push(QuotedPrice {
+                        time,
+                        value: answer.price,
+                        liquidity: Decimal::ZERO,
+                    });
+                }
+            }
+            time += granularity;
+        }
+        Ok(prices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use chain_client::{
+        abi::encode_uint,
+        testing::{BLOCK_TIME, GENESIS_TIME, HEAD, local_node},
+    };
+
+    use super::*;
+
+    const FEED: Address = Address([0x60; 20]);
+
+    /// `latestRoundData()`
+    const LATEST_ROUND_DATA: [u8; 4] = [0xfe, 0xaf, 0x96, 0x8c];
+    /// `decimals()`
+    const DECIMALS: [u8; 4] = [0x31, 0x3c, 0xe5, 0x67];
+
+    fn at_block(number: u64) -> OffsetDateTime {
+        OffsetDateTime::from_unix_timestamp((GENESIS_TIME + number * BLOCK_TIME) as i64).unwrap()
+    }
+
+    async fn history(calls: Arc<Mutex<usize>>) -> ChainlinkHistory {
+        let client = local_node(Arc::new(move |target, data: &[u8]| {
+            assert_eq!(target, FEED);
+            match &data[..4] {
+                s if s == DECIMALS => Some(encode_uint(8).to_vec()),
+                s if s == LATEST_ROUND_DATA => {
+                    *calls.lock().unwrap() += 1;
+                    Some(
+                        [
+                            encode_uint(1),
+                            encode_uint(200_000_000_000),
+                            encode_uint(0),
+                            encode_uint(0),
+                            encode_uint(1),
+                        ]
+                        .concat(),
+                    )
+                }
+                _ => None,
+            }
+        }))
+        .await;
+        let onchain = OnchainReader::new(client).with_confirmations(10);
+        ChainlinkHistory::new(onchain, HashMap::from([("ETH".to_owned(), FEED)]))
+    }
+
+    #[tokio::test]
+    async fn reads_confirmed_times_only() {
+        let calls = Arc::new(Mutex::new(0));
+        let history = history(Arc::clone(&calls)).await;
+        let granularity = Duration::seconds((20 * BLOCK_TIME) as i64);
+
+        // Blocks 10, 30, 50, 70 and 90 are confirmed, block 110 is past the head
+        let prices = history
+            .fetch_history("ETH", at_block(10), at_block(HEAD + 10), granularity)
+            .await
+            .unwrap();
+        assert_eq!(
+            prices.iter().map(|p| p.time).collect::<Vec<_>>(),
+            [10, 30, 50, 70, 90].map(at_block)
+        );
+        assert!(prices.iter().all(|p| p.value == Decimal::new(2000, 0)));
+        assert_eq!(*calls.lock().unwrap(), 5);
+
+        assert!(
+            history
+                .fetch_history("BTC", at_block(10), at_block(20), granularity)
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,179 @@
+//! Periodic jobs run by the worker
+
+use std::{fmt, str::FromStr};
+
+use anyhow::anyhow;
+
+use crate::{
+    balance_worker::BalanceReport, price_worker::PriceReport, staleness::StalenessReport,
+    wallet_worker::SyncReport,
+};
+
+/// Maximum length of an error summary recorded for a run
+const MAX_ERROR_SUMMARY_LEN: usize = 2000;
+
+/// A periodic job
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Job {
+    Balances,
+    Prices,
+    Wallets,
+    Staleness,
+}
+
+impl Job {
+    pub const ALL: [Job; 4] = [Job::Balances, Job::Prices, Job::Wallets, Job::Staleness];
+
+    /// Name of the job, also used as the prefix of its configuration variables
+    pub fn name(&self) -> &'static str {
+        match self {
+            Job::Balances => "balances",
+            Job::Prices => "prices",
+            Job::Wallets => "wallets",
+            Job::Staleness => "staleness",
+        }
+    }
+}
+
+impl fmt::Display for Job {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Job {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Job::ALL
+            .into_iter()
+            .find(|job| job.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| anyhow!("Unknown job {s}"))
+    }
+}
+
+/// Options restricting what a job run does
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    /// Fetch and report without writing to the database
+    pub dry_run: bool,
+    /// Wallets whose balances are fetched, all when empty
+    pub wallet_ids: Vec<i32>,
+    /// Canonical currencies priced, all held ones when empty
+    pub currencies: Vec<String>,
+}
+
+impl RunOptions {
+    pub fn includes_wallet(&self, wallet_id: i32) -> bool {
+        self.wallet_ids.is_empty() || self.wallet_ids.contains(&wallet_id)
+    }
+
+    pub fn includes_currency(&self, currency: &str) -> bool {
+        self.currencies.is_empty()
+            || self
+                .currencies
+                .iter()
+                .any(|c| c.eq_ignore_ascii_case(currency))
+    }
+}
+
+/// Report of one run of a job
+#[derive(Debug)]
+pub enum JobOutput {
+    Balances(BalanceReport),
+    Prices(PriceReport),
+    Wallets(SyncReport),
+    Staleness(StalenessReport),
+}
+
+impl JobOutput {
+    /// Summarises the run for `job_run`, `cancelled` telling whether it was asked to stop
+    pub fn summary(&self, cancelled: bool) -> TickSummary {
+        match self {
+            JobOutput::Balances(report) => TickSummary {
+                wallets_processed: Some(report.outcomes.len() as i32),
+                error_summary: summarize_errors(report.outcomes.iter().filter_map(|outcome| {
+                    let e = outcome.result.as_ref().err()?;
+                    Some(format!(
+                        "wallet {} ({:?}): {}",
+                        outcome.wallet_id, outcome.provider, e
+                    ))
+                })),
+                interrupted: cancelled,
+            },
+            // Prices are fetched in one batch that is never cut short
+            JobOutput::Prices(report) => TickSummary {
+                error_summary: summarize_errors(
+                    report
+                        .unpriced
+                        .iter()
+                        .map(|currency| format!("{currency} unpriced"))
+                        .chain(
+                            report
+                                .unmapped
+                                .iter()
+                                .map(|currency| format!("{currency} unmapped")),
+                        )
+                        .chain(
+                            report
+                                .failed_providers
+                                .iter()
+                                .map(|(provider, e)| format!("{provider:?}: {e}")),
+                        ),
+                ),
+                ..Default::default()
+            },
+            JobOutput::Wallets(report) => TickSummary {
+                wallets_processed: Some(report.accounts as i32),
+                error_summary: summarize_errors(
+                    report
+                        .failed_providers
+                        .iter()
+                        .map(|(provider, e)| format!("{provider:?}: {e}")),
+                ),
+                interrupted: cancelled,
+            },
+            // Stale data is reported to the notifiers, only their failures are errors
+            JobOutput::Staleness(report) => TickSummary {
+                error_summary: summarize_errors(
+                    report
+                        .failed_notifiers
+                        .iter()
+                        .map(|(notifier, e)| format!("{notifier}: {e}")),
+                ),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// What one tick of a job did, recorded in `job_run`
+#[derive(Debug, Default)]
+pub struct TickSummary {
+    pub wallets_processed: Option<i32>,
+    /// Errors that did not fail the tick as a whole, e.g. individual wallets
+    pub error_summary: Option<String>,
+    /// Whether the tick stopped early because of a shutdown
+    pub interrupted: bool,
+}
+
+/// Joins errors into a summary short enough to store
+pub fn summarize_errors<I>(errors: I) -> Option<String>
+where
+    I: IntoIterator<Item = String>,
+{
+    let errors = errors.into_iter().collect::<Vec<_>>();
+    if errors.is_empty() {
+        return None;
+    }
+    let mut summary = format!("{} errors: {}", errors.len(), errors.join("; "));
+    if summary.len() > MAX_ERROR_SUMMARY_LEN {
+        let mut end = MAX_ERROR_SUMMARY_LEN;
+        while !summary.is_char_boundary(end) {
+            end -= 1;
+        }
+        summary.truncate(end);
+        summary.push_str("...");
+    }
+    Some(summary)
+}
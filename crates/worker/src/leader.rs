@@ -0,0 +1,112 @@
+//! Leader election between worker replicas through per-job leases
+
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use hammer_service::HammerService;
+use time::OffsetDateTime;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, warn};
+
+use crate::job::Job;
+
+/// Default time a lease stays valid without renewal
+const DEFAULT_LEASE_TTL: Duration = Duration::from_secs(60);
+
+/// Elects one replica to run each tick of a job
+///
+/// A replica runs a tick only after taking the job's lease for that tick, so a tick runs at
+/// most once across replicas as long as they share the same aligned or cron schedule. The
+/// lease is renewed while the tick runs; when its holder dies it expires after the TTL and the
+/// next tick is taken by another replica.
+#[derive(Debug, Clone)]
+pub struct LeaderElection {
+    holder: String,
+    ttl: Duration,
+}
+
+impl LeaderElection {
+    pub fn new(holder: String) -> Self {
+        Self {
+            holder,
+            ttl: DEFAULT_LEASE_TTL,
+        }
+    }
+
+    /// Creates an election from the environment, or `None` when `LEADER_ELECTION` is off
+    ///
+    /// The holder is `WORKER_ID`, defaulting to the host name and process ID, and the TTL is
+    /// `LEASE_TTL`, e.g. `60s`.
+    pub fn from_env() -> Result<Option<Self>> {
+        let enabled = std::env::var("LEADER_ELECTION")
+            .map(|value| !matches!(value.to_ascii_lowercase().as_str(), "0" | "false" | "off"))
+            .unwrap_or(true);
+        if !enabled {
+            return Ok(None);
+        }
+
+        let holder = std::env::var("WORKER_ID").unwrap_or_else(|_| {
+            let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "worker".to_owned());
+            format!("{host}-{}", std::process::id())
+        });
+        let mut election = Self::new(holder);
+        if let Ok(ttl) = std::env::var("LEASE_TTL") {
+            election = election.with_ttl(humantime::parse_duration(&ttl)?);
+        }
+        if election.ttl < Duration::from_secs(3) {
+            return Err(anyhow!("LEASE_TTL must be at least 3s"));
+        }
+        Ok(Some(election))
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn holder(&self) -> &str {
+        &self.holder
+    }
+
+    /// Takes the lease of a job for the tick scheduled at `tick`
+    pub async fn acquire(&self, svc: &HammerService, job: Job, tick: OffsetDateTime) -> bool {
+        match svc
+            .query
+            .acquire_job_lease(job.name(), &self.holder, tick, self.ttl)
+            .await
+        {
+            Ok(acquired) => acquired,
+            Err(e) => {
+                error!("Failed to acquire {} lease: {:#}", job, e);
+                false
+            }
+        }
+    }
+
+    /// Renews the lease until the future is dropped, cancelling `token` if the lease is lost
+    pub async fn hold(&self, svc: &HammerService, job: Job, token: &CancellationToken) {
+        loop {
+            tokio::time::sleep(self.ttl / 3).await;
+            match svc
+                .query
+                .renew_job_lease(job.name(), &self.holder, self.ttl)
+                .await
+            {
+                Ok(true) => {}
+                Ok(false) => {
+                    warn!("Lost {} lease, stopping the running tick", job);
+                    token.cancel();
+                    return std::future::pending().await;
+                }
+                Err(e) => error!("Failed to renew {} lease: {:#}", job, e),
+            }
+        }
+    }
+
+    /// Releases every lease so that other replicas take over without waiting for expiry
+    pub async fn release(&self, svc: &HammerService) {
+        if let Err(e) = svc.query.release_job_leases(&self.holder).await {
+            error!("Failed to release job leases: {:#}", e);
+        }
+    }
+}
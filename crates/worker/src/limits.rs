@@ -0,0 +1,140 @@
+//! Concurrency limits and timeouts for provider calls
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use anyhow::{Result, anyhow};
+use hammer_entity::sea_orm_active_enums::DataProvider as EntityDataProvider;
+use hammer_service::types::DataProvider;
+use sea_orm::Iterable;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Default number of wallets fetched at once across all providers
+///
+/// Kept below the database pool size, as every fetched wallet is stored in a transaction.
+const DEFAULT_GLOBAL_CONCURRENCY: usize = 8;
+
+/// Default number of wallets fetched at once from one provider
+const DEFAULT_PROVIDER_CONCURRENCY: usize = 4;
+
+/// Default time one wallet may take to be fetched and stored
+const DEFAULT_WALLET_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default time a source may take to prepare a batch, e.g. one multicall per block for every
+/// wallet of the tick
+const DEFAULT_PREPARE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Limits how many wallets are fetched at once, globally and per provider
+///
+/// Limits are shared by every tick, so a slow tick still running does not double the load.
+#[derive(Debug, Clone)]
+pub struct FetchLimits {
+    global: Arc<Semaphore>,
+    providers: HashMap<DataProvider, Arc<Semaphore>>,
+    wallet_timeout: Duration,
+    prepare_timeout: Duration,
+}
+
+impl Default for FetchLimits {
+    fn default() -> Self {
+        Self::new(DEFAULT_GLOBAL_CONCURRENCY, DEFAULT_PROVIDER_CONCURRENCY)
+    }
+}
+
+impl FetchLimits {
+    /// Creates limits with the same concurrency for every provider
+    pub fn new(global: usize, per_provider: usize) -> Self {
+        Self {
+            global: Arc::new(Semaphore::new(global)),
+            providers: EntityDataProvider::iter()
+                .map(|provider| (provider.into(), Arc::new(Semaphore::new(per_provider))))
+                .collect(),
+            wallet_timeout: DEFAULT_WALLET_TIMEOUT,
+            prepare_timeout: DEFAULT_PREPARE_TIMEOUT,
+        }
+    }
+
+    /// Creates limits from the environment
+    ///
+    /// Reads `FETCH_CONCURRENCY` for the global limit, `FETCH_CONCURRENCY_<PROVIDER>`
+    /// (e.g. `FETCH_CONCURRENCY_ONCHAIN`) for each provider, `WALLET_FETCH_TIMEOUT`, e.g. `30s`,
+    /// and `PREPARE_TIMEOUT` for preparing a batch of wallets, e.g. `5m`.
+    pub fn from_env() -> Result<Self> {
+        let global = concurrency_from_env("FETCH_CONCURRENCY")?;
+        let mut limits = Self::new(
+            global.unwrap_or(DEFAULT_GLOBAL_CONCURRENCY),
+            DEFAULT_PROVIDER_CONCURRENCY,
+        );
+        for (provider, semaphore) in &mut limits.providers {
+            let key = format!("FETCH_CONCURRENCY_{provider:?}").to_uppercase();
+            if let Some(limit) = concurrency_from_env(&key)? {
+                *semaphore = Arc::new(Semaphore::new(limit));
+            }
+        }
+        if let Ok(timeout) = std::env::var("WALLET_FETCH_TIMEOUT") {
+            limits.wallet_timeout = humantime::parse_duration(&timeout)
+                .map_err(|e| anyhow!("Invalid WALLET_FETCH_TIMEOUT {timeout:?}: {e}"))?;
+        }
+        if let Ok(timeout) = std::env::var("PREPARE_TIMEOUT") {
+            limits.prepare_timeout = humantime::parse_duration(&timeout)
+                .map_err(|e| anyhow!("Invalid PREPARE_TIMEOUT {timeout:?}: {e}"))?;
+        }
+        Ok(limits)
+    }
+
+    pub fn with_provider_limit(mut self, provider: DataProvider, limit: usize) -> Self {
+        self.providers
+            .insert(provider, Arc::new(Semaphore::new(limit)));
+        self
+    }
+
+    pub fn with_wallet_timeout(mut self, timeout: Duration) -> Self {
+        self.wallet_timeout = timeout;
+        self
+    }
+
+    pub fn wallet_timeout(&self) -> Duration {
+        self.wallet_timeout
+    }
+
+    pub fn with_prepare_timeout(mut self, timeout: Duration) -> Self {
+        self.prepare_timeout = timeout;
+        self
+    }
+
+    /// Get the time a source may take to prepare every wallet of a tick at once
+    pub fn prepare_timeout(&self) -> Duration {
+        self.prepare_timeout
+    }
+
+    /// Waits for a slot of the provider and a global slot, held until the permit is dropped
+    pub async fn acquire(&self, provider: DataProvider) -> FetchPermit {
+        // Semaphores are never closed, so acquiring cannot fail
+        let provider = match self.providers.get(&provider) {
+            Some(semaphore) => Some(semaphore.clone().acquire_owned().await.unwrap()),
+            None => None,
+        };
+        let global = self.global.clone().acquire_owned().await.unwrap();
+        FetchPermit {
+            _provider: provider,
+            _global: global,
+        }
+    }
+}
+
+/// Slot of a running fetch, released on drop
+pub struct FetchPermit {
+    _provider: Option<OwnedSemaphorePermit>,
+    _global: OwnedSemaphorePermit,
+}
+
+fn concurrency_from_env(key: &str) -> Result<Option<usize>> {
+    let Ok(value) = std::env::var(key) else {
+        return Ok(None);
+    };
+    match value.parse() {
+        Ok(0) | Err(_) => Err(anyhow!(
+            "Invalid {key} {value:?}: expected a positive integer"
+        )),
+        Ok(limit) => Ok(Some(limit)),
+    }
+}
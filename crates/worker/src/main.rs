@@ -1,10 +1,390 @@
 //! Hammer Assets Worker Binary
 //!
-//! This binary runs the periodic data fetching workers.
+//! This binary runs the periodic data fetching workers, or one job once.
 
-use hammer_worker::run;
+use std::time::Duration;
+
+use anyhow::{Result, anyhow, bail};
+use clap::{Args, Parser, Subcommand};
+use hammer_entity::{refresh_request, sea_orm_active_enums::DataProvider as EntityDataProvider};
+use hammer_service::types::{DataProvider, RefreshStatus, RefreshTarget};
+use hammer_worker::{
+    WorkerBuilder,
+    balance_backfill::BalanceBackfill,
+    job::{Job, JobOutput, RunOptions},
+    price_backfill::PriceBackfill,
+    shutdown,
+};
+use sea_orm::Iterable;
+use time::{
+    Date, OffsetDateTime,
+    format_description::well_known::{Iso8601, Rfc3339},
+};
+use tracing::{error, info};
+
+/// Time between status checks while waiting for a refresh request
+const REFRESH_WAIT_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Parser)]
+#[command(version, about = "Hammer Assets Worker")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run every enabled job on its schedule until stopped (default)
+    Daemon,
+    /// Run one job once and exit
+    Run {
+        /// Job to run: balances, prices, wallets or staleness
+        job: Job,
+        /// Fetch and print without writing to the database
+        #[arg(long)]
+        dry_run: bool,
+        /// Only fetch or check balances of these wallet IDs
+        #[arg(long = "wallet", value_name = "ID", value_delimiter = ',')]
+        wallet_ids: Vec<i32>,
+        /// Only price or check these currencies
+        #[arg(long = "currency", value_name = "CURRENCY", value_delimiter = ',')]
+        currencies: Vec<String>,
+    },
+    /// Queue an on-demand refresh for the running workers and print its request ID
+    Refresh {
+        #[command(flatten)]
+        target: RefreshArgs,
+        /// Wait for the request to finish and print its result
+        #[arg(long)]
+        wait: bool,
+    },
+    /// Print the status and result of a refresh request
+    RefreshStatus {
+        /// ID of the refresh request
+        id: i32,
+    },
+    /// Fetch historical prices of a currency over a range, resuming an interrupted backfill
+    BackfillPrices {
+        /// Currency to backfill
+        #[arg(long)]
+        currency: String,
+        /// Provider to fetch prices from, e.g. onchain for the Chainlink feeds in `CHAINLINK_FEEDS`
+        #[arg(long, value_parser = parse_provider)]
+        provider: DataProvider,
+        /// Start of the range, as RFC 3339 or a date at midnight UTC
+        #[arg(long, value_parser = parse_time)]
+        from: OffsetDateTime,
+        /// End of the range, inclusive
+        #[arg(long, value_parser = parse_time)]
+        to: OffsetDateTime,
+        /// Time between two prices, e.g. 1h or 1day
+        #[arg(long, default_value = "1h", value_parser = humantime::parse_duration)]
+        granularity: Duration,
+    },
+    /// Read historical on-chain balances of a wallet over a range, skipping stored times
+    BackfillBalances {
+        /// ID of the Ethereum wallet to backfill
+        #[arg(long, value_name = "ID")]
+        wallet: i32,
+        /// Start of the range, as RFC 3339 or a date at midnight UTC
+        #[arg(long, value_parser = parse_time)]
+        from: OffsetDateTime,
+        /// End of the range, inclusive
+        #[arg(long, value_parser = parse_time)]
+        to: OffsetDateTime,
+        /// Time between two balances, e.g. 1day
+        #[arg(long, default_value = "1day", value_parser = humantime::parse_duration)]
+        step: Duration,
+    },
+}
+
+#[derive(Args)]
+#[group(required = true, multiple = false)]
+struct RefreshArgs {
+    /// Refresh the balances of a wallet
+    #[arg(long, value_name = "ID")]
+    wallet: Option<i32>,
+    /// Refresh the price of a currency
+    #[arg(long, value_name = "CURRENCY")]
+    currency: Option<String>,
+    /// Refresh every balance and price
+    #[arg(long)]
+    all: bool,
+}
+
+impl RefreshArgs {
+    fn target(self) -> RefreshTarget {
+        match (self.wallet, self.currency) {
+            (Some(wallet_id), _) => RefreshTarget::Wallet(wallet_id),
+            (_, Some(currency)) => RefreshTarget::Price(currency),
+            _ => RefreshTarget::All,
+        }
+    }
+}
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    run()
+async fn main() -> Result<()> {
+    let command = Cli::parse().command.unwrap_or(Command::Daemon);
+
+    // Initialize tracing
+    tracing_subscriber::fmt::init();
+
+    // Load environment variables
+    dotenvy::dotenv().ok();
+
+    match command {
+        Command::Daemon => {
+            info!("Starting Hammer Assets Worker");
+            let svc = hammer_worker::connect_from_env().await?;
+            let worker = WorkerBuilder::from_env(svc)?
+                .with_shutdown(shutdown_signal())
+                .spawn();
+            worker.wait().await?;
+            Ok(())
+        }
+        Command::Run {
+            job,
+            dry_run,
+            wallet_ids,
+            currencies,
+        } => {
+            if !wallet_ids.is_empty() && !matches!(job, Job::Balances | Job::Staleness) {
+                bail!("--wallet only applies to the balances and staleness jobs");
+            }
+            if !currencies.is_empty() && !matches!(job, Job::Prices | Job::Staleness) {
+                bail!("--currency only applies to the prices and staleness jobs");
+            }
+            let options = RunOptions {
+                dry_run,
+                wallet_ids,
+                currencies,
+            };
+            let svc = hammer_worker::connect_from_env().await?;
+            let output = WorkerBuilder::from_env(svc)?
+                .with_shutdown(shutdown_signal())
+                .run_once(job, options)
+                .await?;
+            print_output(&output);
+            Ok(())
+        }
+        Command::Refresh { target, wait } => {
+            let svc = hammer_worker::connect_from_env().await?;
+            let mut request = svc.query.create_refresh_request(target.target()).await?;
+            println!("queued refresh request {}", request.id);
+            if !wait {
+                return Ok(());
+            }
+            while matches!(
+                RefreshStatus::from(request.status),
+                RefreshStatus::Pending | RefreshStatus::Running
+            ) {
+                tokio::time::sleep(REFRESH_WAIT_INTERVAL).await;
+                request = svc
+                    .query
+                    .get_refresh_request_by_id(request.id)
+                    .await?
+                    .ok_or_else(|| anyhow!("Refresh request {} was deleted", request.id))?;
+            }
+            print_refresh_request(&request);
+            Ok(())
+        }
+        Command::RefreshStatus { id } => {
+            let svc = hammer_worker::connect_from_env().await?;
+            let request = svc
+                .query
+                .get_refresh_request_by_id(id)
+                .await?
+                .ok_or_else(|| anyhow!("Refresh request {id} not found"))?;
+            print_refresh_request(&request);
+            Ok(())
+        }
+        Command::BackfillPrices {
+            currency,
+            provider,
+            from,
+            to,
+            granularity,
+        } => {
+            let backfill = PriceBackfill {
+                currency,
+                provider,
+                start: from,
+                end: to,
+                granularity: granularity.try_into()?,
+            };
+            let svc = hammer_worker::connect_from_env().await?;
+            let report = WorkerBuilder::from_env(svc)?
+                .with_shutdown(shutdown_signal())
+                .backfill_prices(backfill)
+                .await?;
+            if let Some(cursor) = report.resumed_from {
+                println!("resumed from {cursor}");
+            }
+            println!(
+                "backfill {}: {} inserted, {} skipped, {} unavailable in {} requests",
+                report.backfill_id,
+                report.inserted,
+                report.skipped,
+                report.unavailable,
+                report.requests
+            );
+            if !report.complete {
+                println!("interrupted, run again to resume");
+            }
+            Ok(())
+        }
+        Command::BackfillBalances {
+            wallet,
+            from,
+            to,
+            step,
+        } => {
+            let backfill = BalanceBackfill {
+                wallet_id: wallet,
+                start: from,
+                end: to,
+                step: step.try_into()?,
+            };
+            let svc = hammer_worker::connect_from_env().await?;
+            let report = WorkerBuilder::from_env(svc)?
+                .with_shutdown(shutdown_signal())
+                .backfill_balances(backfill)
+                .await?;
+            println!(
+                "wallet {wallet}: {} inserted, {} skipped",
+                report.inserted, report.skipped
+            );
+            if !report.complete {
+                println!("interrupted, run again to resume");
+            }
+            Ok(())
+        }
+    }
+}
+
+fn parse_provider(value: &str) -> Result<DataProvider, String> {
+    EntityDataProvider::iter()
+        .map(DataProvider::from)
+        .find(|provider| format!("{provider:?}").eq_ignore_ascii_case(value))
+        .ok_or_else(|| format!("unknown provider {value:?}"))
+}
+
+fn parse_time(value: &str) -> Result<OffsetDateTime, String> {
+    OffsetDateTime::parse(value, &Rfc3339)
+        .or_else(|_| Date::parse(value, &Iso8601::DEFAULT).map(|date| date.midnight().assume_utc()))
+        .map_err(|e| format!("invalid time {value:?}: {e}"))
+}
+
+/// Waits for SIGINT or SIGTERM, or shuts down at once if they cannot be listened to
+async fn shutdown_signal() {
+    if let Err(e) = shutdown::shutdown_signal().await {
+        error!("Failed to listen for shutdown signals: {:#}", e);
+    }
+}
+
+fn print_refresh_request(request: &refresh_request::Model) {
+    println!(
+        "refresh request {} ({:?}): {:?}",
+        request.id,
+        request.target,
+        RefreshStatus::from(request.status)
+    );
+    if let Some(id) = request.balance_id {
+        println!("balance {id}");
+    }
+    if let Some(id) = request.snapshot_id {
+        println!("snapshot {id}");
+    }
+    if let Some(error) = &request.error {
+        println!("errors: {error}");
+    }
+}
+
+fn print_output(output: &JobOutput) {
+    match output {
+        JobOutput::Balances(report) => {
+            let mut outcomes = report.outcomes.iter().collect::<Vec<_>>();
+            outcomes.sort_by_key(|outcome| outcome.wallet_id);
+            for outcome in outcomes {
+                let balance = match &outcome.result {
+                    Ok(balance) => balance,
+                    Err(e) => {
+                        println!(
+                            "wallet {} {:?}: failed: {}",
+                            outcome.wallet_id, outcome.provider, e
+                        );
+                        continue;
+                    }
+                };
+                let block = balance
+                    .block_number
+                    .map(|block| format!(" block {block}"))
+                    .unwrap_or_default();
+                let stored = outcome
+                    .balance_id
+                    .map(|id| format!(" stored as balance {id}"))
+                    .unwrap_or_default();
+                println!(
+                    "wallet {} {:?} at {}{}{}",
+                    outcome.wallet_id, outcome.provider, balance.time, block, stored
+                );
+                for entry in &balance.entries {
+                    println!("  {} {}", entry.raw_currency, entry.amount);
+                }
+            }
+            if !report.skipped.is_empty() {
+                println!("skipped wallets without a source: {:?}", report.skipped);
+            }
+            if let Some(id) = report.snapshot_id {
+                println!("stored in snapshot {id}");
+            }
+            if report.rechecked > 0 {
+                println!("re-read {} stored balances", report.rechecked);
+            }
+        }
+        JobOutput::Prices(report) => {
+            for price in &report.prices {
+                println!(
+                    "{} {} (liquidity {}) from {:?} at {}",
+                    price.currency, price.value, price.liquidity, price.provider, price.time
+                );
+            }
+            if !report.unpriced.is_empty() {
+                println!("unpriced: {}", report.unpriced.join(", "));
+            }
+            if !report.unmapped.is_empty() {
+                println!("unmapped: {}", report.unmapped.join(", "));
+            }
+            println!("{} prices stored", report.stored);
+        }
+        JobOutput::Wallets(report) => {
+            for change in &report.changes {
+                println!("{change:?}");
+            }
+            for (provider, e) in &report.failed_providers {
+                println!("{provider:?}: failed: {e}");
+            }
+            let verb = if report.dry_run { "planned" } else { "applied" };
+            println!(
+                "{} accounts listed, {} changes {}",
+                report.accounts,
+                report.changes.len(),
+                verb
+            );
+        }
+        JobOutput::Staleness(report) => {
+            for alert in &report.alerts {
+                println!("{alert:?}");
+            }
+            for (notifier, e) in &report.failed_notifiers {
+                println!("{notifier}: failed: {e}");
+            }
+            println!(
+                "{} balances and {} prices checked, {} stale",
+                report.balances_checked,
+                report.prices_checked,
+                report.alerts.len()
+            );
+        }
+    }
 }
@@ -0,0 +1,197 @@
+//! Prometheus metrics of the worker
+
+use std::time::Duration;
+
+use anyhow::Result;
+use hammer_service::{
+    HammerService,
+    types::{DataProvider, JobStatus},
+};
+use prometheus::{
+    Encoder, GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder,
+};
+use time::OffsetDateTime;
+
+use crate::job::{Job, JobOutput};
+
+/// Metrics of job runs and data freshness, in their own registry
+#[derive(Clone)]
+pub struct WorkerMetrics {
+    registry: Registry,
+    job_duration: HistogramVec,
+    rows_written: IntCounterVec,
+    provider_errors: IntCounterVec,
+    balance_age: GaugeVec,
+    price_age: GaugeVec,
+}
+
+impl Default for WorkerMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WorkerMetrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+        let job_duration = HistogramVec::new(
+            HistogramOpts::new("hammer_job_duration_seconds", "Duration of job ticks").buckets(
+                vec![0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0],
+            ),
+            &["job", "status"],
+        )
+        .unwrap();
+        let rows_written = IntCounterVec::new(
+            Opts::new("hammer_rows_written_total", "Rows written by jobs"),
+            &["job", "table"],
+        )
+        .unwrap();
+        let provider_errors = IntCounterVec::new(
+            Opts::new(
+                "hammer_provider_errors_total",
+                "Failed provider calls, per wallet for balances and per batch for prices",
+            ),
+            &["job", "provider"],
+        )
+        .unwrap();
+        let balance_age = GaugeVec::new(
+            Opts::new(
+                "hammer_balance_age_seconds",
+                "Age of the newest balance of a wallet",
+            ),
+            &["wallet_id"],
+        )
+        .unwrap();
+        let price_age = GaugeVec::new(
+            Opts::new(
+                "hammer_price_age_seconds",
+                "Age of the newest price of a currency",
+            ),
+            &["currency"],
+        )
+        .unwrap();
+
+        // Names are unique within the registry, so registration cannot fail
+        registry.register(Box::new(job_duration.clone())).unwrap();
+        registry.register(Box::new(rows_written.clone())).unwrap();
+        registry
+            .register(Box::new(provider_errors.clone()))
+            .unwrap();
+        registry.register(Box::new(balance_age.clone())).unwrap();
+        registry.register(Box::new(price_age.clone())).unwrap();
+
+        Self {
+            registry,
+            job_duration,
+            rows_written,
+            provider_errors,
+            balance_age,
+            price_age,
+        }
+    }
+
+    /// Registry of the metrics, e.g. to serve them next to those of an embedding process
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// Records a finished tick, with its report unless it failed
+    pub fn record_tick(
+        &self,
+        job: Job,
+        status: JobStatus,
+        duration: Duration,
+        output: Option<&JobOutput>,
+    ) {
+        let status = format!("{status:?}").to_lowercase();
+        self.job_duration
+            .with_label_values(&[job.name(), status.as_str()])
+            .observe(duration.as_secs_f64());
+
+        let Some(output) = output else {
+            return;
+        };
+        let mut rows = Vec::new();
+        let mut errors = Vec::new();
+        match output {
+            JobOutput::Balances(report) => {
+                let stored = report
+                    .outcomes
+                    .iter()
+                    .filter(|outcome| outcome.balance_id.is_some());
+                let entries = stored
+                    .clone()
+                    .filter_map(|outcome| outcome.result.as_ref().ok())
+                    .map(|balance| balance.entries.len() as u64)
+                    .sum();
+                rows.push(("balance", stored.count() as u64));
+                rows.push(("balance_entry", entries));
+                errors.extend(report.failed().map(|outcome| outcome.provider));
+            }
+            JobOutput::Prices(report) => {
+                rows.push(("price", report.stored));
+                errors.extend(
+                    report
+                        .failed_providers
+                        .iter()
+                        .map(|(provider, _)| *provider),
+                );
+            }
+            JobOutput::Wallets(report) => {
+                if !report.dry_run {
+                    rows.push(("wallet", report.changes.len() as u64));
+                }
+                errors.extend(
+                    report
+                        .failed_providers
+                        .iter()
+                        .map(|(provider, _)| *provider),
+                );
+            }
+            JobOutput::Staleness(_) => {}
+        }
+        for (table, count) in rows {
+            self.rows_written
+                .with_label_values(&[job.name(), table])
+                .inc_by(count);
+        }
+        for provider in errors {
+            self.provider_errors
+                .with_label_values(&[job.name(), provider_label(provider).as_str()])
+                .inc();
+        }
+    }
+
+    /// Updates the age of the newest balance of every wallet and price of every currency
+    pub async fn refresh_ages(&self, svc: &HammerService) -> Result<()> {
+        let now = OffsetDateTime::now_utc();
+        let balances = svc.query.get_latest_balance_times().await?;
+        let prices = svc.query.get_latest_price_times().await?;
+
+        // Reset first so that deleted wallets and currencies are dropped
+        self.balance_age.reset();
+        for (wallet_id, time) in balances {
+            self.balance_age
+                .with_label_values(&[wallet_id.to_string().as_str()])
+                .set((now - time).as_seconds_f64());
+        }
+        self.price_age.reset();
+        for (currency, time) in prices {
+            self.price_age
+                .with_label_values(&[currency.as_str()])
+                .set((now - time).as_seconds_f64());
+        }
+        Ok(())
+    }
+
+    /// Encodes every metric in the Prometheus text format
+    pub fn encode(&self) -> Result<String> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+}
+
+fn provider_label(provider: DataProvider) -> String {
+    format!("{provider:?}").to_lowercase()
+}
@@ -0,0 +1,200 @@
+//! Alert notifiers, e.g. for stale balances and prices
+
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use hammer_service::types::DataProvider;
+use reqwest::{Client, Url};
+use serde::Serialize;
+use time::OffsetDateTime;
+
+/// Default time a webhook may take to accept alerts
+const DEFAULT_WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Data older than its maximum age
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum StaleAlert {
+    /// A provider has not returned a wallet recently, or never
+    Balance {
+        wallet_id: i32,
+        provider: DataProvider,
+        #[serde(with = "time::serde::rfc3339::option")]
+        latest: Option<OffsetDateTime>,
+        max_age_secs: u64,
+    },
+    /// A currency with a price provider has not been priced recently, or never
+    Price {
+        currency: String,
+        #[serde(with = "time::serde::rfc3339::option")]
+        latest: Option<OffsetDateTime>,
+        max_age_secs: u64,
+    },
+}
+
+/// A destination alerts are sent to
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    /// Name of the notifier, used in logs and reports
+    fn name(&self) -> &str;
+
+    /// Sends the alerts raised by one check, never called with no alerts
+    async fn notify(&self, checked_at: OffsetDateTime, alerts: &[StaleAlert]) -> Result<()>;
+}
+
+/// Body posted to a webhook
+#[derive(Serialize)]
+struct WebhookPayload<'a> {
+    #[serde(with = "time::serde::rfc3339")]
+    checked_at: OffsetDateTime,
+    alerts: &'a [StaleAlert],
+}
+
+/// Posts alerts as JSON to a URL
+///
+/// The body is `{"checked_at": ..., "alerts": [...]}`, where every alert has a `kind` of
+/// `balance` or `price`. Any non-2xx response is an error.
+pub struct WebhookNotifier {
+    client: Client,
+    url: Url,
+}
+
+impl WebhookNotifier {
+    pub fn new(url: Url) -> Result<Self> {
+        Ok(Self {
+            client: Client::builder().timeout(DEFAULT_WEBHOOK_TIMEOUT).build()?,
+            url,
+        })
+    }
+
+    /// Creates a notifier posting to `STALENESS_WEBHOOK_URL`, if set
+    pub fn from_env() -> Result<Option<Self>> {
+        let Ok(url) = std::env::var("STALENESS_WEBHOOK_URL") else {
+            return Ok(None);
+        };
+        let url = url
+            .parse()
+            .map_err(|e| anyhow!("Invalid STALENESS_WEBHOOK_URL {url:?}: {e}"))?;
+        Ok(Some(Self::new(url)?))
+    }
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+    fn name(&self) -> &str {
+        "webhook"
+    }
+
+    async fn notify(&self, checked_at: OffsetDateTime, alerts: &[StaleAlert]) -> Result<()> {
+        self.client
+            .post(self.url.clone())
+            .json(&WebhookPayload { checked_at, alerts })
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::{Json, Router, extract::State, http::StatusCode, routing::post};
+    use serde_json::{Value, json};
+
+    use super::*;
+
+    /// Bodies received by a local webhook
+    pub(crate) type Received = Arc<Mutex<Vec<Value>>>;
+
+    /// Serves a webhook on a local port that records every body and answers `status`
+    pub(crate) async fn local_webhook(status: StatusCode) -> (Url, Received) {
+        let received = Received::default();
+        let router = Router::new()
+            .route(
+                "/alerts",
+                post(
+                    move |State(received): State<Received>, Json(body): Json<Value>| async move {
+                        received.lock().unwrap().push(body);
+                        status
+                    },
+                ),
+            )
+            .with_state(received.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/alerts", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        (url.parse().unwrap(), received)
+    }
+
+    pub(crate) fn alerts() -> Vec<StaleAlert> {
+        vec![
+            StaleAlert::Balance {
+                wallet_id: 3,
+                provider: DataProvider::Onchain,
+                latest: Some(OffsetDateTime::from_unix_timestamp(1_767_225_600).unwrap()),
+                max_age_secs: 900,
+            },
+            StaleAlert::Price {
+                currency: "BTC".to_owned(),
+                latest: None,
+                max_age_secs: 300,
+            },
+        ]
+    }
+
+    #[tokio::test]
+    async fn posts_alerts_as_json() {
+        let (url, received) = local_webhook(StatusCode::OK).await;
+        let notifier = WebhookNotifier::new(url).unwrap();
+
+        notifier
+            .notify(
+                OffsetDateTime::from_unix_timestamp(1_767_229_200).unwrap(),
+                &alerts(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![json!({
+                "checked_at": "2026-01-01T01:00:00Z",
+                "alerts": [
+                    {
+                        "kind": "balance",
+                        "wallet_id": 3,
+                        "provider": "Onchain",
+                        "latest": "2026-01-01T00:00:00Z",
+                        "max_age_secs": 900,
+                    },
+                    {
+                        "kind": "price",
+                        "currency": "BTC",
+                        "latest": null,
+                        "max_age_secs": 300,
+                    },
+                ],
+            })]
+        );
+    }
+
+    #[tokio::test]
+    async fn fails_on_error_status() {
+        let (url, received) = local_webhook(StatusCode::INTERNAL_SERVER_ERROR).await;
+        let notifier = WebhookNotifier::new(url).unwrap();
+
+        let error = notifier
+            .notify(
+                OffsetDateTime::from_unix_timestamp(1_767_229_200).unwrap(),
+                &alerts(),
+            )
+            .await
+            .unwrap_err();
+
+        assert!(format!("{error:#}").contains("500"), "{error:#}");
+        assert_eq!(received.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,495 @@
+//! On-chain balance reader for DeFi wallets
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use chain_client::{
+    BalanceMatrix, ChainClient, ChainError,
+    abi::Address,
+    types::{Asset, Block, BlockTag},
+};
+use hammer_entity::{sea_orm_active_enums::AssetScope, wallet, wallet_metadata};
+use hammer_service::{
+    HammerService,
+    types::{AssetScope as ServiceAssetScope, DataProvider, NewBalance, NewBalanceEntry},
+};
+use sea_orm::ActiveEnum;
+use time::OffsetDateTime;
+use tracing::{info, instrument, warn};
+
+use crate::source::{BalanceSource, FetchedBalance, PreparedBalances, WalletWithMetadata};
+
+/// Default number of blocks between the chain head and the block balances are read at
+const DEFAULT_CONFIRMATIONS: u64 = 12;
+
+/// Default number of times a read is retried when its pinned block is reorged
+const DEFAULT_MAX_ATTEMPTS: usize = 3;
+
+/// Reads wallet balances directly from the chain, anchored to a single block per tick
+#[derive(Clone)]
+pub struct OnchainReader {
+    client: ChainClient,
+    confirmations: u64,
+    max_attempts: usize,
+}
+
+impl OnchainReader {
+    pub fn new(client: ChainClient) -> Self {
+        Self {
+            client,
+            confirmations: DEFAULT_CONFIRMATIONS,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+        }
+    }
+
+    /// Creates a reader for the node in `ETH_RPC_URL`, confirmations from `ETH_CONFIRMATIONS`
+    pub fn from_env() -> Result<Self> {
+        let mut reader = Self::new(ChainClient::new());
+        if let Ok(confirmations) = std::env::var("ETH_CONFIRMATIONS") {
+            reader = reader.with_confirmations(
+                confirmations
+                    .parse()
+                    .map_err(|e| anyhow!("Invalid ETH_CONFIRMATIONS {confirmations:?}: {e}"))?,
+            );
+        }
+        Ok(reader)
+    }
+
+    pub fn with_confirmations(mut self, confirmations: u64) -> Self {
+        self.confirmations = confirmations;
+        self
+    }
+
+    pub fn with_max_attempts(mut self, max_attempts: usize) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    pub fn confirmations(&self) -> u64 {
+        self.confirmations
+    }
+
+    pub fn client(&self) -> &ChainClient {
+        &self.client
+    }
+
+    /// Runs a read at a block pinned behind the head, re-pinning if that block is reorged
+    pub async fn read_anchored<T, F, Fut>(&self, read: F) -> Result<(Block, T)>
+    where
+        F: Fn(Block) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut last_block = 0;
+        for attempt in 1..=self.max_attempts.max(1) {
+            let block = self.client.pin_block(self.confirmations).await?;
+            let result = read(block).await;
+            if self.client.is_canonical(&block).await? {
+                return Ok((block, result?));
+            }
+            warn!(
+                "Block {} ({}) was reorged during read (attempt {attempt}), re-reading",
+                block.number,
+                block.hash_hex(),
+            );
+            last_block = block.number;
+        }
+        Err(anyhow!(ChainError::Reorged(last_block)))
+    }
+}
+
+/// Entries of each owner read at one block, or why they could not be read
+pub(crate) type OwnerEntries = HashMap<Address, Result<Vec<NewBalanceEntry>, String>>;
+
+/// An on-chain source whose balances are anchored to the block they were read at
+///
+/// Implementors only read entries at a given block. Pinning the block, retrying reorged reads
+/// and re-reading balances of reorged blocks are shared by [`prepare_pinned`] and
+/// [`recheck_reorgs`].
+#[async_trait]
+pub(crate) trait AnchoredReader: Send + Sync {
+    fn onchain(&self) -> &OnchainReader;
+
+    /// Scope of the wallets read
+    fn scope(&self) -> AssetScope;
+
+    /// Reads the entries of owners at a block; owners left out hold nothing
+    async fn read_at(
+        &self,
+        svc: &HammerService,
+        owners: &[Address],
+        block: Block,
+    ) -> Result<OwnerEntries>;
+}
+
+/// Reads the wallets of a reader's scope at one block pinned behind the head
+pub(crate) async fn prepare_pinned<R: AnchoredReader>(
+    reader: &R,
+    svc: &HammerService,
+    wallets: &[WalletWithMetadata],
+) -> Result<PreparedBalances> {
+    let owners = group_by_address(wallets, reader.scope());
+    if owners.is_empty() {
+        info!("No {:?} wallets configured", reader.scope());
+        return Ok(PreparedBalances::default());
+    }
+
+    let addresses = owners.keys().copied().collect::<Vec<_>>();
+    let (block, entries) = reader
+        .onchain()
+        .read_anchored(|block| reader.read_at(svc, &addresses, block))
+        .await?;
+    prepare_anchored(&owners, &block, |address| owner_entries(&entries, address))
+}
+
+/// Re-reads stored balances of a reader's scope whose block has been reorged out of the
+/// canonical chain
+///
+/// Only balances newer than the finalized block are checked. Balances are replaced in
+/// place, keeping their ID and snapshot. Returns the number of balances replaced.
+#[instrument(skip_all, fields(scope = ?reader.scope()))]
+pub(crate) async fn recheck_reorgs<R: AnchoredReader>(
+    reader: &R,
+    svc: &HammerService,
+) -> Result<usize> {
+    let client = reader.onchain().client();
+    let finalized = client.get_block(BlockTag::Finalized).await?;
+    let balances = svc
+        .query
+        .get_balances_after_block(DataProvider::Onchain, finalized.number as i64)
+        .await?;
+    let wallets = svc.query.get_wallets_with_metadata().await?;
+    let addresses = group_by_address(&wallets, reader.scope())
+        .into_iter()
+        .flat_map(|(address, wallet_ids)| wallet_ids.into_iter().map(move |id| (id, address)))
+        .collect::<HashMap<_, _>>();
+
+    let mut by_block = BTreeMap::<(i64, String), Vec<_>>::new();
+    for balance in balances
+        .into_iter()
+        .filter(|balance| addresses.contains_key(&balance.wallet_id))
+    {
+        if let (Some(number), Some(hash)) = (balance.block_number, balance.block_hash.clone()) {
+            by_block.entry((number, hash)).or_default().push(balance);
+        }
+    }
+
+    let mut replaced = 0;
+    for ((number, hash), balances) in by_block {
+        let canonical = client.get_block(BlockTag::Number(number as u64)).await?;
+        if canonical.hash_hex() == hash {
+            continue;
+        }
+        warn!(
+            "Block {number} was reorged from {hash} to {}, re-reading {} balances",
+            canonical.hash_hex(),
+            balances.len(),
+        );
+
+        let owners = balances
+            .iter()
+            .map(|balance| addresses[&balance.wallet_id])
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        let entries = reader.read_at(svc, &owners, canonical).await?;
+
+        for balance in balances {
+            let address = addresses[&balance.wallet_id];
+            // Left as is to be re-read by the next check
+            let entries = match owner_entries(&entries, &address) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    warn!("Failed to re-read balance {}: {e:#}", balance.id);
+                    continue;
+                }
+            };
+            svc.query
+                .replace_balance_with_entries(
+                    balance.id,
+                    NewBalance {
+                        snapshot_id: balance.snapshot_id,
+                        ..anchored_balance(balance.wallet_id, &canonical)?
+                    },
+                    entries,
+                )
+                .await?;
+            replaced += 1;
+        }
+    }
+
+    Ok(replaced)
+}
+
+/// Get the entries read for an owner, empty if it holds nothing
+fn owner_entries(entries: &OwnerEntries, owner: &Address) -> Result<Vec<NewBalanceEntry>> {
+    match entries.get(owner) {
+        Some(Ok(entries)) => Ok(entries.clone()),
+        Some(Err(e)) => Err(anyhow!("{e}")),
+        None => Ok(Vec::new()),
+    }
+}
+
+#[async_trait]
+impl AnchoredReader for OnchainReader {
+    fn onchain(&self) -> &OnchainReader {
+        self
+    }
+
+    fn scope(&self) -> AssetScope {
+        AssetScope::Ethereum
+    }
+
+    async fn read_at(
+        &self,
+        svc: &HammerService,
+        owners: &[Address],
+        block: Block,
+    ) -> Result<OwnerEntries> {
+        let raw_currencies = raw_currencies(svc, AssetScope::Ethereum).await?;
+        if raw_currencies.is_empty() {
+            return Err(anyhow!("No assets mapped for on-chain wallets"));
+        }
+        let assets = raw_currencies.keys().copied().collect::<Vec<_>>();
+        let matrix = self
+            .client
+            .read_balances(owners, &assets, BlockTag::Hash(block.hash))
+            .await?;
+        info!(
+            "Read {} balances at block {} ({})",
+            matrix.balances.len(),
+            block.number,
+            block.hash_hex(),
+        );
+
+        Ok(owners
+            .iter()
+            .map(|owner| {
+                let entries = balance_entries(&matrix, owner, &raw_currencies);
+                (*owner, entries.map_err(|e| format!("{e:#}")))
+            })
+            .collect())
+    }
+}
+
+#[async_trait]
+impl BalanceSource for OnchainReader {
+    fn provider(&self) -> DataProvider {
+        DataProvider::Onchain
+    }
+
+    fn scopes(&self) -> Vec<ServiceAssetScope> {
+        vec![ServiceAssetScope::Ethereum]
+    }
+
+    /// Reads balances of every Ethereum wallet at one pinned block
+    async fn prepare(
+        &self,
+        svc: &HammerService,
+        wallets: &[WalletWithMetadata],
+    ) -> Result<PreparedBalances> {
+        prepare_pinned(self, svc, wallets).await
+    }
+
+    async fn fetch(
+        &self,
+        wallet: &wallet::Model,
+        _metadata: &[wallet_metadata::Model],
+        prepared: &PreparedBalances,
+    ) -> Result<FetchedBalance> {
+        prepared.get(wallet.id)
+    }
+
+    async fn recheck(&self, svc: &HammerService) -> Result<usize> {
+        recheck_reorgs(self, svc).await
+    }
+}
+
+/// Groups wallets of a scope by the address in their metadata
+pub(crate) fn group_by_address(
+    wallets: &[WalletWithMetadata],
+    scope: AssetScope,
+) -> BTreeMap<Address, Vec<i32>> {
+    let mut owners = BTreeMap::<Address, Vec<i32>>::new();
+    for (wallet, metadata) in wallets.iter().filter(|(wallet, _)| wallet.scope == scope) {
+        let Some(address) = metadata.iter().find_map(|m| m.address.as_deref()) else {
+            warn!("Wallet {} has no on-chain address", wallet.id);
+            continue;
+        };
+        match address.parse::<Address>() {
+            Ok(address) => owners.entry(address).or_default().push(wallet.id),
+            Err(e) => warn!("Wallet {} has an invalid address: {e:#}", wallet.id),
+        }
+    }
+    owners
+}
+
+/// Get the assets mapped for a scope, with the raw currency their mapping was written as
+///
+/// Entries are stored under that raw currency, so they match the mapping whatever the case of
+/// its address.
+pub(crate) async fn raw_currencies(
+    svc: &HammerService,
+    scope: AssetScope,
+) -> Result<BTreeMap<Asset, String>> {
+    let mut raw_currencies = BTreeMap::new();
+    for mapping in svc
+        .query
+        .get_currency_mappings_by_scope(&scope.to_value())
+        .await?
+    {
+        match mapping.raw_currency.parse::<Asset>() {
+            Ok(asset) => {
+                raw_currencies.entry(asset).or_insert(mapping.raw_currency);
+            }
+            Err(e) => warn!("Skipping unreadable raw currency: {e:#}"),
+        }
+    }
+    Ok(raw_currencies)
+}
+
+/// Builds a balance for every wallet, anchored to the block its entries were read at
+///
+/// Wallets whose entries could not be read are prepared as failed.
+pub(crate) fn prepare_anchored<F>(
+    owners: &BTreeMap<Address, Vec<i32>>,
+    block: &Block,
+    entries_for: F,
+) -> Result<PreparedBalances>
+where
+    F: Fn(&Address) -> Result<Vec<NewBalanceEntry>>,
+{
+    let mut prepared = PreparedBalances::default();
+    for (address, wallet_ids) in owners {
+        let entries = entries_for(address).map_err(|e| format!("{e:#}"));
+        for wallet_id in wallet_ids {
+            let balance = anchored_balance(*wallet_id, block)?;
+            let fetched = match &entries {
+                Ok(entries) => Ok(FetchedBalance {
+                    provider: balance.provider,
+                    time: balance.time,
+                    block_number: balance.block_number,
+                    block_hash: balance.block_hash,
+                    entries: entries.clone(),
+                }),
+                Err(e) => Err(anyhow!("{e}")),
+            };
+            prepared.insert(*wallet_id, fetched);
+        }
+    }
+    Ok(prepared)
+}
+
+/// Builds a balance anchored to the given block
+pub(crate) fn anchored_balance(wallet_id: i32, block: &Block) -> Result<NewBalance> {
+    Ok(NewBalance {
+        wallet_id,
+        time: OffsetDateTime::from_unix_timestamp(block.timestamp as i64)?,
+        provider: DataProvider::Onchain,
+        block_number: Some(block.number as i64),
+        block_hash: Some(block.hash_hex()),
+        snapshot_id: None,
+    })
+}
+
+/// Collects the non-zero balances of one owner as entries under their mapped raw currencies
+///
+/// Fails when any balance of the owner could not be read, as a missing entry would otherwise
+/// read as a zero balance.
+pub(crate) fn balance_entries(
+    matrix: &BalanceMatrix,
+    owner: &Address,
+    raw_currencies: &BTreeMap<Asset, String>,
+) -> Result<Vec<NewBalanceEntry>> {
+    let failed = matrix
+        .failed
+        .iter()
+        .filter(|(failed_owner, _)| failed_owner == owner)
+        .map(|(_, asset)| asset.to_string())
+        .collect::<Vec<_>>();
+    if !failed.is_empty() {
+        return Err(anyhow!(
+            "Failed to read {} balance of {owner}",
+            failed.join(", ")
+        ));
+    }
+
+    Ok(matrix
+        .balances
+        .iter()
+        .filter(|balance| balance.owner == *owner && !balance.amount.is_zero())
+        .map(|balance| NewBalanceEntry {
+            balance_id: 0, // assigned on insert
+            raw_currency: raw_currencies
+                .get(&balance.asset)
+                .cloned()
+                .unwrap_or_else(|| balance.asset.to_string()),
+            amount: balance.amount,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use chain_client::TokenBalance;
+    use rust_decimal::Decimal;
+
+    use super::*;
+
+    const TOKEN: &str = "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48";
+
+    fn owner(byte: u8) -> Address {
+        Address([byte; 20])
+    }
+
+    fn raw_currencies() -> BTreeMap<Asset, String> {
+        [TOKEN, "eth"]
+            .into_iter()
+            .map(|raw| (raw.parse().unwrap(), raw.to_owned()))
+            .collect()
+    }
+
+    fn matrix(failed: Vec<(Address, Asset)>) -> BalanceMatrix {
+        let token = TOKEN.parse().unwrap();
+        BalanceMatrix {
+            balances: vec![
+                TokenBalance {
+                    owner: owner(1),
+                    asset: Asset::Native,
+                    amount: Decimal::new(15, 1),
+                },
+                TokenBalance {
+                    owner: owner(1),
+                    asset: token,
+                    amount: Decimal::ZERO,
+                },
+                TokenBalance {
+                    owner: owner(2),
+                    asset: token,
+                    amount: Decimal::from(100),
+                },
+            ],
+            failed,
+        }
+    }
+
+    #[test]
+    fn balance_entries_use_mapped_raw_currencies() {
+        let matrix = matrix(Vec::new());
+        let entries = balance_entries(&matrix, &owner(2), &raw_currencies()).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].raw_currency, TOKEN);
+        assert_eq!(entries[0].amount, Decimal::from(100));
+
+        let entries = balance_entries(&matrix, &owner(1), &raw_currencies()).unwrap();
+        assert_eq!(entries.len(), 1, "zero balances are skipped");
+        assert_eq!(entries[0].raw_currency, "eth");
+    }
+
+    #[test]
+    fn balance_entries_fail_when_a_read_of_the_owner_failed() {
+        let matrix = matrix(vec![(owner(1), TOKEN.parse().unwrap())]);
+        assert!(balance_entries(&matrix, &owner(1), &raw_currencies()).is_err());
+        assert!(balance_entries(&matrix, &owner(2), &raw_currencies()).is_ok());
+    }
+}
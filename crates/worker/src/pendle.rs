@@ -0,0 +1,211 @@
+//! Pendle v2 position reader for `AssetScope::Pendle2` wallets
+
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use chain_client::{
+    abi::Address,
+    pendle::PendlePosition,
+    types::{Asset, Block, BlockTag},
+};
+use hammer_entity::{sea_orm_active_enums::AssetScope, wallet, wallet_metadata};
+use hammer_service::{
+    HammerService,
+    types::{AssetScope as ServiceAssetScope, DataProvider, NewBalanceEntry},
+};
+use rust_decimal::Decimal;
+use tracing::{info, warn};
+
+use crate::{
+    onchain::{
+        AnchoredReader, OnchainReader, OwnerEntries, prepare_pinned, raw_currencies, recheck_reorgs,
+    },
+    source::{BalanceSource, FetchedBalance, PreparedBalances, WalletWithMetadata},
+};
+
+/// Reads Pendle PT, YT and LP holdings and stores them as underlying asset exposure
+///
+/// Balance entries are keyed by the underlying asset address, so `currency_map` rows for the
+/// `pendle2` scope map asset addresses to canonical currencies. Entries are stored under the
+/// address as written in its mapping.
+#[derive(Clone)]
+pub struct PendleReader {
+    onchain: OnchainReader,
+    markets: Vec<Address>,
+}
+
+impl PendleReader {
+    pub fn new(onchain: OnchainReader, markets: Vec<Address>) -> Self {
+        Self { onchain, markets }
+    }
+
+    /// Creates a reader for the comma-separated market addresses in `PENDLE_MARKETS`
+    pub fn from_env(onchain: OnchainReader) -> Result<Self> {
+        let markets = std::env::var("PENDLE_MARKETS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|market| !market.is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self::new(onchain, markets))
+    }
+}
+
+#[async_trait]
+impl AnchoredReader for PendleReader {
+    fn onchain(&self) -> &OnchainReader {
+        &self.onchain
+    }
+
+    fn scope(&self) -> AssetScope {
+        AssetScope::Pendle2
+    }
+
+    /// Reads the positions of the owners in every market and sums their underlying exposure
+    async fn read_at(
+        &self,
+        svc: &HammerService,
+        owners: &[Address],
+        block: Block,
+    ) -> Result<OwnerEntries> {
+        if self.markets.is_empty() {
+            return Err(anyhow!("No Pendle markets configured"));
+        }
+        let client = self.onchain.client();
+        let tag = BlockTag::Hash(block.hash);
+        let markets = client.read_pendle_markets(&self.markets, tag).await?;
+        let positions = client
+            .read_pendle_positions(owners, &markets, block.timestamp, tag)
+            .await?;
+        for position in &positions {
+            if position.expiry <= block.timestamp {
+                warn!(
+                    "{} holds matured {:?} of market {} (expired at {}), awaiting redemption",
+                    position.owner, position.kind, position.market, position.expiry
+                );
+            }
+        }
+        info!(
+            "Read {} Pendle positions at block {}",
+            positions.len(),
+            block.number
+        );
+
+        let raw_currencies = raw_currencies(svc, AssetScope::Pendle2).await?;
+        Ok(underlying_exposure(&positions, &raw_currencies)
+            .into_iter()
+            .map(|(owner, entries)| (owner, Ok(entries)))
+            .collect())
+    }
+}
+
+#[async_trait]
+impl BalanceSource for PendleReader {
+    fn provider(&self) -> DataProvider {
+        DataProvider::Onchain
+    }
+
+    fn scopes(&self) -> Vec<ServiceAssetScope> {
+        vec![ServiceAssetScope::Pendle2]
+    }
+
+    /// Reads positions of every Pendle wallet at one pinned block
+    async fn prepare(
+        &self,
+        svc: &HammerService,
+        wallets: &[WalletWithMetadata],
+    ) -> Result<PreparedBalances> {
+        if self.markets.is_empty() {
+            info!("No Pendle markets configured");
+            return Ok(PreparedBalances::default());
+        }
+        prepare_pinned(self, svc, wallets).await
+    }
+
+    async fn fetch(
+        &self,
+        wallet: &wallet::Model,
+        _metadata: &[wallet_metadata::Model],
+        prepared: &PreparedBalances,
+    ) -> Result<FetchedBalance> {
+        prepared.get(wallet.id)
+    }
+
+    async fn recheck(&self, svc: &HammerService) -> Result<usize> {
+        recheck_reorgs(self, svc).await
+    }
+}
+
+/// Sums the underlying asset exposure of each owner's positions into balance entries
+///
+/// Entries use the raw currency mapped for their asset, or the lowercase address if unmapped.
+pub fn underlying_exposure(
+    positions: &[PendlePosition],
+    raw_currencies: &BTreeMap<Asset, String>,
+) -> HashMap<Address, Vec<NewBalanceEntry>> {
+    let mut totals = BTreeMap::<(Address, Address), Decimal>::new();
+    for position in positions {
+        *totals.entry((position.owner, position.asset)).or_default() += position.asset_amount;
+    }
+
+    let mut exposure = HashMap::<Address, Vec<NewBalanceEntry>>::new();
+    for ((owner, asset), amount) in totals {
+        exposure.entry(owner).or_default().push(NewBalanceEntry {
+            balance_id: 0, // assigned on insert
+            raw_currency: raw_currencies
+                .get(&Asset::Erc20(asset))
+                .cloned()
+                .unwrap_or_else(|| asset.to_string()),
+            amount,
+        });
+    }
+    exposure
+}
+
+#[cfg(test)]
+mod tests {
+    use chain_client::pendle::PendleTokenKind;
+
+    use super::*;
+
+    const ASSET: &str = "0x7f39C581F595B53c5cb19bD0b3f8dA6c935E2Ca0";
+
+    fn position(owner: Address, kind: PendleTokenKind, asset_amount: Decimal) -> PendlePosition {
+        PendlePosition {
+            owner,
+            market: Address([0xaa; 20]),
+            kind,
+            expiry: 0,
+            balance: asset_amount,
+            asset: ASSET.parse().unwrap(),
+            asset_amount,
+        }
+    }
+
+    #[test]
+    fn underlying_exposure_sums_positions_under_the_mapped_raw_currency() {
+        let owner = Address([0x01; 20]);
+        let positions = [
+            position(owner, PendleTokenKind::Pt, Decimal::from(3)),
+            position(owner, PendleTokenKind::Lp, Decimal::from(2)),
+        ];
+        let raw_currencies = BTreeMap::from([(ASSET.parse().unwrap(), ASSET.to_owned())]);
+
+        let exposure = underlying_exposure(&positions, &raw_currencies);
+        let entries = &exposure[&owner];
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].raw_currency, ASSET);
+        assert_eq!(entries[0].amount, Decimal::from(5));
+    }
+
+    #[test]
+    fn underlying_exposure_falls_back_to_the_address() {
+        let owner = Address([0x01; 20]);
+        let positions = [position(owner, PendleTokenKind::Yt, Decimal::ONE)];
+
+        let exposure = underlying_exposure(&positions, &BTreeMap::new());
+        assert_eq!(exposure[&owner][0].raw_currency, ASSET.to_lowercase());
+    }
+}
@@ -0,0 +1,732 @@
+//! Historical price backfill, resumable and paced to provider rate limits
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use anyhow::{Context, Result, anyhow};
+use async_trait::async_trait;
+use hammer_service::{
+    HammerService,
+    types::{BackfillStatus, DataProvider, NewPrice, NewPriceBackfill, PriceBackfillProgress},
+};
+use time::{Duration, OffsetDateTime};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, instrument, warn};
+
+use crate::{chainlink::ChainlinkHistory, onchain::OnchainReader, price_source::QuotedPrice};
+
+/// Default number of timestamps requested from a source at once
+const DEFAULT_MAX_POINTS: usize = 500;
+
+/// Default minimum time between two requests to a source
+const DEFAULT_REQUEST_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Default number of retries of a failed request
+const DEFAULT_MAX_RETRIES: u32 = 5;
+
+/// Default delay before the first retry, doubled on every further retry
+const DEFAULT_RETRY_BACKOFF: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// A provider of historical prices
+#[async_trait]
+pub trait PriceHistorySource: Send + Sync {
+    /// Provider the prices are attributed to
+    fn provider(&self) -> DataProvider;
+
+    /// Most timestamps one request may cover
+    fn max_points(&self) -> usize {
+        DEFAULT_MAX_POINTS
+    }
+
+    /// Minimum time between two requests required by the provider
+    fn min_request_interval(&self) -> std::time::Duration {
+        std::time::Duration::ZERO
+    }
+
+    /// Fetches the prices of a currency at every `granularity` from `start` to `end` inclusive
+    ///
+    /// Prices at other times are ignored, and times the source has no price for are left
+    /// unfilled.
+    async fn fetch_history(
+        &self,
+        currency: &str,
+        start: OffsetDateTime,
+        end: OffsetDateTime,
+        granularity: Duration,
+    ) -> Result<Vec<QuotedPrice>>;
+}
+
+/// Historical price sources keyed by provider
+#[derive(Clone, Default)]
+pub struct PriceHistoryRegistry {
+    sources: HashMap<DataProvider, Arc<dyn PriceHistorySource>>,
+}
+
+impl PriceHistoryRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a registry of the history sources configured in the environment
+    ///
+    /// On-chain history requires `ETH_RPC_URL` and `CHAINLINK_FEEDS`.
+    pub fn from_env() -> Result<Self> {
+        let mut registry = Self::new();
+        if std::env::var("ETH_RPC_URL").is_ok() && std::env::var("CHAINLINK_FEEDS").is_ok() {
+            let onchain = OnchainReader::from_env()?;
+            registry.register(Arc::new(ChainlinkHistory::from_env(onchain)?));
+        }
+        Ok(registry)
+    }
+
+    /// Registers a source, replacing any source of the same provider
+    pub fn register(&mut self, source: Arc<dyn PriceHistorySource>) {
+        self.sources.insert(source.provider(), source);
+    }
+
+    pub fn get(&self, provider: DataProvider) -> Option<Arc<dyn PriceHistorySource>> {
+        self.sources.get(&provider).cloned()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+}
+
+/// Pacing and retries of backfill requests
+#[derive(Debug, Clone)]
+pub struct BackfillLimits {
+    /// Minimum time between two requests, raised to the source's own minimum
+    pub request_interval: std::time::Duration,
+    pub max_retries: u32,
+    pub retry_backoff: std::time::Duration,
+}
+
+impl Default for BackfillLimits {
+    fn default() -> Self {
+        Self {
+            request_interval: DEFAULT_REQUEST_INTERVAL,
+            max_retries: DEFAULT_MAX_RETRIES,
+            retry_backoff: DEFAULT_RETRY_BACKOFF,
+        }
+    }
+}
+
+impl BackfillLimits {
+    /// Creates limits from `PRICE_BACKFILL_REQUEST_INTERVAL`, `PRICE_BACKFILL_MAX_RETRIES` and
+    /// `PRICE_BACKFILL_RETRY_BACKOFF`
+    pub fn from_env() -> Result<Self> {
+        let mut limits = Self::default();
+        if let Ok(value) = std::env::var("PRICE_BACKFILL_REQUEST_INTERVAL") {
+            limits.request_interval = humantime::parse_duration(&value)
+                .map_err(|e| anyhow!("Invalid PRICE_BACKFILL_REQUEST_INTERVAL {value:?}: {e}"))?;
+        }
+        if let Ok(value) = std::env::var("PRICE_BACKFILL_MAX_RETRIES") {
+            limits.max_retries = value
+                .parse()
+                .map_err(|e| anyhow!("Invalid PRICE_BACKFILL_MAX_RETRIES {value:?}: {e}"))?;
+        }
+        if let Ok(value) = std::env::var("PRICE_BACKFILL_RETRY_BACKOFF") {
+            limits.retry_backoff = humantime::parse_duration(&value)
+                .map_err(|e| anyhow!("Invalid PRICE_BACKFILL_RETRY_BACKOFF {value:?}: {e}"))?;
+        }
+        Ok(limits)
+    }
+}
+
+/// Range of prices to backfill for a currency from one provider
+#[derive(Debug, Clone)]
+pub struct PriceBackfill {
+    pub currency: String,
+    pub provider: DataProvider,
+    pub start: OffsetDateTime,
+    pub end: OffsetDateTime,
+    pub granularity: Duration,
+}
+
+impl PriceBackfill {
+    /// Price times from `from` to `end` inclusive, `granularity` apart
+    pub fn timestamps(&self, from: OffsetDateTime) -> Vec<OffsetDateTime> {
+        let mut timestamps = Vec::new();
+        let mut time = from;
+        while time <= self.end {
+            timestamps.push(time);
+            time += self.granularity;
+        }
+        timestamps
+    }
+}
+
+/// Outcome of one backfill run
+#[derive(Debug, Clone, Default)]
+pub struct PriceBackfillReport {
+    /// ID of the `price_backfill` row tracking progress
+    pub backfill_id: i32,
+    /// Time the run resumed from, `None` when it started at the beginning of the range
+    pub resumed_from: Option<OffsetDateTime>,
+    pub inserted: usize,
+    /// Times that already had a price from the provider
+    pub skipped: usize,
+    /// Times the provider had no price for
+    pub unavailable: usize,
+    pub requests: usize,
+    /// Whether the whole range is backfilled, false when stopped by a shutdown
+    pub complete: bool,
+}
+
+/// Fetches a currency's historical prices from one source and stores them in bulk
+///
+/// The range is fetched in chunks of at most the source's `max_points` times, paced by
+/// `limits` and retried with exponential backoff. Times that already have a price from the
+/// provider are skipped, and chunks without missing times are not requested at all. Progress
+/// is recorded in `price_backfill` after every chunk, so running the same backfill again
+/// resumes where it stopped. Once `token` is cancelled the backfill stops after the current
+/// chunk.
+#[instrument(skip(svc, source, limits, token))]
+pub async fn backfill_prices(
+    svc: &HammerService,
+    source: &dyn PriceHistorySource,
+    backfill: PriceBackfill,
+    limits: &BackfillLimits,
+    token: &CancellationToken,
+) -> Result<PriceBackfillReport> {
+    if backfill.granularity <= Duration::ZERO {
+        return Err(anyhow!("Backfill granularity must be positive"));
+    }
+    if backfill.start > backfill.end {
+        return Err(anyhow!("Backfill start must not be after its end"));
+    }
+    if source.provider() != backfill.provider {
+        return Err(anyhow!(
+            "Source of {:?} cannot backfill {:?} prices",
+            source.provider(),
+            backfill.provider
+        ));
+    }
+    if svc
+        .query
+        .get_currency_by_name(&backfill.currency)
+        .await?
+        .is_none()
+    {
+        return Err(anyhow!("Currency {} not found", backfill.currency));
+    }
+
+    let progress = svc
+        .query
+        .start_price_backfill(NewPriceBackfill {
+            currency: backfill.currency.clone(),
+            provider: backfill.provider,
+            start_time: backfill.start,
+            end_time: backfill.end,
+            granularity_secs: backfill.granularity.whole_seconds(),
+        })
+        .await?;
+    let mut report = PriceBackfillReport {
+        backfill_id: progress.id,
+        resumed_from: progress.cursor,
+        ..Default::default()
+    };
+    if progress.status == BackfillStatus::Complete.into() {
+        info!("Backfill {} is already complete", progress.id);
+        report.complete = true;
+        return Ok(report);
+    }
+
+    let result = backfill_chunks(
+        svc,
+        source,
+        &backfill,
+        &progress,
+        limits,
+        token,
+        &mut report,
+    )
+    .await;
+    let (status, error) = match &result {
+        Ok(()) if report.complete => (BackfillStatus::Complete, None),
+        Ok(()) => (
+            BackfillStatus::Failed,
+            Some("Interrupted by shutdown".to_owned()),
+        ),
+        Err(e) => (BackfillStatus::Failed, Some(format!("{e:#}"))),
+    };
+    svc.query
+        .finish_price_backfill(progress.id, status, error)
+        .await?;
+    result?;
+
+    info!(
+        "Backfilled {} {:?} prices: {} inserted, {} skipped, {} unavailable in {} requests",
+        backfill.currency,
+        backfill.provider,
+        report.inserted,
+        report.skipped,
+        report.unavailable,
+        report.requests
+    );
+    Ok(report)
+}
+
+async fn backfill_chunks(
+    svc: &HammerService,
+    source: &dyn PriceHistorySource,
+    backfill: &PriceBackfill,
+    progress: &hammer_entity::price_backfill::Model,
+    limits: &BackfillLimits,
+    token: &CancellationToken,
+    report: &mut PriceBackfillReport,
+) -> Result<()> {
+    let from = progress.cursor.unwrap_or(backfill.start);
+    let existing = svc
+        .query
+        .get_price_times(&backfill.currency, backfill.provider, from, backfill.end)
+        .await?
+        .into_iter()
+        .collect::<HashSet<_>>();
+    let resumed = PriceBackfillProgress {
+        cursor: from,
+        inserted: progress.inserted,
+        skipped: progress.skipped,
+    };
+    backfill_times(
+        source,
+        backfill,
+        resumed,
+        &existing,
+        limits,
+        token,
+        report,
+        |prices| async move { Ok(svc.query.create_prices(prices).await? as usize) },
+        |update| async move {
+            svc.query
+                .update_price_backfill_progress(progress.id, update)
+                .await?;
+            Ok(())
+        },
+    )
+    .await
+}
+
+/// Fetches the times from `resumed.cursor` not in `existing` a chunk at a time, passing each
+/// chunk's prices to `store` and the progress after it to `record`
+#[allow(clippy::too_many_arguments)]
+async fn backfill_times<S, SFut, R, RFut>(
+    source: &dyn PriceHistorySource,
+    backfill: &PriceBackfill,
+    resumed: PriceBackfillProgress,
+    existing: &HashSet<OffsetDateTime>,
+    limits: &BackfillLimits,
+    token: &CancellationToken,
+    report: &mut PriceBackfillReport,
+    mut store: S,
+    mut record: R,
+) -> Result<()>
+where
+    S: FnMut(Vec<NewPrice>) -> SFut,
+    SFut: Future<Output = Result<usize>>,
+    R: FnMut(PriceBackfillProgress) -> RFut,
+    RFut: Future<Output = Result<()>>,
+{
+    let mut pacer = Pacer::new(limits.request_interval.max(source.min_request_interval()));
+    let mut inserted = resumed.inserted;
+    let mut skipped = resumed.skipped;
+
+    for chunk in backfill
+        .timestamps(resumed.cursor)
+        .chunks(source.max_points().max(1))
+    {
+        if token.is_cancelled() {
+            warn!(
+                "Backfill of {} {:?} prices stopped by shutdown",
+                backfill.currency, backfill.provider
+            );
+            return Ok(());
+        }
+
+        let missing = chunk
+            .iter()
+            .filter(|time| !existing.contains(time))
+            .copied()
+            .collect::<Vec<_>>();
+        let chunk_skipped = chunk.len() - missing.len();
+        let mut chunk_inserted = 0;
+        if let (Some(first), Some(last)) = (missing.first(), missing.last()) {
+            let quotes =
+                fetch_with_retries(source, backfill, *first, *last, limits, &mut pacer, token)
+                    .await?;
+            report.requests += 1;
+
+            let mut wanted = missing.iter().copied().collect::<HashSet<_>>();
+            let prices = quotes
+                .into_iter()
+                .filter(|quote| wanted.remove(&quote.time))
+                .map(|quote| NewPrice {
+                    currency: backfill.currency.clone(),
+                    time: quote.time,
+                    value: quote.value,
+                    liquidity: quote.liquidity,
+                    provider: backfill.provider,
+                })
+                .collect::<Vec<_>>();
+            chunk_inserted = store(prices).await?;
+            report.unavailable += wanted.len();
+        }
+
+        report.inserted += chunk_inserted;
+        report.skipped += chunk_skipped;
+        inserted += chunk_inserted as i32;
+        skipped += chunk_skipped as i32;
+        let cursor = *chunk.last().expect("chunks are never empty") + backfill.granularity;
+        record(PriceBackfillProgress {
+            cursor,
+            inserted,
+            skipped,
+        })
+        .await?;
+    }
+    report.complete = true;
+    Ok(())
+}
+
+/// Fetches one range, retrying failures with exponential backoff
+async fn fetch_with_retries(
+    source: &dyn PriceHistorySource,
+    backfill: &PriceBackfill,
+    start: OffsetDateTime,
+    end: OffsetDateTime,
+    limits: &BackfillLimits,
+    pacer: &mut Pacer,
+    token: &CancellationToken,
+) -> Result<Vec<QuotedPrice>> {
+    let mut retries = 0;
+    loop {
+        pacer.wait().await;
+        let error = match source
+            .fetch_history(&backfill.currency, start, end, backfill.granularity)
+            .await
+        {
+            Ok(quotes) => return Ok(quotes),
+            Err(e) => e,
+        };
+        if retries == limits.max_retries {
+            return Err(error).with_context(|| {
+                format!("Failed to fetch prices from {start} to {end} after {retries} retries")
+            });
+        }
+
+        let backoff = limits
+            .retry_backoff
+            .saturating_mul(2u32.saturating_pow(retries));
+        retries += 1;
+        warn!(
+            "Failed to fetch {} prices from {} to {}, retry {} in {:?}: {:#}",
+            backfill.currency, start, end, retries, backoff, error
+        );
+        tokio::select! {
+            _ = token.cancelled() => return Err(error.context("Cancelled by shutdown")),
+            _ = tokio::time::sleep(backoff) => {}
+        }
+    }
+}
+
+/// Spaces requests at least `interval` apart
+struct Pacer {
+    interval: std::time::Duration,
+    next: Option<Instant>,
+}
+
+impl Pacer {
+    fn new(interval: std::time::Duration) -> Self {
+        Self {
+            interval,
+            next: None,
+        }
+    }
+
+    async fn wait(&mut self) {
+        if let Some(next) = self.next {
+            tokio::time::sleep_until(next).await;
+        }
+        self.next = Some(Instant::now() + self.interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use rust_decimal::Decimal;
+
+    use super::*;
+
+    /// Source with 2 points per request, failing its first `failures` requests and without
+    /// prices at the times in `gaps`
+    struct FakeHistory {
+        gaps: HashSet<OffsetDateTime>,
+        failures: Mutex<u32>,
+        requests: Mutex<Vec<(OffsetDateTime, OffsetDateTime)>>,
+        /// Cancelled after the first request to simulate a shutdown
+        shutdown: Option<CancellationToken>,
+    }
+
+    impl FakeHistory {
+        fn new() -> Self {
+            Self {
+                gaps: HashSet::new(),
+                failures: Mutex::new(0),
+                requests: Mutex::new(Vec::new()),
+                shutdown: None,
+            }
+        }
+
+        fn requests(&self) -> Vec<(OffsetDateTime, OffsetDateTime)> {
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait]
+    impl PriceHistorySource for FakeHistory {
+        fn provider(&self) -> DataProvider {
+            DataProvider::Onchain
+        }
+
+        fn max_points(&self) -> usize {
+            2
+        }
+
+        async fn fetch_history(
+            &self,
+            _currency: &str,
+            start: OffsetDateTime,
+            end: OffsetDateTime,
+            granularity: Duration,
+        ) -> Result<Vec<QuotedPrice>> {
+            self.requests.lock().unwrap().push((start, end));
+            if let Some(token) = &self.shutdown {
+                token.cancel();
+            }
+            let mut failures = self.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err(anyhow!("rate limited"));
+            }
+
+            let mut quotes = Vec::new();
+            let mut time = start;
+            while time <= end {
+                if !self.gaps.contains(&time) {
+                    quotes.push(QuotedPrice {
+                        time,
+                        value: Decimal::ONE,
+                        liquidity: Decimal::ZERO,
+                    });
+                }
+                time += granularity;
+            }
+            Ok(quotes)
+        }
+    }
+
+    fn hour(n: i64) -> OffsetDateTime {
+        OffsetDateTime::from_unix_timestamp(n * 3600).unwrap()
+    }
+
+    /// Backfill of hours 0 to 5, three chunks of the fake source
+    fn backfill() -> PriceBackfill {
+        PriceBackfill {
+            currency: "ETH".to_owned(),
+            provider: DataProvider::Onchain,
+            start: hour(0),
+            end: hour(5),
+            granularity: Duration::hours(1),
+        }
+    }
+
+    fn limits(max_retries: u32) -> BackfillLimits {
+        BackfillLimits {
+            request_interval: std::time::Duration::ZERO,
+            max_retries,
+            retry_backoff: std::time::Duration::ZERO,
+        }
+    }
+
+    fn fresh() -> PriceBackfillProgress {
+        PriceBackfillProgress {
+            cursor: hour(0),
+            inserted: 0,
+            skipped: 0,
+        }
+    }
+
+    /// Runs a backfill, returning its result, report, stored prices and recorded progress
+    async fn run(
+        source: &FakeHistory,
+        resumed: PriceBackfillProgress,
+        existing: &HashSet<OffsetDateTime>,
+        limits: &BackfillLimits,
+        token: &CancellationToken,
+    ) -> (
+        Result<()>,
+        PriceBackfillReport,
+        Vec<NewPrice>,
+        Vec<PriceBackfillProgress>,
+    ) {
+        let mut report = PriceBackfillReport::default();
+        let mut stored = Vec::new();
+        let mut recorded = Vec::new();
+        let result = backfill_times(
+            source,
+            &backfill(),
+            resumed,
+            existing,
+            limits,
+            token,
+            &mut report,
+            |prices| {
+                let count = prices.len();
+                stored.extend(prices);
+                std::future::ready(Ok(count))
+            },
+            |progress| {
+                recorded.push(progress);
+                std::future::ready(Ok(()))
+            },
+        )
+        .await;
+        (result, report, stored, recorded)
+    }
+
+    #[tokio::test]
+    async fn skips_requests_for_chunks_without_missing_times() {
+        let source = FakeHistory::new();
+        let existing = HashSet::from([hour(0), hour(1), hour(3)]);
+        let (result, report, stored, recorded) = run(
+            &source,
+            fresh(),
+            &existing,
+            &limits(0),
+            &CancellationToken::new(),
+        )
+        .await;
+        result.unwrap();
+
+        // The first chunk is fully stored, the second only needs hour 2
+        assert_eq!(
+            source.requests(),
+            vec![(hour(2), hour(2)), (hour(4), hour(5))]
+        );
+        assert_eq!(
+            stored.iter().map(|p| p.time).collect::<Vec<_>>(),
+            vec![hour(2), hour(4), hour(5)]
+        );
+        assert_eq!(
+            (report.inserted, report.skipped, report.requests),
+            (3, 3, 2)
+        );
+        assert!(report.complete);
+        assert_eq!(
+            recorded.iter().map(|p| p.cursor).collect::<Vec<_>>(),
+            vec![hour(2), hour(4), hour(6)]
+        );
+    }
+
+    #[tokio::test]
+    async fn counts_times_the_source_did_not_return() {
+        let mut source = FakeHistory::new();
+        source.gaps = HashSet::from([hour(1), hour(4)]);
+        let (result, report, stored, _) = run(
+            &source,
+            fresh(),
+            &HashSet::new(),
+            &limits(0),
+            &CancellationToken::new(),
+        )
+        .await;
+        result.unwrap();
+        assert_eq!(stored.len(), 4);
+        assert_eq!((report.inserted, report.unavailable), (4, 2));
+        assert!(report.complete);
+    }
+
+    #[tokio::test]
+    async fn retries_stop_at_max_retries() {
+        let source = FakeHistory::new();
+        *source.failures.lock().unwrap() = 2;
+        let (result, report, _, _) = run(
+            &source,
+            fresh(),
+            &HashSet::new(),
+            &limits(2),
+            &CancellationToken::new(),
+        )
+        .await;
+        result.unwrap();
+        assert!(report.complete);
+        assert_eq!(
+            source.requests().len(),
+            5,
+            "two failures, then three chunks"
+        );
+
+        let source = FakeHistory::new();
+        *source.failures.lock().unwrap() = u32::MAX;
+        let (result, report, stored, recorded) = run(
+            &source,
+            fresh(),
+            &HashSet::new(),
+            &limits(2),
+            &CancellationToken::new(),
+        )
+        .await;
+        assert!(result.is_err());
+        assert_eq!(
+            source.requests().len(),
+            3,
+            "the first attempt and two retries"
+        );
+        assert!(!report.complete);
+        assert!(stored.is_empty() && recorded.is_empty());
+    }
+
+    #[tokio::test]
+    async fn second_run_resumes_from_the_cursor() {
+        let token = CancellationToken::new();
+        let mut source = FakeHistory::new();
+        source.shutdown = Some(token.clone());
+        let (result, report, _, recorded) =
+            run(&source, fresh(), &HashSet::new(), &limits(0), &token).await;
+        result.unwrap();
+        assert!(
+            !report.complete,
+            "stopped by shutdown after the first chunk"
+        );
+        let progress = recorded.last().unwrap().clone();
+        assert_eq!(
+            (progress.cursor, progress.inserted, progress.skipped),
+            (hour(2), 2, 0)
+        );
+
+        let source = FakeHistory::new();
+        let (result, report, stored, recorded) = run(
+            &source,
+            progress,
+            &HashSet::new(),
+            &limits(0),
+            &CancellationToken::new(),
+        )
+        .await;
+        result.unwrap();
+        assert!(report.complete);
+        assert_eq!(
+            source.requests(),
+            vec![(hour(2), hour(3)), (hour(4), hour(5))]
+        );
+        assert_eq!(stored.first().unwrap().time, hour(2));
+        let last = recorded.last().unwrap();
+        assert_eq!((last.cursor, last.inserted, last.skipped), (hour(6), 6, 0));
+    }
+}
@@ -0,0 +1,218 @@
+//! Provider-agnostic price sources with priority fallback
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use cam_client::CamError;
+use hammer_service::{
+    HammerService,
+    types::{DataProvider, NewPrice},
+};
+use rust_decimal::Decimal;
+use time::OffsetDateTime;
+use tracing::{error, instrument, warn};
+
+/// Default time a source is given to answer one batch before falling back
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Price of one currency as quoted by a source
+#[derive(Debug, Clone)]
+pub struct QuotedPrice {
+    pub time: OffsetDateTime,
+    pub value: Decimal,
+    pub liquidity: Decimal,
+}
+
+/// A provider of currency prices
+#[async_trait]
+pub trait PriceSource: Send + Sync {
+    /// Provider the prices are attributed to
+    fn provider(&self) -> DataProvider;
+
+    /// Fetches the prices of several currencies at once
+    ///
+    /// Currencies the source has no price for are either left out of the result or reported
+    /// with `CamError::TokenPriceNotFound`.
+    async fn fetch_prices(&self, currencies: &[String]) -> Result<HashMap<String, QuotedPrice>>;
+}
+
+/// Prices resolved in one pass, with the currencies no provider could price
+#[derive(Debug, Default)]
+pub struct PriceResolution {
+    pub prices: Vec<NewPrice>,
+    pub unpriced: Vec<String>,
+    /// Providers that failed or timed out on a batch, which fell back to the next provider
+    pub failed: Vec<(DataProvider, String)>,
+}
+
+/// Resolves prices by trying each currency's providers in `price_provider` priority order
+#[derive(Clone)]
+pub struct PriceResolver {
+    sources: HashMap<DataProvider, Arc<dyn PriceSource>>,
+    timeout: Duration,
+}
+
+impl PriceResolver {
+    pub fn new() -> Self {
+        Self {
+            sources: HashMap::new(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Creates a resolver with the timeout from `PRICE_SOURCE_TIMEOUT`, e.g. `10s`
+    pub fn from_env() -> Result<Self> {
+        let mut resolver = Self::new();
+        if let Ok(timeout) = std::env::var("PRICE_SOURCE_TIMEOUT") {
+            resolver = resolver.with_timeout(
+                humantime::parse_duration(&timeout)
+                    .map_err(|e| anyhow!("Invalid PRICE_SOURCE_TIMEOUT {timeout:?}: {e}"))?,
+            );
+        }
+        Ok(resolver)
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Registers a source, replacing any source of the same provider
+    pub fn register(&mut self, source: Arc<dyn PriceSource>) {
+        self.sources.insert(source.provider(), source);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    /// Resolves the price of every currency
+    ///
+    /// Each round queries every currency's next provider in one batch per provider. A
+    /// currency falls back to its next provider when the current one has no price for it or
+    /// does not answer within the timeout. Other errors leave the currency unpriced.
+    #[instrument(skip_all, fields(currencies = currencies.len()))]
+    pub async fn resolve(
+        &self,
+        svc: &HammerService,
+        currencies: &[String],
+    ) -> Result<PriceResolution> {
+        let mut chains = BTreeMap::new();
+        for currency in currencies {
+            let chain = svc
+                .query
+                .get_price_providers(currency)
+                .await?
+                .into_iter()
+                .map(|row| DataProvider::from(row.provider))
+                .filter(|provider| self.sources.contains_key(provider))
+                .collect::<Vec<_>>();
+            chains.insert(currency.clone(), chain);
+        }
+
+        let mut resolution = PriceResolution::default();
+        let mut pending = chains.keys().cloned().collect::<Vec<_>>();
+        let mut round = 0;
+        while !pending.is_empty() {
+            let mut batches = HashMap::<DataProvider, Vec<String>>::new();
+            for currency in pending.drain(..) {
+                match chains[&currency].get(round) {
+                    Some(provider) => batches.entry(*provider).or_default().push(currency),
+                    None => resolution.unpriced.push(currency),
+                }
+            }
+
+            for (provider, batch) in batches {
+                let (prices, fallback, error) = self.fetch_batch(provider, batch).await;
+                resolution.prices.extend(prices);
+                pending.extend(fallback);
+                resolution.failed.extend(error.map(|e| (provider, e)));
+            }
+            round += 1;
+        }
+
+        resolution.unpriced.sort();
+        Ok(resolution)
+    }
+
+    /// Queries one provider, returning its prices, the currencies to retry elsewhere and the
+    /// error that failed the batch
+    async fn fetch_batch(
+        &self,
+        provider: DataProvider,
+        mut batch: Vec<String>,
+    ) -> (Vec<NewPrice>, Vec<String>, Option<String>) {
+        let source = &self.sources[&provider];
+        let mut fallback = Vec::new();
+        while !batch.is_empty() {
+            let result = tokio::time::timeout(self.timeout, source.fetch_prices(&batch)).await;
+            let quotes = match result {
+                Ok(Ok(quotes)) => quotes,
+                Ok(Err(e)) => match not_found_currency(&e) {
+                    // Retry the rest of the batch without the currency the source rejected
+                    Some(currency) if batch.contains(&currency) => {
+                        warn!("{provider:?} has no price for {currency}, falling back");
+                        batch.retain(|c| *c != currency);
+                        fallback.push(currency);
+                        continue;
+                    }
+                    _ => {
+                        error!(
+                            "{provider:?} failed to price {:?}, falling back: {e:#}",
+                            batch
+                        );
+                        fallback.extend(batch);
+                        return (Vec::new(), fallback, Some(format!("{e:#}")));
+                    }
+                },
+                Err(_) => {
+                    warn!(
+                        "{provider:?} timed out after {:?} pricing {} currencies, falling back",
+                        self.timeout,
+                        batch.len()
+                    );
+                    fallback.extend(batch);
+                    let error = format!("Timed out after {:?}", self.timeout);
+                    return (Vec::new(), fallback, Some(error));
+                }
+            };
+
+            let mut prices = Vec::new();
+            for currency in batch {
+                match quotes.get(&currency) {
+                    Some(quote) => prices.push(NewPrice {
+                        currency,
+                        time: quote.time,
+                        value: quote.value,
+                        liquidity: quote.liquidity,
+                        provider,
+                    }),
+                    None => fallback.push(currency),
+                }
+            }
+            return (prices, fallback, None);
+        }
+        (Vec::new(), fallback, None)
+    }
+}
+
+impl Default for PriceResolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Extracts the currency of a `TokenPriceNotFound` error anywhere in the chain
+fn not_found_currency(error: &anyhow::Error) -> Option<String> {
+    error
+        .chain()
+        .find_map(|cause| match cause.downcast_ref::<CamError>() {
+            Some(CamError::TokenPriceNotFound(currency)) => Some(currency.clone()),
+            _ => None,
+        })
+}
@@ -1,19 +1,147 @@
 //! Price worker for fetching price data
 
-use anyhow::Result;
-use hammer_service::HammerService;
-use tracing::{info, instrument};
+use std::{
+    collections::{BTreeSet, HashMap},
+    time::Duration,
+};
 
-/// Fetches price data from CAM and stores it in the database
-#[instrument(skip(_svc))]
-pub async fn fetch_prices(_svc: &HammerService) -> Result<()> {
+use anyhow::{Result, anyhow};
+use hammer_service::{
+    HammerService,
+    types::{AssetScope, DataProvider, NewPrice},
+};
+use time::OffsetDateTime;
+use tracing::{info, instrument, warn};
+
+use crate::{job::RunOptions, price_source::PriceResolver};
+
+/// Default window of balances whose currencies are considered held
+const DEFAULT_HOLDINGS_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Which currencies the price job prices
+#[derive(Debug, Clone)]
+pub struct PriceConfig {
+    /// Age of the oldest balance whose currencies are priced
+    pub holdings_window: Duration,
+}
+
+impl Default for PriceConfig {
+    fn default() -> Self {
+        Self {
+            holdings_window: DEFAULT_HOLDINGS_WINDOW,
+        }
+    }
+}
+
+impl PriceConfig {
+    /// Creates the config from `PRICE_HOLDINGS_WINDOW`, e.g. `24h`
+    pub fn from_env() -> Result<Self> {
+        let mut config = Self::default();
+        if let Ok(window) = std::env::var("PRICE_HOLDINGS_WINDOW") {
+            config.holdings_window = humantime::parse_duration(&window)
+                .map_err(|e| anyhow!("Invalid PRICE_HOLDINGS_WINDOW {window:?}: {e}"))?;
+        }
+        Ok(config)
+    }
+}
+
+/// Outcome of a price fetch
+#[derive(Debug, Default)]
+pub struct PriceReport {
+    /// Prices resolved for the currencies
+    pub prices: Vec<NewPrice>,
+    /// Number of prices stored, zero in a dry run
+    pub stored: u64,
+    /// Canonical currencies no provider could price
+    pub unpriced: Vec<String>,
+    /// Held raw currencies with no canonical currency, as `scope:raw_currency`
+    pub unmapped: Vec<String>,
+    /// Providers that failed a batch, whose currencies fell back to the next provider
+    pub failed_providers: Vec<(DataProvider, String)>,
+}
+
+/// Fetches prices of the currencies in use and stores them in bulk
+///
+/// The currencies priced are every currency in `currency` plus those held in a balance within
+/// the holdings window through `currency_map` for the wallet's scope, as selected by `options`.
+/// In a dry run the prices are only reported.
+#[instrument(skip_all)]
+pub async fn fetch_prices(
+    svc: &HammerService,
+    resolver: &PriceResolver,
+    config: &PriceConfig,
+    options: &RunOptions,
+) -> Result<PriceReport> {
     info!("Starting price fetch");
 
-    // TODO: Implement price fetching logic
-    // 1. Get list of currencies from database
-    // 2. Fetch prices from CAM for each currency
-    // 3. Store prices in database
+    let mut report = PriceReport::default();
+    let mut currencies = priced_currencies(svc, config, &mut report).await?;
+    currencies.retain(|currency| options.includes_currency(currency));
+    if !report.unmapped.is_empty() {
+        warn!(
+            "Held raw currencies without a mapping: {:?}",
+            report.unmapped
+        );
+    }
+
+    let resolution = resolver.resolve(svc, &currencies).await?;
+    report.unpriced = resolution.unpriced;
+    report.failed_providers = resolution.failed;
+    if !report.unpriced.is_empty() {
+        warn!("No provider priced {:?}", report.unpriced);
+    }
+    report.prices = resolution.prices;
+    if !options.dry_run {
+        report.stored = svc.query.create_prices(report.prices.clone()).await?;
+    }
+
+    info!(
+        "Price fetch completed: {} of {} currencies priced, {} stored",
+        report.prices.len(),
+        currencies.len(),
+        report.stored
+    );
+    Ok(report)
+}
+
+/// Get the known currencies and the canonical currencies held within the holdings window
+async fn priced_currencies(
+    svc: &HammerService,
+    config: &PriceConfig,
+    report: &mut PriceReport,
+) -> Result<Vec<String>> {
+    let known = svc
+        .query
+        .get_currencies()
+        .await?
+        .into_iter()
+        .map(|currency| currency.name)
+        .collect::<BTreeSet<_>>();
+    let mappings = svc
+        .query
+        .get_all_currency_mappings()
+        .await?
+        .into_iter()
+        .map(|mapping| {
+            (
+                (AssetScope::from(mapping.scope), mapping.raw_currency),
+                mapping.currency,
+            )
+        })
+        .collect::<HashMap<_, _>>();
 
-    info!("Price fetch completed");
-    Ok(())
+    let mut currencies = known.clone();
+    let held = svc
+        .query
+        .get_raw_currencies_since(OffsetDateTime::now_utc() - config.holdings_window)
+        .await?;
+    for (scope, raw_currency) in held {
+        let key = (AssetScope::from(scope), raw_currency);
+        if let Some(currency) = mappings.get(&key) {
+            currencies.insert(currency.clone());
+        } else if !known.contains(&key.1) {
+            report.unmapped.push(format!("{:?}:{}", key.0, key.1));
+        }
+    }
+    Ok(currencies.into_iter().collect())
 }
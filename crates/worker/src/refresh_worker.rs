@@ -0,0 +1,344 @@
+//! On-demand refreshes requested through the `refresh_request` queue
+
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use hammer_entity::refresh_request;
+use hammer_service::{
+    HammerService,
+    types::{DataProvider, FinishedRefresh, RefreshStatus, RefreshTarget},
+};
+use time::OffsetDateTime;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, instrument};
+
+use crate::{
+    balance_worker,
+    job::{JobOutput, RunOptions},
+    limits::FetchLimits,
+    price_source::PriceResolver,
+    price_worker::{self, PriceConfig},
+    source::SourceRegistry,
+};
+
+/// Default time between polls of an empty queue
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Default time after which a running request whose claim was not extended is considered
+/// abandoned and claimed again
+const DEFAULT_CLAIM_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Polling of the refresh queue
+#[derive(Debug, Clone)]
+pub struct RefreshConfig {
+    pub poll_interval: Duration,
+    pub claim_timeout: Duration,
+}
+
+impl Default for RefreshConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            claim_timeout: DEFAULT_CLAIM_TIMEOUT,
+        }
+    }
+}
+
+impl RefreshConfig {
+    /// Creates a config from `REFRESH_POLL_INTERVAL` and `REFRESH_CLAIM_TIMEOUT`, or `None`
+    /// when `REFRESH_ENABLED` is off
+    pub fn from_env() -> Result<Option<Self>> {
+        let enabled = std::env::var("REFRESH_ENABLED")
+            .map(|value| !matches!(value.to_ascii_lowercase().as_str(), "0" | "false" | "off"))
+            .unwrap_or(true);
+        if !enabled {
+            return Ok(None);
+        }
+
+        let mut config = Self::default();
+        if let Ok(value) = std::env::var("REFRESH_POLL_INTERVAL") {
+            config.poll_interval = humantime::parse_duration(&value)
+                .map_err(|e| anyhow!("Invalid REFRESH_POLL_INTERVAL {value:?}: {e}"))?;
+        }
+        if let Ok(value) = std::env::var("REFRESH_CLAIM_TIMEOUT") {
+            config.claim_timeout = humantime::parse_duration(&value)
+                .map_err(|e| anyhow!("Invalid REFRESH_CLAIM_TIMEOUT {value:?}: {e}"))?;
+        }
+        if config.poll_interval.is_zero() {
+            return Err(anyhow!("REFRESH_POLL_INTERVAL must not be zero"));
+        }
+        if config.claim_timeout.is_zero() {
+            return Err(anyhow!("REFRESH_CLAIM_TIMEOUT must not be zero"));
+        }
+        Ok(Some(config))
+    }
+
+    /// Time between extensions of the claim on a running request, a third of the claim timeout
+    /// so that one late extension does not release it
+    pub fn heartbeat_interval(&self) -> Duration {
+        (self.claim_timeout / 3).max(Duration::from_millis(1))
+    }
+}
+
+/// Executes a claimed refresh request, out of the regular schedules
+///
+/// A wallet refresh stores the wallet's balances from each of its providers, and reports the
+/// balance of the highest-priority provider that succeeded. A price refresh prices one held
+/// currency, and a refresh of everything runs a full balance and price fetch. Failures are
+/// reported in the result rather than returned.
+#[instrument(skip_all, fields(request = request.id))]
+pub async fn refresh(
+    svc: &HammerService,
+    balances: &SourceRegistry,
+    limits: &FetchLimits,
+    prices: &PriceResolver,
+    price_config: &PriceConfig,
+    request: &refresh_request::Model,
+    token: &CancellationToken,
+) -> FinishedRefresh {
+    let result = match RefreshTarget::try_from(request) {
+        Ok(RefreshTarget::Wallet(wallet_id)) => {
+            refresh_wallet(svc, balances, limits, wallet_id, token).await
+        }
+        Ok(RefreshTarget::Price(currency)) => {
+            refresh_price(svc, prices, price_config, currency).await
+        }
+        Ok(RefreshTarget::All) => {
+            refresh_all(svc, balances, limits, prices, price_config, token).await
+        }
+        Err(e) => Err(anyhow!(e)),
+    };
+    let finished = result.unwrap_or_else(failed);
+    info!(
+        "Refresh request {} {:?}: balance {:?}, snapshot {:?}",
+        request.id, finished.status, finished.balance_id, finished.snapshot_id
+    );
+    finished
+}
+
+async fn refresh_wallet(
+    svc: &HammerService,
+    balances: &SourceRegistry,
+    limits: &FetchLimits,
+    wallet_id: i32,
+    token: &CancellationToken,
+) -> Result<FinishedRefresh> {
+    let options = RunOptions {
+        wallet_ids: vec![wallet_id],
+        ..Default::default()
+    };
+    let report = balance_worker::fetch_balances(
+        svc,
+        balances,
+        limits,
+        OffsetDateTime::now_utc(),
+        &options,
+        token,
+    )
+    .await?;
+    if report.skipped.contains(&wallet_id) {
+        return Err(anyhow!("No eligible balance source for wallet {wallet_id}"));
+    }
+
+    let priorities = svc.query.get_balance_priorities(wallet_id).await?;
+    let balance_id = priorities.iter().find_map(|priority| {
+        let provider = DataProvider::from(priority.provider);
+        report
+            .outcomes
+            .iter()
+            .find(|outcome| outcome.provider == provider)
+            .and_then(|outcome| outcome.balance_id)
+    });
+    let snapshot_id = report.snapshot_id;
+    let error = JobOutput::Balances(report)
+        .summary(token.is_cancelled())
+        .error_summary;
+    Ok(wallet_outcome(balance_id, snapshot_id, error))
+}
+
+async fn refresh_price(
+    svc: &HammerService,
+    prices: &PriceResolver,
+    price_config: &PriceConfig,
+    currency: String,
+) -> Result<FinishedRefresh> {
+    let options = RunOptions {
+        currencies: vec![currency.clone()],
+        ..Default::default()
+    };
+    let report = price_worker::fetch_prices(svc, prices, price_config, &options).await?;
+    let priced = report.stored > 0;
+    let error = JobOutput::Prices(report).summary(false).error_summary;
+    Ok(price_outcome(&currency, priced, error))
+}
+
+async fn refresh_all(
+    svc: &HammerService,
+    balances: &SourceRegistry,
+    limits: &FetchLimits,
+    prices: &PriceResolver,
+    price_config: &PriceConfig,
+    token: &CancellationToken,
+) -> Result<FinishedRefresh> {
+    let options = RunOptions::default();
+    let balances = balance_worker::fetch_balances(
+        svc,
+        balances,
+        limits,
+        OffsetDateTime::now_utc(),
+        &options,
+        token,
+    )
+    .await?;
+    let snapshot_id = balances.snapshot_id;
+    let prices = price_worker::fetch_prices(svc, prices, price_config, &options).await?;
+
+    let errors = [
+        JobOutput::Balances(balances).summary(token.is_cancelled()),
+        JobOutput::Prices(prices).summary(false),
+    ]
+    .into_iter()
+    .filter_map(|summary| summary.error_summary)
+    .collect::<Vec<_>>();
+    Ok(all_outcome(snapshot_id, errors))
+}
+
+/// Outcome of a request that could not run
+fn failed(error: anyhow::Error) -> FinishedRefresh {
+    FinishedRefresh {
+        status: RefreshStatus::Failed,
+        balance_id: None,
+        snapshot_id: None,
+        error: Some(format!("{error:#}")),
+    }
+}
+
+/// Outcome of a wallet refresh, succeeded when one of its prioritised providers stored a balance
+fn wallet_outcome(
+    balance_id: Option<i32>,
+    snapshot_id: Option<i32>,
+    error: Option<String>,
+) -> FinishedRefresh {
+    FinishedRefresh {
+        status: if balance_id.is_some() {
+            RefreshStatus::Succeeded
+        } else {
+            RefreshStatus::Failed
+        },
+        balance_id,
+        snapshot_id,
+        error,
+    }
+}
+
+/// Outcome of a price refresh, failed when no price was stored
+fn price_outcome(currency: &str, priced: bool, error: Option<String>) -> FinishedRefresh {
+    FinishedRefresh {
+        status: if priced {
+            RefreshStatus::Succeeded
+        } else {
+            RefreshStatus::Failed
+        },
+        balance_id: None,
+        snapshot_id: None,
+        error: error.or_else(|| (!priced).then(|| format!("{currency} is not held"))),
+    }
+}
+
+/// Outcome of a refresh of everything, succeeded with the errors of both jobs reported
+fn all_outcome(snapshot_id: Option<i32>, errors: Vec<String>) -> FinishedRefresh {
+    FinishedRefresh {
+        status: RefreshStatus::Succeeded,
+        balance_id: None,
+        snapshot_id,
+        error: (!errors.is_empty()).then(|| errors.join("; ")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hammer_entity::sea_orm_active_enums::{
+        RefreshStatus as EntityRefreshStatus, RefreshTarget as EntityRefreshTarget,
+    };
+
+    use super::*;
+
+    fn request(
+        target: EntityRefreshTarget,
+        wallet_id: Option<i32>,
+        currency: Option<&str>,
+    ) -> refresh_request::Model {
+        refresh_request::Model {
+            id: 1,
+            target,
+            wallet_id,
+            currency: currency.map(str::to_owned),
+            status: EntityRefreshStatus::Running,
+            requested_at: OffsetDateTime::UNIX_EPOCH,
+            started_at: Some(OffsetDateTime::UNIX_EPOCH),
+            finished_at: None,
+            balance_id: None,
+            snapshot_id: None,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn requests_without_their_subject_fail() {
+        let requests = [
+            request(EntityRefreshTarget::Wallet, None, Some("ETH")),
+            request(EntityRefreshTarget::Price, Some(7), None),
+        ];
+        for request in &requests {
+            let finished = failed(anyhow!(RefreshTarget::try_from(request).unwrap_err()));
+            assert_eq!(finished.status, RefreshStatus::Failed);
+            assert!(finished.error.unwrap().contains("without its subject"));
+        }
+        assert_eq!(
+            RefreshTarget::try_from(&request(EntityRefreshTarget::All, None, None)),
+            Ok(RefreshTarget::All)
+        );
+    }
+
+    #[test]
+    fn wallet_refresh_succeeds_with_a_balance() {
+        let finished = wallet_outcome(Some(3), Some(4), Some("cam: timeout".to_owned()));
+        assert_eq!(finished.status, RefreshStatus::Succeeded);
+        assert_eq!(
+            (finished.balance_id, finished.snapshot_id),
+            (Some(3), Some(4))
+        );
+        assert_eq!(finished.error.as_deref(), Some("cam: timeout"));
+
+        let finished = wallet_outcome(None, Some(4), None);
+        assert_eq!(finished.status, RefreshStatus::Failed);
+        assert_eq!(finished.snapshot_id, Some(4));
+    }
+
+    #[test]
+    fn price_refresh_fails_when_nothing_is_priced() {
+        let finished = price_outcome("ETH", true, None);
+        assert_eq!(finished.status, RefreshStatus::Succeeded);
+        assert_eq!((finished.balance_id, finished.snapshot_id), (None, None));
+        assert_eq!(finished.error, None);
+
+        let finished = price_outcome("ETH", false, None);
+        assert_eq!(finished.status, RefreshStatus::Failed);
+        assert_eq!(finished.error.as_deref(), Some("ETH is not held"));
+
+        let finished = price_outcome("ETH", false, Some("cam: timeout".to_owned()));
+        assert_eq!(finished.error.as_deref(), Some("cam: timeout"));
+    }
+
+    #[test]
+    fn full_refresh_succeeds_with_errors_reported() {
+        let finished = all_outcome(Some(4), Vec::new());
+        assert_eq!(finished.status, RefreshStatus::Succeeded);
+        assert_eq!((finished.balance_id, finished.snapshot_id), (None, Some(4)));
+        assert_eq!(finished.error, None);
+
+        let finished = all_outcome(None, vec!["balances".to_owned(), "prices".to_owned()]);
+        assert_eq!(finished.status, RefreshStatus::Succeeded);
+        assert_eq!(finished.error.as_deref(), Some("balances; prices"));
+    }
+}
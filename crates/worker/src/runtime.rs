@@ -0,0 +1,786 @@
+//! Embeddable worker runtime
+
+use std::{net::SocketAddr, pin::Pin, sync::Arc, time::Duration};
+
+use anyhow::{Result, anyhow};
+use hammer_entity::refresh_request;
+use hammer_service::{
+    HammerService,
+    types::{FinishedJobRun, FinishedRefresh, JobStatus, RefreshStatus},
+};
+use time::OffsetDateTime;
+use tokio::task::{JoinError, JoinHandle};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    account_source::AccountSource,
+    balance_backfill::{self, BackfillReport, BalanceBackfill},
+    balance_worker,
+    job::{Job, JobOutput, RunOptions, summarize_errors},
+    leader::LeaderElection,
+    limits::FetchLimits,
+    metrics::WorkerMetrics,
+    notifier::WebhookNotifier,
+    onchain::OnchainReader,
+    price_backfill::{
+        self, BackfillLimits, PriceBackfill, PriceBackfillReport, PriceHistoryRegistry,
+    },
+    price_source::PriceResolver,
+    price_worker::{self, PriceConfig},
+    refresh_worker::{self, RefreshConfig},
+    schedule::{JobSchedule, ScheduleConfig, Ticker},
+    server::{self, DEFAULT_READY_INTERVALS, ServerState},
+    shutdown::{self, JobHandle, ShutdownSummary, TickState},
+    source::SourceRegistry,
+    staleness::{StalenessChecker, StalenessConfig},
+    supervisor::{self, SupervisorConfig, WorkerHealth},
+    wallet_worker,
+};
+
+type ShutdownSignal = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Builds a worker on an existing service, e.g. inside an API server or a test
+///
+/// Nothing is read from the environment unless [`WorkerBuilder::from_env`] is used. Without a
+/// shutdown signal the worker runs until [`WorkerHandle::shutdown`] is called.
+pub struct WorkerBuilder {
+    svc: HammerService,
+    balances: SourceRegistry,
+    limits: FetchLimits,
+    prices: PriceResolver,
+    price_config: PriceConfig,
+    history: PriceHistoryRegistry,
+    backfill_limits: BackfillLimits,
+    onchain: Option<OnchainReader>,
+    accounts: Vec<Arc<dyn AccountSource>>,
+    staleness: StalenessChecker,
+    schedules: ScheduleConfig,
+    leader: Option<LeaderElection>,
+    supervisor: SupervisorConfig,
+    drain_timeout: Duration,
+    http_addr: Option<SocketAddr>,
+    ready_intervals: u32,
+    refresh: Option<RefreshConfig>,
+    shutdown: Option<ShutdownSignal>,
+}
+
+impl WorkerBuilder {
+    /// Creates a worker without sources, on the default schedules and without leader election
+    pub fn new(svc: HammerService) -> Self {
+        Self {
+            svc,
+            balances: SourceRegistry::new(),
+            limits: FetchLimits::default(),
+            prices: PriceResolver::new(),
+            price_config: PriceConfig::default(),
+            history: PriceHistoryRegistry::new(),
+            backfill_limits: BackfillLimits::default(),
+            onchain: None,
+            accounts: Vec::new(),
+            staleness: StalenessChecker::default(),
+            schedules: ScheduleConfig::default(),
+            leader: None,
+            supervisor: SupervisorConfig::default(),
+            drain_timeout: shutdown::DEFAULT_DRAIN_TIMEOUT,
+            http_addr: None,
+            ready_intervals: DEFAULT_READY_INTERVALS,
+            refresh: Some(RefreshConfig::default()),
+            shutdown: None,
+        }
+    }
+
+    /// Creates a worker configured from the environment
+    ///
+    /// The HTTP listener is enabled by `HTTP_ADDR`, e.g. `0.0.0.0:9090`, and `READY_INTERVALS`
+    /// sets how many intervals a job may go without success before the worker is not ready.
+    /// Jobs left without a source, e.g. wallets while no account source exists, are disabled
+    /// when the worker is spawned.
+    pub fn from_env(svc: HammerService) -> Result<Self> {
+        let balances = SourceRegistry::from_env()?;
+        let prices = PriceResolver::from_env()?;
+        let history = PriceHistoryRegistry::from_env()?;
+        if history.is_empty() {
+            warn!("No price history sources configured");
+        }
+
+        let mut staleness = StalenessChecker::new(StalenessConfig::from_env()?);
+        match WebhookNotifier::from_env()? {
+            Some(webhook) => staleness = staleness.with_notifier(Arc::new(webhook)),
+            None => warn!("No staleness notifier configured, stale data is only logged"),
+        }
+
+        let leader = LeaderElection::from_env()?;
+        match &leader {
+            Some(leader) => info!("Leader election enabled as {}", leader.holder()),
+            None => warn!("Leader election disabled, do not run several replicas"),
+        }
+
+        let mut builder = Self::new(svc);
+        if std::env::var("ETH_RPC_URL").is_ok() {
+            builder = builder.with_onchain_reader(OnchainReader::from_env()?);
+        }
+        if let Ok(addr) = std::env::var("HTTP_ADDR") {
+            builder = builder.with_http(
+                addr.parse()
+                    .map_err(|e| anyhow!("Invalid HTTP_ADDR {addr:?}: {e}"))?,
+            );
+        }
+        if let Ok(intervals) = std::env::var("READY_INTERVALS") {
+            builder = builder.with_ready_intervals(
+                intervals
+                    .parse()
+                    .map_err(|e| anyhow!("Invalid READY_INTERVALS {intervals:?}: {e}"))?,
+            );
+        }
+
+        Ok(builder
+            .with_balance_sources(balances)
+            .with_fetch_limits(FetchLimits::from_env()?)
+            .with_price_sources(prices)
+            .with_price_config(PriceConfig::from_env()?)
+            .with_price_history_sources(history)
+            .with_backfill_limits(BackfillLimits::from_env()?)
+            .with_staleness_checker(staleness)
+            .with_schedules(ScheduleConfig::from_env()?)
+            .with_leader_election(leader)
+            .with_supervisor(SupervisorConfig::from_env()?)
+            .with_refresh_polling(RefreshConfig::from_env()?)
+            .with_drain_timeout(shutdown::drain_timeout_from_env()?))
+    }
+
+    pub fn with_balance_sources(mut self, sources: SourceRegistry) -> Self {
+        self.balances = sources;
+        self
+    }
+
+    pub fn with_fetch_limits(mut self, limits: FetchLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn with_price_sources(mut self, resolver: PriceResolver) -> Self {
+        self.prices = resolver;
+        self
+    }
+
+    pub fn with_price_config(mut self, config: PriceConfig) -> Self {
+        self.price_config = config;
+        self
+    }
+
+    /// Historical price sources used by [`WorkerBuilder::backfill_prices`]
+    pub fn with_price_history_sources(mut self, sources: PriceHistoryRegistry) -> Self {
+        self.history = sources;
+        self
+    }
+
+    pub fn with_backfill_limits(mut self, limits: BackfillLimits) -> Self {
+        self.backfill_limits = limits;
+        self
+    }
+
+    /// On-chain reader used by [`WorkerBuilder::backfill_balances`]
+    pub fn with_onchain_reader(mut self, reader: OnchainReader) -> Self {
+        self.onchain = Some(reader);
+        self
+    }
+
+    pub fn with_account_source(mut self, source: Arc<dyn AccountSource>) -> Self {
+        self.accounts.push(source);
+        self
+    }
+
+    pub fn with_staleness_checker(mut self, checker: StalenessChecker) -> Self {
+        self.staleness = checker;
+        self
+    }
+
+    pub fn with_schedules(mut self, schedules: ScheduleConfig) -> Self {
+        self.schedules = schedules;
+        self
+    }
+
+    pub fn with_leader_election(mut self, leader: Option<LeaderElection>) -> Self {
+        self.leader = leader;
+        self
+    }
+
+    pub fn with_supervisor(mut self, supervisor: SupervisorConfig) -> Self {
+        self.supervisor = supervisor;
+        self
+    }
+
+    /// Time running ticks are given to finish once shutdown starts
+    pub fn with_drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    /// Serves `/healthz`, `/readyz` and `/metrics` on `addr`
+    pub fn with_http(mut self, addr: SocketAddr) -> Self {
+        self.http_addr = Some(addr);
+        self
+    }
+
+    /// Number of intervals a job may go without a successful run before `/readyz` fails
+    pub fn with_ready_intervals(mut self, intervals: u32) -> Self {
+        self.ready_intervals = intervals;
+        self
+    }
+
+    /// Polls the `refresh_request` queue, or never with `None`
+    pub fn with_refresh_polling(mut self, config: Option<RefreshConfig>) -> Self {
+        self.refresh = config;
+        self
+    }
+
+    /// Shuts the worker down when `signal` completes, e.g. [`shutdown::shutdown_signal`]
+    pub fn with_shutdown<S>(mut self, signal: S) -> Self
+    where
+        S: Future<Output = ()> + Send + 'static,
+    {
+        self.shutdown = Some(Box::pin(signal));
+        self
+    }
+
+    /// Fails if `job` has no source to fetch from
+    pub fn check_sources(&self, job: Job) -> Result<()> {
+        let missing = match job {
+            Job::Balances => self.balances.is_empty().then_some("balance"),
+            Job::Prices => self.prices.is_empty().then_some("price"),
+            Job::Wallets => self.accounts.is_empty().then_some("account"),
+            Job::Staleness => None,
+        };
+        match missing {
+            Some(kind) => Err(anyhow!("No {kind} sources configured for the {job} job")),
+            None => Ok(()),
+        }
+    }
+
+    /// Spawns every enabled job on the current Tokio runtime
+    ///
+    /// Enabled jobs without a source are disabled with a warning rather than run empty.
+    pub fn spawn(mut self) -> WorkerHandle {
+        for job in Job::ALL {
+            if self.schedules.get(job).enabled
+                && let Err(e) = self.check_sources(job)
+            {
+                warn!("{:#}, disabling it", e);
+                self.schedules.get_mut(job).enabled = false;
+            }
+        }
+
+        let token = CancellationToken::new();
+        let health = WorkerHealth::new(self.supervisor.max_crashes);
+        let metrics = WorkerMetrics::new();
+        let runtime = JobRuntime {
+            svc: self.svc.clone(),
+            sources: Arc::new(Sources {
+                balances: self.balances,
+                limits: self.limits,
+                prices: self.prices,
+                price_config: self.price_config,
+                accounts: self.accounts,
+                staleness: self.staleness,
+            }),
+            leader: self.leader.clone(),
+            supervisor: self.supervisor,
+            health: health.clone(),
+            metrics: metrics.clone(),
+        };
+        let handles = Job::ALL
+            .into_iter()
+            .filter_map(|job| {
+                spawn_job(
+                    runtime.clone(),
+                    job,
+                    self.schedules.get(job).clone(),
+                    token.child_token(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let poller = self.refresh.map(|config| {
+            tokio::spawn(run_refresh_poller(
+                self.svc.clone(),
+                runtime.sources.clone(),
+                config,
+                token.child_token(),
+            ))
+        });
+        info!("All workers spawned successfully");
+
+        if let Some(addr) = self.http_addr {
+            let state = ServerState {
+                svc: self.svc.clone(),
+                health: health.clone(),
+                metrics: metrics.clone(),
+                schedules: self.schedules.clone(),
+                ready_intervals: self.ready_intervals,
+            };
+            let token = token.clone();
+            tokio::spawn(async move {
+                if let Err(e) = server::serve(addr, state, token).await {
+                    error!("HTTP listener on {} failed: {:#}", addr, e);
+                }
+            });
+        }
+
+        let svc = self.svc;
+        let leader = self.leader;
+        let drain_timeout = self.drain_timeout;
+        let signal = self.shutdown;
+        let stop = token.clone();
+        let task = tokio::spawn(async move {
+            match signal {
+                Some(signal) => tokio::select! {
+                    _ = stop.cancelled() => {}
+                    _ = signal => info!("Shutdown signal received"),
+                },
+                None => stop.cancelled().await,
+            }
+            info!("Draining running jobs");
+
+            stop.cancel();
+            let (summary, ()) = tokio::join!(
+                shutdown::drain(handles, drain_timeout),
+                drain_refresh_poller(poller, drain_timeout),
+            );
+            for &(job, run) in &summary.interrupted_runs {
+                if let Err(e) = svc.query.interrupt_job_run(run).await {
+                    error!("Failed to record interrupted {} run: {:#}", job, e);
+                }
+            }
+            if let Some(leader) = &leader {
+                leader.release(&svc).await;
+            }
+            summary
+        });
+
+        WorkerHandle {
+            token,
+            health,
+            metrics,
+            task,
+        }
+    }
+
+    /// Runs one job once and returns its report, e.g. from cron or for debugging
+    ///
+    /// The run is recorded in `job_run` unless it is a dry run. The shutdown signal stops it at
+    /// the next wallet.
+    pub async fn run_once(self, job: Job, options: RunOptions) -> Result<JobOutput> {
+        self.check_sources(job)?;
+        let svc = self.svc;
+        let sources = Sources {
+            balances: self.balances,
+            limits: self.limits,
+            prices: self.prices,
+            price_config: self.price_config,
+            accounts: self.accounts,
+            staleness: self.staleness,
+        };
+
+        let token = CancellationToken::new();
+        if let Some(signal) = self.shutdown {
+            let token = token.clone();
+            tokio::spawn(async move {
+                signal.await;
+                token.cancel();
+            });
+        }
+
+        let scheduled = OffsetDateTime::now_utc();
+        if options.dry_run {
+            return run_tick(&svc, &sources, job, scheduled, &options, &token).await;
+        }
+        let run = svc.query.start_job_run(job.name()).await?;
+        let result = run_tick(&svc, &sources, job, scheduled, &options, &token).await;
+        let finished = match &result {
+            Ok(output) => finished_job_run(output, token.is_cancelled()),
+            Err(e) => failed_job_run(e),
+        };
+        svc.query.finish_job_run(run.id, finished).await?;
+        result
+    }
+
+    /// Backfills historical prices from the history source of the backfill's provider
+    ///
+    /// The shutdown signal stops the backfill after the current chunk; running it again
+    /// resumes from there.
+    pub async fn backfill_prices(self, backfill: PriceBackfill) -> Result<PriceBackfillReport> {
+        let source = self.history.get(backfill.provider).ok_or_else(|| {
+            anyhow!(
+                "No price history source configured for {:?}",
+                backfill.provider
+            )
+        })?;
+
+        let token = CancellationToken::new();
+        if let Some(signal) = self.shutdown {
+            let token = token.clone();
+            tokio::spawn(async move {
+                signal.await;
+                token.cancel();
+            });
+        }
+
+        price_backfill::backfill_prices(
+            &self.svc,
+            source.as_ref(),
+            backfill,
+            &self.backfill_limits,
+            &token,
+        )
+        .await
+    }
+
+    /// Backfills historical on-chain balances of a wallet with the on-chain reader
+    ///
+    /// The shutdown signal stops the backfill after the current time; running it again
+    /// resumes from there.
+    pub async fn backfill_balances(self, backfill: BalanceBackfill) -> Result<BackfillReport> {
+        let reader = self
+            .onchain
+            .ok_or_else(|| anyhow!("No on-chain reader configured, set ETH_RPC_URL"))?;
+
+        let token = CancellationToken::new();
+        if let Some(signal) = self.shutdown {
+            let token = token.clone();
+            tokio::spawn(async move {
+                signal.await;
+                token.cancel();
+            });
+        }
+
+        balance_backfill::backfill_onchain_balances(&self.svc, &reader, backfill, &token).await
+    }
+}
+
+/// Handle of a running worker
+pub struct WorkerHandle {
+    token: CancellationToken,
+    health: WorkerHealth,
+    metrics: WorkerMetrics,
+    task: JoinHandle<ShutdownSummary>,
+}
+
+impl WorkerHandle {
+    /// Crash counts of the jobs, e.g. for a health check
+    pub fn health(&self) -> &WorkerHealth {
+        &self.health
+    }
+
+    /// Metrics of the jobs, e.g. to serve them from an embedding process
+    pub fn metrics(&self) -> &WorkerMetrics {
+        &self.metrics
+    }
+
+    /// Starts shutting down without waiting
+    pub fn shutdown(&self) {
+        self.token.cancel();
+    }
+
+    /// Waits until the worker has shut down and its jobs are drained
+    pub async fn wait(self) -> Result<ShutdownSummary> {
+        Ok(self.task.await?)
+    }
+
+    /// Shuts down and waits for the jobs to drain
+    pub async fn stop(self) -> Result<ShutdownSummary> {
+        self.shutdown();
+        self.wait().await
+    }
+}
+
+/// Sources the jobs fetch from
+struct Sources {
+    balances: SourceRegistry,
+    limits: FetchLimits,
+    prices: PriceResolver,
+    price_config: PriceConfig,
+    accounts: Vec<Arc<dyn AccountSource>>,
+    staleness: StalenessChecker,
+}
+
+/// Runs one tick of a job scheduled at `scheduled`
+async fn run_tick(
+    svc: &HammerService,
+    sources: &Sources,
+    job: Job,
+    scheduled: OffsetDateTime,
+    options: &RunOptions,
+    token: &CancellationToken,
+) -> Result<JobOutput> {
+    Ok(match job {
+        Job::Balances => JobOutput::Balances(
+            balance_worker::fetch_balances(
+                svc,
+                &sources.balances,
+                &sources.limits,
+                scheduled,
+                options,
+                token,
+            )
+            .await?,
+        ),
+        Job::Prices => JobOutput::Prices(
+            price_worker::fetch_prices(svc, &sources.prices, &sources.price_config, options)
+                .await?,
+        ),
+        Job::Wallets => JobOutput::Wallets(
+            wallet_worker::sync_wallets(svc, &sources.accounts, options.dry_run, token).await?,
+        ),
+        Job::Staleness => JobOutput::Staleness(sources.staleness.check(svc, options).await?),
+    })
+}
+
+fn finished_job_run(output: &JobOutput, cancelled: bool) -> FinishedJobRun {
+    let summary = output.summary(cancelled);
+    FinishedJobRun {
+        status: if summary.interrupted {
+            JobStatus::Interrupted
+        } else {
+            JobStatus::Succeeded
+        },
+        wallets_processed: summary.wallets_processed,
+        error_summary: summary.error_summary,
+    }
+}
+
+fn failed_job_run(e: &anyhow::Error) -> FinishedJobRun {
+    FinishedJobRun {
+        status: JobStatus::Failed,
+        wallets_processed: None,
+        error_summary: summarize_errors([format!("{e:#}")]),
+    }
+}
+
+/// State shared by every job loop
+#[derive(Clone)]
+struct JobRuntime {
+    svc: HammerService,
+    sources: Arc<Sources>,
+    leader: Option<LeaderElection>,
+    supervisor: SupervisorConfig,
+    health: WorkerHealth,
+    metrics: WorkerMetrics,
+}
+
+/// Spawns a supervised job loop, restarted whenever it panics
+fn spawn_job(
+    runtime: JobRuntime,
+    job: Job,
+    schedule: JobSchedule,
+    token: CancellationToken,
+) -> Option<JobHandle> {
+    if !schedule.enabled {
+        info!("{} worker is disabled", job);
+        return None;
+    }
+    let tick = TickState::default();
+
+    let start = {
+        let runtime = runtime.clone();
+        let tick = tick.clone();
+        let token = token.clone();
+        move || {
+            run_job(
+                runtime.clone(),
+                job,
+                schedule.clone(),
+                tick.clone(),
+                token.clone(),
+            )
+        }
+    };
+    let handle = tokio::spawn(supervisor::supervise(
+        runtime.svc,
+        job,
+        runtime.supervisor,
+        runtime.health,
+        tick.clone(),
+        token,
+        start,
+    ));
+    Some(JobHandle { job, handle, tick })
+}
+
+/// Executes queued refresh requests one at a time until cancelled
+///
+/// Every replica polls the queue, and each request is claimed by one of them. A request
+/// interrupted by a shutdown is recorded as failed, or claimed again after the claim timeout
+/// when its worker was aborted.
+async fn run_refresh_poller(
+    svc: HammerService,
+    sources: Arc<Sources>,
+    config: RefreshConfig,
+    token: CancellationToken,
+) {
+    info!("Polling refresh requests every {:?}", config.poll_interval);
+    while !token.is_cancelled() {
+        let request = match svc.query.claim_refresh_request(config.claim_timeout).await {
+            Ok(Some(request)) => request,
+            Ok(None) => {
+                tokio::select! {
+                    _ = token.cancelled() => break,
+                    _ = tokio::time::sleep(config.poll_interval) => continue,
+                }
+            }
+            Err(e) => {
+                error!("Failed to claim refresh request: {:#}", e);
+                tokio::select! {
+                    _ = token.cancelled() => break,
+                    _ = tokio::time::sleep(config.poll_interval) => continue,
+                }
+            }
+        };
+
+        // Run on its own task so that a panic fails the request rather than the poller
+        let task = {
+            let svc = svc.clone();
+            let sources = sources.clone();
+            let request = request.clone();
+            let token = token.clone();
+            tokio::spawn(async move {
+                refresh_worker::refresh(
+                    &svc,
+                    &sources.balances,
+                    &sources.limits,
+                    &sources.prices,
+                    &sources.price_config,
+                    &request,
+                    &token,
+                )
+                .await
+            })
+        };
+        let finished = keep_claimed(&svc, &request, &config, task)
+            .await
+            .unwrap_or_else(|e| FinishedRefresh {
+                status: RefreshStatus::Failed,
+                balance_id: None,
+                snapshot_id: None,
+                error: Some(format!("Refresh task failed: {e}")),
+            });
+        if let Err(e) = svc.query.finish_refresh_request(request.id, finished).await {
+            error!("Failed to record refresh request {}: {:#}", request.id, e);
+        }
+    }
+}
+
+/// Waits for a refresh task, extending the request's claim every heartbeat until it finishes
+async fn keep_claimed(
+    svc: &HammerService,
+    request: &refresh_request::Model,
+    config: &RefreshConfig,
+    mut task: JoinHandle<FinishedRefresh>,
+) -> Result<FinishedRefresh, JoinError> {
+    let mut heartbeat = tokio::time::interval(config.heartbeat_interval());
+    heartbeat.tick().await;
+    loop {
+        tokio::select! {
+            result = &mut task => return result,
+            _ = heartbeat.tick() => {
+                if let Err(e) = svc.query.touch_refresh_request(request.id).await {
+                    warn!("Failed to extend claim on refresh request {}: {:#}", request.id, e);
+                }
+            }
+        }
+    }
+}
+
+/// Waits up to `timeout` for the refresh poller to finish its request, then aborts it
+async fn drain_refresh_poller(poller: Option<JoinHandle<()>>, timeout: Duration) {
+    let Some(mut poller) = poller else {
+        return;
+    };
+    if tokio::time::timeout(timeout, &mut poller).await.is_err() {
+        warn!(
+            "Refresh request interrupted after {:?} drain timeout",
+            timeout
+        );
+        poller.abort();
+    }
+}
+
+/// Runs a job on its schedule until cancelled, recording every tick in `job_run`
+///
+/// Cancellation stops the loop between ticks; a running tick receives the token and decides
+/// where it can stop safely. With leader election, ticks whose lease is taken by another
+/// replica are skipped, and a tick is cancelled when its lease is lost.
+async fn run_job(
+    runtime: JobRuntime,
+    job: Job,
+    schedule: JobSchedule,
+    state: TickState,
+    token: CancellationToken,
+) {
+    let JobRuntime {
+        svc,
+        sources,
+        leader,
+        health,
+        metrics,
+        ..
+    } = runtime;
+    let options = RunOptions::default();
+    let mut ticker = Ticker::new(schedule);
+    loop {
+        let due = tokio::select! {
+            _ = token.cancelled() => break,
+            result = ticker.tick() => match result {
+                Ok(due) => due,
+                Err(e) => {
+                    error!("Failed to schedule {} worker: {:#}", job, e);
+                    break;
+                }
+            },
+        };
+        if let Some(leader) = &leader
+            && !leader.acquire(&svc, job, due).await
+        {
+            debug!("{} tick at {} is run by another replica", job, due);
+            continue;
+        }
+
+        let _guard = state.start();
+        match svc.query.start_job_run(job.name()).await {
+            Ok(run) => state.set_run(Some(run.id)),
+            Err(e) => {
+                error!("Failed to record start of {} run: {:#}", job, e);
+                state.set_run(None);
+            }
+        }
+        let started = std::time::Instant::now();
+        let tick_token = token.child_token();
+        let tick = run_tick(&svc, &sources, job, due, &options, &tick_token);
+        let result = match &leader {
+            Some(leader) => tokio::select! {
+                result = tick => result,
+                _ = leader.hold(&svc, job, &tick_token) => unreachable!("lease renewal never completes"),
+            },
+            None => tick.await,
+        };
+        let finished = match &result {
+            Ok(output) => finished_job_run(output, tick_token.is_cancelled()),
+            Err(e) => {
+                error!("{} run failed: {:#}", job, e);
+                failed_job_run(e)
+            }
+        };
+        metrics.record_tick(
+            job,
+            finished.status,
+            started.elapsed(),
+            result.as_ref().ok(),
+        );
+        if let Some(id) = state.take_run()
+            && let Err(e) = svc.query.finish_job_run(id, finished).await
+        {
+            error!("Failed to record end of {} run: {:#}", job, e);
+        }
+        health.record_tick(job);
+    }
+    info!("{} worker stopped", job);
+}
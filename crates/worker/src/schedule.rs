@@ -0,0 +1,307 @@
+//! Job schedules: fixed intervals or cron expressions, with jitter and wall-clock alignment
+
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use croner::Cron;
+use time::OffsetDateTime;
+
+use crate::job::Job;
+
+/// When a job fires
+#[derive(Debug, Clone)]
+pub enum Trigger {
+    /// Every `period`; when `align` is set, on multiples of the period since the unix epoch,
+    /// so that e.g. a one-minute period fires on the minute
+    Interval { period: Duration, align: bool },
+    /// On every match of a cron expression, evaluated in UTC
+    Cron(Box<Cron>),
+}
+
+/// Schedule of one job
+#[derive(Debug, Clone)]
+pub struct JobSchedule {
+    pub enabled: bool,
+    pub trigger: Trigger,
+    /// Upper bound of a random delay added to every tick, to spread load across instances
+    pub jitter: Duration,
+}
+
+impl JobSchedule {
+    /// Fires every `period`, starting immediately
+    pub fn every(period: Duration) -> Self {
+        Self {
+            enabled: true,
+            trigger: Trigger::Interval {
+                period,
+                align: false,
+            },
+            jitter: Duration::ZERO,
+        }
+    }
+
+    /// Fires on every match of a cron expression, with optional leading seconds field
+    pub fn cron(expression: &str) -> Result<Self> {
+        Ok(Self {
+            enabled: true,
+            trigger: Trigger::Cron(Box::new(parse_cron(expression)?)),
+            jitter: Duration::ZERO,
+        })
+    }
+
+    /// Aligns an interval to wall-clock boundaries; cron schedules are always aligned
+    pub fn aligned(mut self) -> Self {
+        if let Trigger::Interval { align, .. } = &mut self.trigger {
+            *align = true;
+        }
+        self
+    }
+
+    pub fn with_jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    pub fn with_enabled(mut self, enabled: bool) -> Self {
+        self.enabled = enabled;
+        self
+    }
+
+    /// Replaces the trigger with an interval such as `5m` or a cron expression
+    ///
+    /// An interval keeps the alignment of the current trigger, so overriding only the period
+    /// of an aligned schedule still fires on wall-clock boundaries.
+    pub fn with_schedule(mut self, value: &str) -> Result<Self> {
+        self.trigger = match humantime::parse_duration(value) {
+            Ok(period) => Trigger::Interval {
+                period,
+                align: match self.trigger {
+                    Trigger::Interval { align, .. } => align,
+                    Trigger::Cron(_) => true,
+                },
+            },
+            Err(_) => Trigger::Cron(Box::new(parse_cron(value)?)),
+        };
+        Ok(self)
+    }
+
+    /// Overrides a default schedule from the environment
+    ///
+    /// For a job named `balances` the variables are `BALANCES_SCHEDULE` (an interval such as
+    /// `5m` or a cron expression), `BALANCES_ENABLED`, `BALANCES_JITTER` and `BALANCES_ALIGN`.
+    /// An interval keeps the default alignment unless `BALANCES_ALIGN` is set.
+    pub fn from_env(job: Job, default: JobSchedule) -> Result<Self> {
+        let prefix = job.name().to_uppercase();
+        let var = |suffix: &str| std::env::var(format!("{prefix}_{suffix}")).ok();
+
+        let mut schedule = match var("SCHEDULE") {
+            Some(value) => default
+                .with_schedule(&value)
+                .map_err(|e| anyhow!("Invalid {prefix}_SCHEDULE {value:?}: {e:#}"))?,
+            None => default,
+        };
+        if let Some(enabled) = var("ENABLED") {
+            schedule.enabled = parse_bool(&enabled)
+                .ok_or_else(|| anyhow!("Invalid {prefix}_ENABLED {enabled:?}"))?;
+        }
+        if let Some(jitter) = var("JITTER") {
+            schedule.jitter = humantime::parse_duration(&jitter)
+                .map_err(|e| anyhow!("Invalid {prefix}_JITTER {jitter:?}: {e}"))?;
+        }
+        if let Some(align) = var("ALIGN") {
+            let align =
+                parse_bool(&align).ok_or_else(|| anyhow!("Invalid {prefix}_ALIGN {align:?}"))?;
+            if let Trigger::Interval { align: aligned, .. } = &mut schedule.trigger {
+                *aligned = align;
+            }
+        }
+        if let Trigger::Interval { period, .. } = schedule.trigger
+            && period.is_zero()
+        {
+            return Err(anyhow!("{prefix}_SCHEDULE must not be zero"));
+        }
+        Ok(schedule)
+    }
+
+    /// Get the time between the next two ticks after `now`, which varies for cron schedules
+    pub fn interval_at(&self, now: OffsetDateTime) -> Result<Duration> {
+        match &self.trigger {
+            Trigger::Interval { period, .. } => Ok(*period),
+            Trigger::Cron(_) => {
+                let next = self.next_boundary(now)?;
+                Ok((self.next_boundary(next)? - next).try_into()?)
+            }
+        }
+    }
+
+    /// Get the first fire time strictly after `after` for aligned and cron schedules
+    fn next_boundary(&self, after: OffsetDateTime) -> Result<OffsetDateTime> {
+        match &self.trigger {
+            Trigger::Interval { period, .. } => {
+                let period = period.as_nanos() as i128;
+                let nanos = after.unix_timestamp_nanos();
+                let next = (nanos.div_euclid(period) + 1) * period;
+                Ok(OffsetDateTime::from_unix_timestamp_nanos(next)?)
+            }
+            Trigger::Cron(cron) => {
+                let after = chrono::DateTime::from_timestamp(after.unix_timestamp(), 0)
+                    .ok_or_else(|| anyhow!("Time {after} out of range"))?;
+                let next = cron.find_next_occurrence(&after, false)?;
+                Ok(OffsetDateTime::from_unix_timestamp(next.timestamp())?)
+            }
+        }
+    }
+}
+
+/// Schedules of every job
+#[derive(Debug, Clone)]
+pub struct ScheduleConfig {
+    pub balances: JobSchedule,
+    pub prices: JobSchedule,
+    pub wallets: JobSchedule,
+    pub staleness: JobSchedule,
+}
+
+impl ScheduleConfig {
+    /// Loads schedules from the environment, defaulting to aligned intervals of five minutes
+    /// for balances and staleness checks, one minute for prices and one hour for wallets
+    pub fn from_env() -> Result<Self> {
+        Ok(Self {
+            balances: JobSchedule::from_env(Job::Balances, Self::default().balances)?,
+            prices: JobSchedule::from_env(Job::Prices, Self::default().prices)?,
+            wallets: JobSchedule::from_env(Job::Wallets, Self::default().wallets)?,
+            staleness: JobSchedule::from_env(Job::Staleness, Self::default().staleness)?,
+        })
+    }
+
+    pub fn get(&self, job: Job) -> &JobSchedule {
+        match job {
+            Job::Balances => &self.balances,
+            Job::Prices => &self.prices,
+            Job::Wallets => &self.wallets,
+            Job::Staleness => &self.staleness,
+        }
+    }
+
+    pub fn get_mut(&mut self, job: Job) -> &mut JobSchedule {
+        match job {
+            Job::Balances => &mut self.balances,
+            Job::Prices => &mut self.prices,
+            Job::Wallets => &mut self.wallets,
+            Job::Staleness => &mut self.staleness,
+        }
+    }
+}
+
+impl Default for ScheduleConfig {
+    fn default() -> Self {
+        Self {
+            balances: JobSchedule::every(Duration::from_secs(300)).aligned(),
+            prices: JobSchedule::every(Duration::from_secs(60)).aligned(),
+            wallets: JobSchedule::every(Duration::from_secs(3600)).aligned(),
+            staleness: JobSchedule::every(Duration::from_secs(300)).aligned(),
+        }
+    }
+}
+
+/// Waits for the ticks of a schedule
+///
+/// Ticks missed while a run was still in progress are skipped rather than run back to back.
+pub struct Ticker {
+    schedule: JobSchedule,
+    last: Option<OffsetDateTime>,
+}
+
+impl Ticker {
+    pub fn new(schedule: JobSchedule) -> Self {
+        Self {
+            schedule,
+            last: None,
+        }
+    }
+
+    /// Sleeps until the next tick and returns its scheduled time, before jitter
+    pub async fn tick(&mut self) -> Result<OffsetDateTime> {
+        let now = OffsetDateTime::now_utc();
+        let due = match (&self.schedule.trigger, self.last) {
+            (Trigger::Interval { align: false, .. }, None) => now,
+            (
+                Trigger::Interval {
+                    period,
+                    align: false,
+                },
+                Some(last),
+            ) => {
+                let mut due = last + *period;
+                while due < now {
+                    due += *period;
+                }
+                due
+            }
+            (_, last) => self
+                .schedule
+                .next_boundary(last.map_or(now, |last| last.max(now)))?,
+        };
+        self.last = Some(due);
+
+        let jitter = self.schedule.jitter.mul_f64(fastrand::f64());
+        let wait = Duration::try_from(due - now).unwrap_or_default() + jitter;
+        tokio::time::sleep(wait).await;
+        Ok(due)
+    }
+}
+
+fn parse_cron(expression: &str) -> Result<Cron> {
+    Ok(Cron::new(expression).with_seconds_optional().parse()?)
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "0" | "false" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interval(schedule: &JobSchedule) -> (Duration, bool) {
+        match schedule.trigger {
+            Trigger::Interval { period, align } => (period, align),
+            Trigger::Cron(_) => panic!("expected an interval"),
+        }
+    }
+
+    #[test]
+    fn period_override_keeps_alignment() {
+        let aligned = JobSchedule::every(Duration::from_secs(300)).aligned();
+        let schedule = aligned.with_schedule("1m").unwrap();
+        assert_eq!(interval(&schedule), (Duration::from_secs(60), true));
+
+        let unaligned = JobSchedule::every(Duration::from_secs(300));
+        let schedule = unaligned.with_schedule("1m").unwrap();
+        assert_eq!(interval(&schedule), (Duration::from_secs(60), false));
+    }
+
+    #[test]
+    fn period_override_of_cron_is_aligned() {
+        let cron = JobSchedule::cron("0 * * * *").unwrap();
+        let schedule = cron.with_schedule("10m").unwrap();
+        assert_eq!(interval(&schedule), (Duration::from_secs(600), true));
+    }
+
+    #[test]
+    fn cron_override_replaces_interval() {
+        let schedule = JobSchedule::every(Duration::from_secs(300))
+            .with_schedule("*/5 * * * *")
+            .unwrap();
+        assert!(matches!(schedule.trigger, Trigger::Cron(_)));
+        assert!(
+            JobSchedule::every(Duration::from_secs(300))
+                .with_schedule("soon")
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,135 @@
+//! HTTP listener serving health, readiness and metrics
+
+use std::{collections::HashMap, net::SocketAddr};
+
+use anyhow::Result;
+use axum::{
+    Router,
+    extract::State,
+    http::{StatusCode, header},
+    response::IntoResponse,
+    routing::get,
+};
+use hammer_service::HammerService;
+use time::OffsetDateTime;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+use crate::{job::Job, metrics::WorkerMetrics, schedule::ScheduleConfig, supervisor::WorkerHealth};
+
+/// Default number of intervals a job may go without a successful run and stay ready
+pub(crate) const DEFAULT_READY_INTERVALS: u32 = 3;
+
+#[derive(Clone)]
+pub(crate) struct ServerState {
+    pub svc: HammerService,
+    pub health: WorkerHealth,
+    pub metrics: WorkerMetrics,
+    pub schedules: ScheduleConfig,
+    pub ready_intervals: u32,
+}
+
+/// Serves `/healthz`, `/readyz` and `/metrics` on `addr` until `token` is cancelled
+pub(crate) async fn serve(
+    addr: SocketAddr,
+    state: ServerState,
+    token: CancellationToken,
+) -> Result<()> {
+    let app = Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/metrics", get(metrics))
+        .with_state(state);
+
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    info!("HTTP listener on {}", listener.local_addr()?);
+    axum::serve(listener, app)
+        .with_graceful_shutdown(token.cancelled_owned())
+        .await?;
+    Ok(())
+}
+
+/// Healthy while the database is reachable and no job keeps crashing
+async fn healthz(State(state): State<ServerState>) -> impl IntoResponse {
+    if let Err(e) = state.svc.query.ping().await {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("database unreachable: {e}"),
+        );
+    }
+    let crashing = state.health.unhealthy_jobs();
+    if !crashing.is_empty() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("jobs crashing repeatedly: {}", join(&crashing)),
+        );
+    }
+    (StatusCode::OK, "ok".to_owned())
+}
+
+/// Ready while every enabled job succeeded within its last `ready_intervals` intervals
+///
+/// Runs of every replica count, as they share the `job_run` table.
+async fn readyz(State(state): State<ServerState>) -> impl IntoResponse {
+    match stale_jobs(&state).await {
+        Ok(stale) if stale.is_empty() => (StatusCode::OK, "ok".to_owned()),
+        Ok(stale) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("no recent successful run: {}", join(&stale)),
+        ),
+        Err(e) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("failed to check job runs: {e:#}"),
+        ),
+    }
+}
+
+async fn stale_jobs(state: &ServerState) -> Result<Vec<Job>> {
+    let now = OffsetDateTime::now_utc();
+    let last_success = state
+        .svc
+        .query
+        .get_last_successful_job_runs()
+        .await?
+        .into_iter()
+        .map(|run| (run.job_name, run.finished_at.unwrap_or(run.started_at)))
+        .collect::<HashMap<_, _>>();
+
+    let mut stale = Vec::new();
+    for job in Job::ALL {
+        let schedule = state.schedules.get(job);
+        if !schedule.enabled {
+            continue;
+        }
+        let max_age = schedule.interval_at(now)? * state.ready_intervals;
+        let fresh = last_success
+            .get(job.name())
+            .is_some_and(|finished| now - *finished <= max_age);
+        if !fresh {
+            stale.push(job);
+        }
+    }
+    Ok(stale)
+}
+
+async fn metrics(State(state): State<ServerState>) -> impl IntoResponse {
+    if let Err(e) = state.metrics.refresh_ages(&state.svc).await {
+        error!("Failed to refresh data ages: {:#}", e);
+    }
+    match state.metrics.encode() {
+        Ok(body) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+            body,
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            [(header::CONTENT_TYPE, "text/plain")],
+            format!("failed to encode metrics: {e:#}"),
+        ),
+    }
+}
+
+fn join(jobs: &[Job]) -> String {
+    jobs.iter().map(Job::name).collect::<Vec<_>>().join(", ")
+}
@@ -0,0 +1,168 @@
+//! Graceful shutdown of running jobs
+
+use std::{
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::Result;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::job::Job;
+
+/// Default time running ticks are given to finish after a shutdown signal
+pub(crate) const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Get the drain timeout from `SHUTDOWN_DRAIN_TIMEOUT`, e.g. `30s`
+pub fn drain_timeout_from_env() -> Result<Duration> {
+    match std::env::var("SHUTDOWN_DRAIN_TIMEOUT") {
+        Ok(value) => Ok(humantime::parse_duration(&value)?),
+        Err(_) => Ok(DEFAULT_DRAIN_TIMEOUT),
+    }
+}
+
+/// Waits for SIGINT or, on Unix, SIGTERM
+pub async fn shutdown_signal() -> Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result?,
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
+    Ok(())
+}
+
+/// Whether a job is in the middle of a tick, and the `job_run` recording it
+#[derive(Debug, Clone, Default)]
+pub struct TickState {
+    running: Arc<AtomicBool>,
+    run: Arc<Mutex<Option<i32>>>,
+}
+
+impl TickState {
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    /// Marks a tick as running until the returned guard is dropped
+    pub fn start(&self) -> TickGuard {
+        self.running.store(true, Ordering::SeqCst);
+        TickGuard(self.clone())
+    }
+
+    /// Remembers the `job_run` of the running tick
+    pub fn set_run(&self, id: Option<i32>) {
+        *self.run.lock().unwrap() = id;
+    }
+
+    /// Takes the `job_run` of the running tick, or of the tick that crashed
+    pub fn take_run(&self) -> Option<i32> {
+        self.run.lock().unwrap().take()
+    }
+}
+
+/// Marks the end of a tick when dropped
+pub struct TickGuard(TickState);
+
+impl Drop for TickGuard {
+    fn drop(&mut self) {
+        self.0.running.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Handle of a spawned job loop
+pub struct JobHandle {
+    pub job: Job,
+    pub handle: JoinHandle<()>,
+    pub tick: TickState,
+}
+
+/// Jobs that stopped cleanly and jobs that were still running when the drain timed out
+#[derive(Debug, Default)]
+pub struct ShutdownSummary {
+    pub stopped: Vec<Job>,
+    pub interrupted: Vec<Job>,
+    /// `job_run` IDs of the interrupted ticks
+    pub interrupted_runs: Vec<(Job, i32)>,
+}
+
+/// Waits up to `timeout` for cancelled jobs to finish their running tick, then aborts the rest
+pub async fn drain(handles: Vec<JobHandle>, timeout: Duration) -> ShutdownSummary {
+    let deadline = tokio::time::Instant::now() + timeout;
+    let mut summary = ShutdownSummary::default();
+    for JobHandle {
+        job,
+        mut handle,
+        tick,
+    } in handles
+    {
+        if tokio::time::timeout_at(deadline, &mut handle).await.is_ok() {
+            summary.stopped.push(job);
+            continue;
+        }
+        // Only a job caught mid-tick loses work
+        if tick.is_running() {
+            summary.interrupted.push(job);
+            if let Some(run) = tick.take_run() {
+                summary.interrupted_runs.push((job, run));
+            }
+        } else {
+            summary.stopped.push(job);
+        }
+        handle.abort();
+    }
+
+    if summary.interrupted.is_empty() {
+        info!("All jobs stopped: {:?}", summary.stopped);
+    } else {
+        warn!(
+            "Jobs interrupted after {:?} drain timeout: {:?}; stopped cleanly: {:?}",
+            timeout, summary.interrupted, summary.stopped
+        );
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn drain_reports_only_the_running_tick() {
+        let running = TickState::default();
+        let guard = running.start();
+        running.set_run(Some(7));
+        let idle = TickState::default();
+        idle.set_run(Some(8));
+        let handles = vec![
+            JobHandle {
+                job: Job::Balances,
+                handle: tokio::spawn(async move {
+                    let _guard = guard;
+                    std::future::pending::<()>().await
+                }),
+                tick: running,
+            },
+            JobHandle {
+                job: Job::Prices,
+                handle: tokio::spawn(std::future::pending()),
+                tick: idle,
+            },
+        ];
+
+        let summary = drain(handles, Duration::from_millis(10)).await;
+        assert_eq!(summary.interrupted, vec![Job::Balances]);
+        assert_eq!(summary.interrupted_runs, vec![(Job::Balances, 7)]);
+        assert_eq!(summary.stopped, vec![Job::Prices]);
+    }
+}
@@ -0,0 +1,173 @@
+//! Provider-agnostic balance sources
+
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use hammer_entity::{wallet, wallet_metadata};
+use hammer_service::{
+    HammerService,
+    types::{AssetScope, DataProvider, NewBalance, NewBalanceEntry},
+};
+use time::OffsetDateTime;
+
+use crate::{onchain::OnchainReader, pendle::PendleReader, stakestone::StakestoneReader};
+
+/// A wallet together with its metadata rows
+pub type WalletWithMetadata = (wallet::Model, Vec<wallet_metadata::Model>);
+
+/// Balances of one wallet as returned by a source, normalised for storage
+#[derive(Debug, Clone)]
+pub struct FetchedBalance {
+    pub provider: DataProvider,
+    pub time: OffsetDateTime,
+    pub block_number: Option<i64>,
+    pub block_hash: Option<String>,
+    pub entries: Vec<NewBalanceEntry>,
+}
+
+impl FetchedBalance {
+    /// Splits into the rows stored for the given wallet, as part of a snapshot
+    pub fn into_parts(
+        self,
+        wallet_id: i32,
+        snapshot_id: Option<i32>,
+    ) -> (NewBalance, Vec<NewBalanceEntry>) {
+        let balance = NewBalance {
+            wallet_id,
+            time: self.time,
+            provider: self.provider,
+            block_number: self.block_number,
+            block_hash: self.block_hash,
+            snapshot_id,
+        };
+        (balance, self.entries)
+    }
+}
+
+/// A provider of wallet balances
+#[async_trait]
+pub trait BalanceSource: Send + Sync {
+    /// Provider the fetched balances are attributed to
+    fn provider(&self) -> DataProvider;
+
+    /// Scopes of the wallets this source can fetch
+    fn scopes(&self) -> Vec<AssetScope>;
+
+    /// Called once per fetch with every wallet the source will be asked to fetch
+    ///
+    /// Sources that read all wallets at once, such as on-chain readers pinned to one block,
+    /// do their reads here and return them to be passed to `fetch`.
+    async fn prepare(
+        &self,
+        _svc: &HammerService,
+        _wallets: &[WalletWithMetadata],
+    ) -> Result<PreparedBalances> {
+        Ok(PreparedBalances::default())
+    }
+
+    /// Fetches the balances of one wallet, given what `prepare` returned for this fetch
+    async fn fetch(
+        &self,
+        wallet: &wallet::Model,
+        metadata: &[wallet_metadata::Model],
+        prepared: &PreparedBalances,
+    ) -> Result<FetchedBalance>;
+
+    /// Re-reads stored balances that are no longer valid, e.g. read at a reorged block
+    ///
+    /// Called after every full balance tick. Returns the number of balances re-read.
+    async fn recheck(&self, _svc: &HammerService) -> Result<usize> {
+        Ok(0)
+    }
+}
+
+/// Balances read ahead by a source during `prepare` for one fetch, or why they failed, keyed
+/// by wallet ID
+#[derive(Debug, Default)]
+pub struct PreparedBalances(HashMap<i32, Result<FetchedBalance, String>>);
+
+impl PreparedBalances {
+    /// Records the balance of a wallet, or why it could not be read
+    pub fn insert(&mut self, wallet_id: i32, balance: Result<FetchedBalance>) {
+        self.0
+            .insert(wallet_id, balance.map_err(|e| format!("{e:#}")));
+    }
+
+    /// Get the balance prepared for a wallet
+    pub fn get(&self, wallet_id: i32) -> Result<FetchedBalance> {
+        match self.0.get(&wallet_id) {
+            Some(Ok(balance)) => Ok(balance.clone()),
+            Some(Err(e)) => Err(anyhow!("{e}")),
+            None => Err(anyhow!("No balance prepared for wallet {wallet_id}")),
+        }
+    }
+}
+
+/// Balance sources keyed by provider and wallet scope
+#[derive(Clone, Default)]
+pub struct SourceRegistry {
+    sources: HashMap<(DataProvider, AssetScope), Arc<dyn BalanceSource>>,
+}
+
+impl SourceRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the sources configured in the environment
+    ///
+    /// On-chain sources require `ETH_RPC_URL`; the Pendle and StakeStone readers additionally
+    /// require `PENDLE_MARKETS` and `STAKESTONE_VAULT`.
+    pub fn from_env() -> Result<Self> {
+        let mut registry = Self::new();
+        if std::env::var("ETH_RPC_URL").is_ok() {
+            let onchain = OnchainReader::from_env()?;
+            if std::env::var("PENDLE_MARKETS").is_ok() {
+                registry.register(Arc::new(PendleReader::from_env(onchain.clone())?));
+            }
+            if std::env::var("STAKESTONE_VAULT").is_ok() {
+                registry.register(Arc::new(StakestoneReader::from_env(onchain.clone())?));
+            }
+            registry.register(Arc::new(onchain));
+        }
+        Ok(registry)
+    }
+
+    /// Registers a source for every scope it supports, replacing existing registrations
+    pub fn register(&mut self, source: Arc<dyn BalanceSource>) {
+        for scope in source.scopes() {
+            self.sources
+                .insert((source.provider(), scope), Arc::clone(&source));
+        }
+    }
+
+    /// Get the source of a provider for a scope
+    pub fn get(&self, provider: DataProvider, scope: AssetScope) -> Option<Arc<dyn BalanceSource>> {
+        self.sources.get(&(provider, scope)).cloned()
+    }
+
+    /// Get every source that can fetch wallets of a scope
+    pub fn sources_for(&self, scope: AssetScope) -> Vec<Arc<dyn BalanceSource>> {
+        self.sources
+            .iter()
+            .filter(|((_, source_scope), _)| *source_scope == scope)
+            .map(|(_, source)| Arc::clone(source))
+            .collect()
+    }
+
+    /// Get every registered source once, however many scopes it is registered for
+    pub fn all(&self) -> Vec<Arc<dyn BalanceSource>> {
+        let mut sources = Vec::<Arc<dyn BalanceSource>>::new();
+        for source in self.sources.values() {
+            if !sources.iter().any(|known| Arc::ptr_eq(known, source)) {
+                sources.push(Arc::clone(source));
+            }
+        }
+        sources
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+}
@@ -0,0 +1,239 @@
+//! StakeStone position reader for `AssetScope::Stakestone` wallets
+
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use chain_client::{
+    abi::Address,
+    stakestone::StakestonePosition,
+    types::{Asset, Block, BlockTag},
+};
+use hammer_entity::{sea_orm_active_enums::AssetScope, wallet, wallet_metadata};
+use hammer_service::{
+    HammerService,
+    types::{AssetScope as ServiceAssetScope, DataProvider, NewBalanceEntry},
+};
+use rust_decimal::Decimal;
+use tracing::debug;
+
+use crate::{
+    onchain::{
+        AnchoredReader, OnchainReader, OwnerEntries, prepare_pinned, raw_currencies, recheck_reorgs,
+    },
+    source::{BalanceSource, FetchedBalance, PreparedBalances, WalletWithMetadata},
+};
+
+/// Raw currency of STONE share balances
+pub const STONE_CURRENCY: &str = "STONE";
+
+/// Raw currency of the ETH value of a withdrawal queued in a round that is not settled yet
+pub const PENDING_WITHDRAWAL_CURRENCY: &str = "STONE_WITHDRAWAL_PENDING";
+
+/// Raw currency of ETH from settled withdrawals, queued in a settled round or claimable
+pub const SETTLED_WITHDRAWAL_CURRENCY: &str = "STONE_WITHDRAWAL_SETTLED";
+
+/// Reads STONE holdings and pending withdrawals from the StakeStone vault
+///
+/// Each balance carries the held STONE shares, their ETH value at the current share price,
+/// and the ETH value of pending and settled withdrawals. The ETH value of held shares is
+/// stored under the raw currency mapping ETH in the `stakestone` scope, or `ETH` if unmapped.
+/// Only one of the STONE and ETH entries should be mapped in `currency_map`, or held shares
+/// are counted twice; withdrawal entries are amounts of ETH.
+#[derive(Clone)]
+pub struct StakestoneReader {
+    onchain: OnchainReader,
+    vault: Address,
+}
+
+impl StakestoneReader {
+    pub fn new(onchain: OnchainReader, vault: Address) -> Self {
+        Self { onchain, vault }
+    }
+
+    /// Creates a reader for the vault address in `STAKESTONE_VAULT`
+    pub fn from_env(onchain: OnchainReader) -> Result<Self> {
+        let vault = std::env::var("STAKESTONE_VAULT")
+            .map_err(|_| anyhow!("STAKESTONE_VAULT is not set"))?
+            .parse()?;
+        Ok(Self::new(onchain, vault))
+    }
+}
+
+#[async_trait]
+impl AnchoredReader for StakestoneReader {
+    fn onchain(&self) -> &OnchainReader {
+        &self.onchain
+    }
+
+    fn scope(&self) -> AssetScope {
+        AssetScope::Stakestone
+    }
+
+    /// Reads the vault and the positions of the owners in it
+    async fn read_at(
+        &self,
+        svc: &HammerService,
+        owners: &[Address],
+        block: Block,
+    ) -> Result<OwnerEntries> {
+        let client = self.onchain.client();
+        let tag = BlockTag::Hash(block.hash);
+        let vault = client.read_stakestone_vault(self.vault, tag).await?;
+        let positions = client
+            .read_stakestone_positions(owners, &vault, tag)
+            .await?;
+        for position in &positions {
+            if let Some(pending) = &position.pending {
+                debug!(
+                    "{} has {} STONE queued for withdrawal in round {} ({}, {} ETH)",
+                    position.owner,
+                    pending.shares,
+                    pending.round,
+                    if pending.settled {
+                        "settled"
+                    } else {
+                        "pending"
+                    },
+                    pending.eth_amount,
+                );
+            }
+        }
+
+        let raw_currency = raw_currencies(svc, AssetScope::Stakestone)
+            .await?
+            .remove(&Asset::Native)
+            .unwrap_or_else(|| Asset::NATIVE_SYMBOL.to_owned());
+        Ok(positions
+            .iter()
+            .map(|position| {
+                (
+                    position.owner,
+                    Ok(position_entries(position, &raw_currency)),
+                )
+            })
+            .collect())
+    }
+}
+
+#[async_trait]
+impl BalanceSource for StakestoneReader {
+    fn provider(&self) -> DataProvider {
+        DataProvider::Onchain
+    }
+
+    fn scopes(&self) -> Vec<ServiceAssetScope> {
+        vec![ServiceAssetScope::Stakestone]
+    }
+
+    /// Reads positions of every StakeStone wallet at one pinned block
+    async fn prepare(
+        &self,
+        svc: &HammerService,
+        wallets: &[WalletWithMetadata],
+    ) -> Result<PreparedBalances> {
+        prepare_pinned(self, svc, wallets).await
+    }
+
+    async fn fetch(
+        &self,
+        wallet: &wallet::Model,
+        _metadata: &[wallet_metadata::Model],
+        prepared: &PreparedBalances,
+    ) -> Result<FetchedBalance> {
+        prepared.get(wallet.id)
+    }
+
+    async fn recheck(&self, svc: &HammerService) -> Result<usize> {
+        recheck_reorgs(self, svc).await
+    }
+}
+
+/// Expresses a position as STONE shares, their ETH value and its withdrawals in ETH
+pub fn position_entries(position: &StakestonePosition, raw_currency: &str) -> Vec<NewBalanceEntry> {
+    let (pending, settled) = match &position.pending {
+        Some(request) if request.settled => (Decimal::ZERO, request.eth_amount),
+        Some(request) => (request.eth_amount, Decimal::ZERO),
+        None => (Decimal::ZERO, Decimal::ZERO),
+    };
+    [
+        (STONE_CURRENCY, position.shares),
+        (raw_currency, position.shares_eth),
+        (PENDING_WITHDRAWAL_CURRENCY, pending),
+        (
+            SETTLED_WITHDRAWAL_CURRENCY,
+            settled + position.withdrawable_eth,
+        ),
+    ]
+    .into_iter()
+    .filter(|(_, amount)| !amount.is_zero())
+    .map(|(raw_currency, amount)| NewBalanceEntry {
+        balance_id: 0, // assigned on insert
+        raw_currency: raw_currency.to_owned(),
+        amount,
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use chain_client::stakestone::WithdrawalRequest;
+
+    use super::*;
+
+    fn position(shares: u32, pending: Option<u32>) -> StakestonePosition {
+        StakestonePosition {
+            owner: Address([0x01; 20]),
+            shares: Decimal::from(shares),
+            shares_eth: Decimal::from(shares) * Decimal::new(105, 2),
+            withdrawable_eth: Decimal::ONE,
+            pending: pending.map(|shares| WithdrawalRequest {
+                round: 3,
+                shares: Decimal::from(shares),
+                settled: true,
+                eth_amount: Decimal::from(shares),
+            }),
+        }
+    }
+
+    fn amounts(entries: &[NewBalanceEntry]) -> Vec<(&str, Decimal)> {
+        entries
+            .iter()
+            .map(|entry| (entry.raw_currency.as_str(), entry.amount))
+            .collect()
+    }
+
+    #[test]
+    fn position_entries_hold_shares_eth_and_settled_withdrawals() {
+        let entries = position_entries(&position(2, Some(4)), "eth");
+        assert_eq!(
+            amounts(&entries),
+            vec![
+                (STONE_CURRENCY, Decimal::from(2)),
+                ("eth", Decimal::new(210, 2)),
+                (SETTLED_WITHDRAWAL_CURRENCY, Decimal::from(5)),
+            ]
+        );
+    }
+
+    #[test]
+    fn position_entries_keep_pending_withdrawals_apart() {
+        let mut queued = position(2, Some(4));
+        queued.withdrawable_eth = Decimal::ZERO;
+        queued.pending.as_mut().unwrap().settled = false;
+        let entries = position_entries(&queued, Asset::NATIVE_SYMBOL);
+        assert_eq!(
+            amounts(&entries),
+            vec![
+                (STONE_CURRENCY, Decimal::from(2)),
+                (Asset::NATIVE_SYMBOL, Decimal::new(210, 2)),
+                (PENDING_WITHDRAWAL_CURRENCY, Decimal::from(4)),
+            ]
+        );
+    }
+
+    #[test]
+    fn position_entries_skip_empty_positions() {
+        let mut empty = position(0, None);
+        empty.withdrawable_eth = Decimal::ZERO;
+        assert!(position_entries(&empty, Asset::NATIVE_SYMBOL).is_empty());
+    }
+}
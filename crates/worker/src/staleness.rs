@@ -0,0 +1,251 @@
+//! Staleness checks of balances and prices against maximum ages
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use anyhow::{Result, anyhow};
+use hammer_entity::sea_orm_active_enums::DataProvider as EntityDataProvider;
+use hammer_service::{HammerService, types::DataProvider};
+use sea_orm::Iterable;
+use time::OffsetDateTime;
+use tracing::{error, info, instrument, warn};
+
+use crate::{
+    job::RunOptions,
+    notifier::{Notifier, StaleAlert},
+};
+
+/// Default age after which the balance of a wallet from a provider is stale
+const DEFAULT_BALANCE_MAX_AGE: Duration = Duration::from_secs(30 * 60);
+
+/// Default age after which the price of a currency is stale
+const DEFAULT_PRICE_MAX_AGE: Duration = Duration::from_secs(10 * 60);
+
+/// Maximum ages of balances, per provider, and of prices
+#[derive(Debug, Clone)]
+pub struct StalenessConfig {
+    balances: HashMap<DataProvider, Duration>,
+    price_max_age: Duration,
+}
+
+impl Default for StalenessConfig {
+    fn default() -> Self {
+        Self::new(DEFAULT_BALANCE_MAX_AGE, DEFAULT_PRICE_MAX_AGE)
+    }
+}
+
+impl StalenessConfig {
+    /// Creates maximum ages with the same balance age for every provider
+    pub fn new(balance_max_age: Duration, price_max_age: Duration) -> Self {
+        Self {
+            balances: EntityDataProvider::iter()
+                .map(|provider| (provider.into(), balance_max_age))
+                .collect(),
+            price_max_age,
+        }
+    }
+
+    /// Creates maximum ages from the environment
+    ///
+    /// Reads `BALANCE_MAX_AGE` for every provider, `BALANCE_MAX_AGE_<PROVIDER>` (e.g.
+    /// `BALANCE_MAX_AGE_ONCHAIN`) for each provider, and `PRICE_MAX_AGE`, e.g. `30m`.
+    pub fn from_env() -> Result<Self> {
+        let mut config = Self::new(
+            max_age_from_env("BALANCE_MAX_AGE")?.unwrap_or(DEFAULT_BALANCE_MAX_AGE),
+            max_age_from_env("PRICE_MAX_AGE")?.unwrap_or(DEFAULT_PRICE_MAX_AGE),
+        );
+        for (provider, max_age) in &mut config.balances {
+            let key = format!("BALANCE_MAX_AGE_{provider:?}").to_uppercase();
+            if let Some(value) = max_age_from_env(&key)? {
+                *max_age = value;
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn with_balance_max_age(mut self, provider: DataProvider, max_age: Duration) -> Self {
+        self.balances.insert(provider, max_age);
+        self
+    }
+
+    pub fn with_price_max_age(mut self, max_age: Duration) -> Self {
+        self.price_max_age = max_age;
+        self
+    }
+
+    pub fn balance_max_age(&self, provider: DataProvider) -> Duration {
+        self.balances
+            .get(&provider)
+            .copied()
+            .unwrap_or(DEFAULT_BALANCE_MAX_AGE)
+    }
+
+    pub fn price_max_age(&self) -> Duration {
+        self.price_max_age
+    }
+}
+
+/// Outcome of a staleness check
+#[derive(Debug, Default)]
+pub struct StalenessReport {
+    /// Balances checked, one per wallet and prioritised provider
+    pub balances_checked: usize,
+    /// Currencies checked
+    pub prices_checked: usize,
+    pub alerts: Vec<StaleAlert>,
+    /// Notifiers that failed to send the alerts, empty in a dry run
+    pub failed_notifiers: Vec<(String, String)>,
+}
+
+/// Checks data freshness and sends alerts to notifiers
+#[derive(Clone, Default)]
+pub struct StalenessChecker {
+    config: StalenessConfig,
+    notifiers: Vec<Arc<dyn Notifier>>,
+}
+
+impl StalenessChecker {
+    pub fn new(config: StalenessConfig) -> Self {
+        Self {
+            config,
+            notifiers: Vec::new(),
+        }
+    }
+
+    pub fn with_notifier(mut self, notifier: Arc<dyn Notifier>) -> Self {
+        self.notifiers.push(notifier);
+        self
+    }
+
+    pub fn config(&self) -> &StalenessConfig {
+        &self.config
+    }
+
+    /// Compares the newest balance of every wallet from each prioritised provider, and the
+    /// newest price of every currency with a price provider, against their maximum ages
+    ///
+    /// Only wallets and currencies selected by `options` are checked. Alerts are sent to every
+    /// notifier on each check for as long as the data stays stale, except in a dry run. A
+    /// failing notifier is reported without failing the check.
+    #[instrument(skip_all)]
+    pub async fn check(
+        &self,
+        svc: &HammerService,
+        options: &RunOptions,
+    ) -> Result<StalenessReport> {
+        info!("Starting staleness check");
+        let now = OffsetDateTime::now_utc();
+        let mut report = StalenessReport::default();
+
+        for (wallet_id, provider, latest) in
+            svc.query.get_latest_balance_times_by_provider().await?
+        {
+            if !options.includes_wallet(wallet_id) {
+                continue;
+            }
+            report.balances_checked += 1;
+            let max_age = self.config.balance_max_age(provider);
+            if is_stale(now, latest, max_age) {
+                report.alerts.push(StaleAlert::Balance {
+                    wallet_id,
+                    provider,
+                    latest,
+                    max_age_secs: max_age.as_secs(),
+                });
+            }
+        }
+        for (currency, latest) in svc
+            .query
+            .get_latest_price_times_by_priced_currency()
+            .await?
+        {
+            if !options.includes_currency(&currency) {
+                continue;
+            }
+            report.prices_checked += 1;
+            let max_age = self.config.price_max_age;
+            if is_stale(now, latest, max_age) {
+                report.alerts.push(StaleAlert::Price {
+                    currency,
+                    latest,
+                    max_age_secs: max_age.as_secs(),
+                });
+            }
+        }
+
+        for alert in &report.alerts {
+            warn!("Stale data: {:?}", alert);
+        }
+        if !report.alerts.is_empty() && !options.dry_run {
+            report.failed_notifiers = self.notify(now, &report.alerts).await;
+        }
+
+        info!(
+            "Staleness check completed: {} balances and {} prices checked, {} stale",
+            report.balances_checked,
+            report.prices_checked,
+            report.alerts.len()
+        );
+        Ok(report)
+    }
+
+    /// Sends alerts to every notifier, returning the notifiers that failed with their errors
+    async fn notify(
+        &self,
+        checked_at: OffsetDateTime,
+        alerts: &[StaleAlert],
+    ) -> Vec<(String, String)> {
+        let mut failed = Vec::new();
+        for notifier in &self.notifiers {
+            if let Err(e) = notifier.notify(checked_at, alerts).await {
+                error!("Failed to notify {}: {:#}", notifier.name(), e);
+                failed.push((notifier.name().to_owned(), format!("{e:#}")));
+            }
+        }
+        failed
+    }
+}
+
+fn is_stale(now: OffsetDateTime, latest: Option<OffsetDateTime>, max_age: Duration) -> bool {
+    latest.is_none_or(|latest| now - latest > max_age)
+}
+
+fn max_age_from_env(key: &str) -> Result<Option<Duration>> {
+    let Ok(value) = std::env::var(key) else {
+        return Ok(None);
+    };
+    humantime::parse_duration(&value)
+        .map(Some)
+        .map_err(|e| anyhow!("Invalid {key} {value:?}: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+
+    use super::*;
+    use crate::notifier::{
+        WebhookNotifier,
+        tests::{alerts, local_webhook},
+    };
+
+    #[tokio::test]
+    async fn reports_failing_notifiers_and_notifies_the_rest() {
+        let (failing, _) = local_webhook(StatusCode::INTERNAL_SERVER_ERROR).await;
+        let (working, received) = local_webhook(StatusCode::NO_CONTENT).await;
+        let checker = StalenessChecker::default()
+            .with_notifier(Arc::new(WebhookNotifier::new(failing).unwrap()))
+            .with_notifier(Arc::new(WebhookNotifier::new(working).unwrap()));
+
+        let failed = checker
+            .notify(
+                OffsetDateTime::from_unix_timestamp(1_767_229_200).unwrap(),
+                &alerts(),
+            )
+            .await;
+
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].0, "webhook");
+        assert!(failed[0].1.contains("500"), "{}", failed[0].1);
+        assert_eq!(received.lock().unwrap().len(), 1);
+    }
+}
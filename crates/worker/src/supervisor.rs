@@ -0,0 +1,194 @@
+//! Supervision of job loops: restarts after panics, with backoff and crash tracking
+
+use std::{
+    any::Any,
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{Result, anyhow};
+use hammer_service::{
+    HammerService,
+    types::{FinishedJobRun, JobStatus},
+};
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use crate::{
+    job::{Job, summarize_errors},
+    shutdown::TickState,
+};
+
+/// Default delay before restarting a job after its first crash
+const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Default upper bound of the restart delay
+const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Default number of consecutive crashes of a job after which the worker is unhealthy
+const DEFAULT_MAX_CRASHES: u32 = 5;
+
+/// Restart policy of crashed job loops
+#[derive(Debug, Clone)]
+pub struct SupervisorConfig {
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub max_crashes: u32,
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        Self {
+            initial_backoff: DEFAULT_INITIAL_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            max_crashes: DEFAULT_MAX_CRASHES,
+        }
+    }
+}
+
+impl SupervisorConfig {
+    /// Creates a policy from `RESTART_BACKOFF`, `RESTART_BACKOFF_MAX` and `MAX_JOB_CRASHES`
+    pub fn from_env() -> Result<Self> {
+        let mut config = Self::default();
+        if let Ok(value) = std::env::var("RESTART_BACKOFF") {
+            config.initial_backoff = humantime::parse_duration(&value)
+                .map_err(|e| anyhow!("Invalid RESTART_BACKOFF {value:?}: {e}"))?;
+        }
+        if let Ok(value) = std::env::var("RESTART_BACKOFF_MAX") {
+            config.max_backoff = humantime::parse_duration(&value)
+                .map_err(|e| anyhow!("Invalid RESTART_BACKOFF_MAX {value:?}: {e}"))?;
+        }
+        if let Ok(value) = std::env::var("MAX_JOB_CRASHES") {
+            config.max_crashes = value
+                .parse()
+                .map_err(|e| anyhow!("Invalid MAX_JOB_CRASHES {value:?}: {e}"))?;
+        }
+        Ok(config)
+    }
+
+    /// Delay before restarting a job that crashed `crashes` times in a row
+    pub fn backoff(&self, crashes: u32) -> Duration {
+        let factor = 2u32.saturating_pow(crashes.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+/// Consecutive crashes of every job, shared by the supervisors and health checks
+#[derive(Debug, Clone)]
+pub struct WorkerHealth {
+    crashes: Arc<Mutex<HashMap<Job, u32>>>,
+    max_crashes: u32,
+}
+
+impl WorkerHealth {
+    pub fn new(max_crashes: u32) -> Self {
+        Self {
+            crashes: Default::default(),
+            max_crashes,
+        }
+    }
+
+    /// Whether every job crashed fewer than `max_crashes` times in a row
+    pub fn is_healthy(&self) -> bool {
+        self.unhealthy_jobs().is_empty()
+    }
+
+    /// Jobs that crashed at least `max_crashes` times in a row
+    pub fn unhealthy_jobs(&self) -> Vec<Job> {
+        let crashes = self.crashes.lock().unwrap();
+        Job::ALL
+            .into_iter()
+            .filter(|job| crashes.get(job).is_some_and(|n| *n >= self.max_crashes))
+            .collect()
+    }
+
+    /// Consecutive crashes of a job
+    pub fn crashes(&self, job: Job) -> u32 {
+        self.crashes.lock().unwrap().get(&job).copied().unwrap_or(0)
+    }
+
+    /// Counts a crash, returning the number of consecutive crashes
+    pub fn record_crash(&self, job: Job) -> u32 {
+        let mut crashes = self.crashes.lock().unwrap();
+        let count = crashes.entry(job).or_default();
+        *count += 1;
+        *count
+    }
+
+    /// Resets the crash count of a job after a completed tick
+    pub fn record_tick(&self, job: Job) {
+        self.crashes.lock().unwrap().remove(&job);
+    }
+}
+
+/// Runs the job loop built by `start` until `token` is cancelled, restarting it when it panics
+/// or returns early
+///
+/// A crash is logged, the tick it interrupted is recorded as failed in `job_run`, and the loop
+/// is restarted after an exponential backoff. The supervised loop is aborted when the
+/// supervisor itself is aborted.
+pub async fn supervise<F, Fut>(
+    svc: HammerService,
+    job: Job,
+    config: SupervisorConfig,
+    health: WorkerHealth,
+    tick: TickState,
+    token: CancellationToken,
+    start: F,
+) where
+    F: Fn() -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    loop {
+        let mut tasks = JoinSet::new();
+        tasks.spawn(start());
+        let reason = match tasks.join_next().await {
+            Some(Ok(())) if token.is_cancelled() => break,
+            Some(Ok(())) => "stopped unexpectedly".to_owned(),
+            Some(Err(e)) if e.is_panic() => format!("panicked: {}", panic_message(e.into_panic())),
+            Some(Err(e)) => format!("failed: {e}"),
+            None => unreachable!("one loop is spawned"),
+        };
+
+        let crashes = health.record_crash(job);
+        error!("{} worker {} ({} in a row)", job, reason, crashes);
+        if crashes == config.max_crashes {
+            error!(
+                "{} worker crashed {} times in a row, marking the worker unhealthy",
+                job, crashes
+            );
+        }
+        if let Some(id) = tick.take_run() {
+            let finished = FinishedJobRun {
+                status: JobStatus::Failed,
+                wallets_processed: None,
+                error_summary: summarize_errors([format!("Worker {reason}")]),
+            };
+            if let Err(e) = svc.query.finish_job_run(id, finished).await {
+                error!("Failed to record crash of {} run: {:#}", job, e);
+            }
+        }
+
+        let backoff = config.backoff(crashes);
+        warn!("Restarting {} worker in {:?}", job, backoff);
+        tokio::select! {
+            _ = token.cancelled() => break,
+            _ = tokio::time::sleep(backoff) => {}
+        }
+        info!("{} worker restarted", job);
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_owned()
+    }
+}
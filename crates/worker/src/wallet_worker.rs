@@ -1,19 +1,352 @@
 //! Wallet worker for syncing wallet data
 
-use anyhow::Result;
-use hammer_service::HammerService;
-use tracing::{info, instrument};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 
-/// Syncs wallet data from CAM and stores it in the database
-#[instrument(skip(_svc))]
-pub async fn sync_wallets(_svc: &HammerService) -> Result<()> {
+use anyhow::{Result, anyhow};
+use hammer_service::{
+    HammerService,
+    types::{AssetScope, DataProvider, NewWallet, NewWalletMetadata},
+};
+use time::OffsetDateTime;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, instrument, warn};
+
+use crate::account_source::{AccountSource, ExternalAccount};
+
+/// Parent of a wallet to create, which may itself be created in the same sync
+#[derive(Debug, Clone)]
+pub enum ParentRef {
+    Wallet(i32),
+    External(String),
+}
+
+/// A change the sync makes, or would make in a dry run
+#[derive(Debug, Clone)]
+pub enum WalletChange {
+    Create {
+        provider: DataProvider,
+        external_id: String,
+        scope: AssetScope,
+        parent: Option<ParentRef>,
+        alias: String,
+        address: Option<String>,
+    },
+    UpdateAlias {
+        wallet_id: i32,
+        from: Option<String>,
+        to: String,
+    },
+    Reparent {
+        wallet_id: i32,
+        from: Option<i32>,
+        to: Option<ParentRef>,
+    },
+    /// The account is no longer listed upstream; the wallet is kept and flagged
+    MarkMissing { wallet_id: i32 },
+    /// The account is listed upstream again
+    Restore { wallet_id: i32 },
+}
+
+/// Changes of a wallet sync and the providers that could not be listed
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    pub dry_run: bool,
+    /// Number of accounts listed upstream
+    pub accounts: usize,
+    pub changes: Vec<WalletChange>,
+    pub failed_providers: Vec<(DataProvider, String)>,
+}
+
+/// Syncs wallets with the accounts listed by each provider
+///
+/// Accounts are matched to wallets by provider and external ID. Missing wallets are created
+/// under their parent, aliases and known parents are updated, and wallets no longer listed
+/// are flagged rather than deleted. With `dry_run` the changes are only reported. Once `token` is
+/// cancelled the remaining providers are skipped.
+#[instrument(skip(svc, sources, token))]
+pub async fn sync_wallets(
+    svc: &HammerService,
+    sources: &[Arc<dyn AccountSource>],
+    dry_run: bool,
+    token: &CancellationToken,
+) -> Result<SyncReport> {
     info!("Starting wallet sync");
 
-    // TODO: Implement wallet sync logic
-    // 1. Get list of wallets from CAM
-    // 2. Sync wallet metadata and structure
-    // 3. Update database with latest wallet information
+    let mut report = SyncReport {
+        dry_run,
+        ..Default::default()
+    };
+    for source in sources {
+        if token.is_cancelled() {
+            warn!("Wallet sync cancelled by shutdown");
+            break;
+        }
+        let provider = source.provider();
+        let accounts = match source.fetch_accounts().await {
+            Ok(accounts) => accounts,
+            Err(e) => {
+                // Without a listing nothing can be flagged as missing
+                error!("Failed to list {:?} accounts: {:#}", provider, e);
+                report.failed_providers.push((provider, format!("{e:#}")));
+                continue;
+            }
+        };
+
+        report.accounts += accounts.len();
+        let changes = plan_changes(svc, provider, accounts).await?;
+        for change in &changes {
+            info!("{}{:?}", if dry_run { "[dry run] " } else { "" }, change);
+        }
+        if !dry_run {
+            apply_changes(svc, &changes).await?;
+        }
+        report.changes.extend(changes);
+    }
+
+    info!(
+        "Wallet sync completed: {} changes, {} providers failed",
+        report.changes.len(),
+        report.failed_providers.len()
+    );
+    Ok(report)
+}
+
+/// Compares a provider's accounts with its wallets, parents before children
+async fn plan_changes(
+    svc: &HammerService,
+    provider: DataProvider,
+    accounts: Vec<ExternalAccount>,
+) -> Result<Vec<WalletChange>> {
+    let wallets = svc.query.get_wallets_by_provider(provider).await?;
+    let aliases = svc
+        .query
+        .get_wallets_with_metadata()
+        .await?
+        .into_iter()
+        .filter_map(|(wallet, metadata)| Some((wallet.id, metadata.into_iter().next()?.alias)))
+        .collect::<HashMap<_, _>>();
+    let by_external_id = wallets
+        .iter()
+        .filter_map(|wallet| Some((wallet.external_id.clone()?, wallet)))
+        .collect::<HashMap<_, _>>();
+    let listed = accounts
+        .iter()
+        .map(|account| account.external_id.clone())
+        .collect::<HashSet<_>>();
+
+    let mut changes = Vec::new();
+    for account in order_parents_first(accounts) {
+        let parent = match &account.parent_external_id {
+            None => None,
+            Some(parent_id) => match by_external_id.get(parent_id) {
+                Some(parent) => Some(ParentRef::Wallet(parent.id)),
+                None if listed.contains(parent_id) => Some(ParentRef::External(parent_id.clone())),
+                None => {
+                    warn!(
+                        "{:?} account {} has unknown parent {}",
+                        provider, account.external_id, parent_id
+                    );
+                    None
+                }
+            },
+        };
+
+        let Some(wallet) = by_external_id.get(&account.external_id) else {
+            changes.push(WalletChange::Create {
+                provider,
+                external_id: account.external_id,
+                scope: account.scope,
+                parent,
+                alias: account.alias,
+                address: account.address,
+            });
+            continue;
+        };
 
-    info!("Wallet sync completed");
+        if AssetScope::from(wallet.scope) != account.scope {
+            warn!(
+                "Wallet {} is {:?} but {:?} lists it as {:?}, keeping the wallet scope",
+                wallet.id, wallet.scope, provider, account.scope
+            );
+        }
+        if wallet.missing_since.is_some() {
+            changes.push(WalletChange::Restore {
+                wallet_id: wallet.id,
+            });
+        }
+        let alias = aliases.get(&wallet.id);
+        if alias != Some(&account.alias) {
+            changes.push(WalletChange::UpdateAlias {
+                wallet_id: wallet.id,
+                from: alias.cloned(),
+                to: account.alias,
+            });
+        }
+        if needs_reparent(wallet.parent_id, parent.as_ref()) {
+            changes.push(WalletChange::Reparent {
+                wallet_id: wallet.id,
+                from: wallet.parent_id,
+                to: parent,
+            });
+        }
+    }
+
+    for wallet in wallets {
+        let Some(external_id) = &wallet.external_id else {
+            continue;
+        };
+        if !listed.contains(external_id) && wallet.missing_since.is_none() {
+            changes.push(WalletChange::MarkMissing {
+                wallet_id: wallet.id,
+            });
+        }
+    }
+
+    Ok(changes)
+}
+
+/// Whether a wallet moves to the parent resolved from its account
+///
+/// Wallets only move under a parent named upstream and known to the sync. An account without
+/// a parent, or with an unknown one, keeps the wallet where it is, e.g. under a manually
+/// assigned parent.
+fn needs_reparent(parent_id: Option<i32>, parent: Option<&ParentRef>) -> bool {
+    match parent {
+        Some(ParentRef::Wallet(id)) => parent_id != Some(*id),
+        Some(ParentRef::External(_)) => true,
+        None => false,
+    }
+}
+
+/// Orders accounts so that every listed parent comes before its children
+fn order_parents_first(mut accounts: Vec<ExternalAccount>) -> Vec<ExternalAccount> {
+    let listed = accounts
+        .iter()
+        .map(|account| account.external_id.clone())
+        .collect::<HashSet<_>>();
+    let mut placed = HashSet::new();
+    let mut ordered = Vec::with_capacity(accounts.len());
+    while !accounts.is_empty() {
+        let (ready, waiting): (Vec<_>, Vec<_>) = accounts.into_iter().partition(|account| {
+            account
+                .parent_external_id
+                .as_ref()
+                .is_none_or(|parent| !listed.contains(parent) || placed.contains(parent))
+        });
+        if ready.is_empty() {
+            // Cyclic parents; place the rest as they are
+            ordered.extend(waiting);
+            break;
+        }
+        placed.extend(ready.iter().map(|account| account.external_id.clone()));
+        ordered.extend(ready);
+        accounts = waiting;
+    }
+    ordered
+}
+
+/// Applies planned changes in order, resolving parents created earlier in the same sync
+async fn apply_changes(svc: &HammerService, changes: &[WalletChange]) -> Result<()> {
+    let mut created = HashMap::<String, i32>::new();
+    let resolve = |parent: &Option<ParentRef>, created: &HashMap<String, i32>| match parent {
+        None => Ok(None),
+        Some(ParentRef::Wallet(id)) => Ok(Some(*id)),
+        Some(ParentRef::External(external_id)) => created
+            .get(external_id)
+            .copied()
+            .map(Some)
+            .ok_or_else(|| anyhow!("Parent account {external_id} was not created")),
+    };
+
+    for change in changes {
+        match change {
+            WalletChange::Create {
+                provider,
+                external_id,
+                scope,
+                parent,
+                alias,
+                address,
+            } => {
+                let wallet = svc
+                    .query
+                    .create_wallet_with_metadata(
+                        NewWallet {
+                            scope: *scope,
+                            parent_id: resolve(parent, &created)?,
+                            provider: Some(*provider),
+                            external_id: Some(external_id.clone()),
+                        },
+                        alias.clone(),
+                        address.clone(),
+                    )
+                    .await?;
+                created.insert(external_id.clone(), wallet.id);
+            }
+            WalletChange::UpdateAlias { wallet_id, to, .. } => {
+                match svc.query.get_wallet_metadata(*wallet_id).await? {
+                    Some(metadata) => {
+                        svc.query
+                            .update_wallet_metadata(
+                                metadata.id,
+                                NewWalletMetadata {
+                                    wallet_id: *wallet_id,
+                                    alias: to.clone(),
+                                    address: metadata.address,
+                                },
+                            )
+                            .await?;
+                    }
+                    None => {
+                        svc.query
+                            .create_wallet_metadata(NewWalletMetadata {
+                                wallet_id: *wallet_id,
+                                alias: to.clone(),
+                                address: None,
+                            })
+                            .await?;
+                    }
+                }
+            }
+            WalletChange::Reparent { wallet_id, to, .. } => {
+                svc.query
+                    .set_wallet_parent(*wallet_id, resolve(to, &created)?)
+                    .await?;
+            }
+            WalletChange::MarkMissing { wallet_id } => {
+                svc.query
+                    .set_wallet_missing_since(*wallet_id, Some(OffsetDateTime::now_utc()))
+                    .await?;
+            }
+            WalletChange::Restore { wallet_id } => {
+                svc.query.set_wallet_missing_since(*wallet_id, None).await?;
+            }
+        }
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reparents_only_under_known_parents() {
+        assert!(needs_reparent(None, Some(&ParentRef::Wallet(1))));
+        assert!(needs_reparent(Some(2), Some(&ParentRef::Wallet(1))));
+        assert!(!needs_reparent(Some(1), Some(&ParentRef::Wallet(1))));
+        assert!(needs_reparent(
+            Some(1),
+            Some(&ParentRef::External("master".to_owned()))
+        ));
+    }
+
+    #[test]
+    fn keeps_parent_when_upstream_names_none() {
+        assert!(!needs_reparent(Some(1), None));
+        assert!(!needs_reparent(None, None));
+    }
+}